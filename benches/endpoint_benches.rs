@@ -2,6 +2,7 @@ use criterion::{criterion_group, criterion_main, Criterion, BenchmarkId};
 use actix_web::{test, web, App};
 use serde_json::json;
 use riot_api::{routes, crypto, models}; // Import necessary modules from your crate
+use riot_api::key_manager::KeyManager;
 use tokio::runtime::Runtime;
 
 fn benchmark_endpoints(c: &mut Criterion) {
@@ -19,10 +20,12 @@ fn benchmark_endpoints(c: &mut Criterion) {
 
     let encrypted_data = crypto::encrypt_data(&sample_data).expect("Encryption failed for setup");
     let secret_key = "test-secret-key".as_bytes().to_vec();
+    let keyring = crypto::Keyring::single(secret_key.clone());
     let signature = crypto::sign_data(&sample_data, &secret_key).expect("Signing failed for setup");
     let verify_payload = models::VerifyRequest {
-        data: sample_data.clone(),
-        signature: signature.clone(),
+        data: Some(sample_data.clone()),
+        signature: Some(signature.clone()),
+        ..Default::default()
     };
 
     // --- Benchmark Group ---
@@ -56,7 +59,7 @@ fn benchmark_endpoints(c: &mut Criterion) {
     group.bench_function(BenchmarkId::new("POST", "/sign"), |b| {
         b.to_async(&runtime).iter(|| async {
             let app = test::init_service(App::new()
-                .app_data(web::Data::new(secret_key.clone()))
+                .app_data(web::Data::new(KeyManager::from(keyring.clone())))
                 .route("/sign", web::post().to(routes::sign))
             ).await;
             let req = test::TestRequest::post().uri("/sign").set_json(&sample_data).to_request();
@@ -69,7 +72,7 @@ fn benchmark_endpoints(c: &mut Criterion) {
     group.bench_function(BenchmarkId::new("POST", "/verify"), |b| {
         b.to_async(&runtime).iter(|| async {
             let app = test::init_service(App::new()
-                .app_data(web::Data::new(secret_key.clone()))
+                .app_data(web::Data::new(KeyManager::from(keyring.clone())))
                 .route("/verify", web::post().to(routes::verify))
             ).await;
             let req = test::TestRequest::post().uri("/verify").set_json(&verify_payload).to_request();
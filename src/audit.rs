@@ -0,0 +1,335 @@
+//! Append-only audit log of the signatures issued by `/sign`.
+//! Every entry carries the hash of the previous one, so that editing,
+//! removing or reordering entries breaks the chain. The log is kept in
+//! memory or in a file of JSON lines, next to a small head file recording
+//! the entry count and last hash.
+//!
+//! A head only exposes truncation if it is kept where whoever can truncate
+//! the log cannot also rewrite it. The neighbouring head file catches lost
+//! writes and careless edits, not an attacker with access to the directory:
+//! record heads elsewhere too, e.g. from `/audit` into another system.
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::crypto::canonicalize_json;
+
+/// `prev_hash` of the first entry.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// A signature about to be logged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// Id of the key that produced the signature; for a tenant, the id of
+    /// the master key its key was derived from.
+    pub kid: String,
+    /// The tenant the request was scoped to, if any.
+    pub tenant: Option<String>,
+    /// Output format of the signature, e.g. `signature`, `jws` or `raw`.
+    pub format: String,
+    /// Hex SHA-256 of the signed payload (its canonical JSON, or the raw bytes).
+    pub payload_digest: String,
+    /// The signature itself; for JWS and COSE only the signature part.
+    pub signature: String,
+}
+
+/// One entry of the audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Position in the log, starting at 0.
+    pub seq: u64,
+    /// Unix time the signature was issued, in seconds.
+    pub timestamp: u64,
+    pub kid: String,
+    /// Absent for requests without a tenant, so that older entries keep their hash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub format: String,
+    pub payload_digest: String,
+    pub signature: String,
+    /// `hash` of the previous entry, or `GENESIS_HASH` for the first one.
+    pub prev_hash: String,
+    /// Hex SHA-256 of the canonical JSON of every other field of this entry.
+    pub hash: String,
+}
+
+impl AuditEntry {
+    /// Computes the hash this entry should carry.
+    pub fn compute_hash(&self) -> String {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        if let Some(object) = value.as_object_mut() {
+            object.remove("hash");
+        }
+        hex::encode(Sha256::digest(canonicalize_json(&value).to_string().as_bytes()))
+    }
+}
+
+/// Number of entries and hash of the last one. Recording the head elsewhere
+/// allows later truncation, or rewriting of the whole chain, to be detected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditHead {
+    pub count: u64,
+    pub hash: String,
+}
+
+impl Default for AuditHead {
+    fn default() -> Self {
+        AuditHead { count: 0, hash: GENESIS_HASH.to_string() }
+    }
+}
+
+/// Why an audit log was refused or could not be written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditError {
+    /// The entry's contents do not match its hash.
+    Tampered(u64),
+    /// The entry does not follow the previous one: wrong `seq` or `prev_hash`.
+    Broken(u64),
+    /// The chain ends before the expected head.
+    Truncated,
+    /// The log could not be read or written.
+    Storage(String),
+}
+
+impl AuditError {
+    /// Stable machine-readable code returned by the API.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuditError::Tampered(_) => "audit_entry_tampered",
+            AuditError::Broken(_) => "audit_chain_broken",
+            AuditError::Truncated => "audit_chain_truncated",
+            AuditError::Storage(_) => "audit_storage_failed",
+        }
+    }
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuditError::Tampered(seq) => write!(f, "Audit entry {} was modified", seq),
+            AuditError::Broken(seq) => write!(f, "Audit chain is broken at entry {}", seq),
+            AuditError::Truncated => write!(f, "Audit chain is truncated"),
+            AuditError::Storage(e) => write!(f, "Audit log storage failed: {}", e),
+        }
+    }
+}
+
+/// Checks a complete chain, from its first entry, and returns its head.
+///
+/// Every entry must hash to its `hash`, follow its predecessor in `seq` and
+/// `prev_hash`, and the first one must start at 0 from `GENESIS_HASH`. When
+/// `expected` is given, typically a head recorded earlier, the chain must
+/// pass through it: it may have grown since, but not lost or changed entries.
+pub fn verify_chain(entries: &[AuditEntry], expected: Option<&AuditHead>) -> Result<AuditHead, AuditError> {
+    let mut head = AuditHead::default();
+    let mut passed_expected = expected.is_none_or(|expected| *expected == head);
+    for entry in entries {
+        if entry.seq != head.count || entry.prev_hash != head.hash {
+            return Err(AuditError::Broken(entry.seq));
+        }
+        if entry.compute_hash() != entry.hash {
+            return Err(AuditError::Tampered(entry.seq));
+        }
+        head = AuditHead { count: head.count + 1, hash: entry.hash.clone() };
+        passed_expected |= expected == Some(&head);
+    }
+    match expected {
+        Some(expected) if expected.count > head.count => Err(AuditError::Truncated),
+        Some(expected) if !passed_expected => Err(AuditError::Broken(expected.count.saturating_sub(1))),
+        _ => Ok(head),
+    }
+}
+
+struct AuditState {
+    head: AuditHead,
+    /// All entries, when the log is not backed by a file.
+    entries: Vec<AuditEntry>,
+    /// Byte offset of every entry in the file, followed by the length of the
+    /// file, so that a page can be read without parsing what precedes it.
+    offsets: Vec<u64>,
+}
+
+/// The audit log shared by all workers.
+///
+/// Like the `NonceStore`, create it once and register the same `web::Data`
+/// in every `App`.
+pub struct AuditLog {
+    state: Mutex<AuditState>,
+    path: Option<PathBuf>,
+}
+
+fn storage_error(path: &Path, action: &str, e: impl fmt::Display) -> AuditError {
+    AuditError::Storage(format!("Failed to {} {}: {}", action, path.display(), e))
+}
+
+fn head_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".head");
+    PathBuf::from(name)
+}
+
+/// Reads every entry of the file, with the offsets `AuditState` keeps.
+fn read_entries(path: &Path) -> Result<(Vec<AuditEntry>, Vec<u64>), AuditError> {
+    if !path.exists() {
+        return Ok((Vec::new(), vec![0]));
+    }
+    let file = File::open(path).map_err(|e| storage_error(path, "open", e))?;
+    let mut entries = Vec::new();
+    let mut offsets = vec![0];
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| storage_error(path, "read", e))?;
+        let entry = serde_json::from_str(&line).map_err(|_| AuditError::Tampered(index as u64))?;
+        entries.push(entry);
+        offsets.push(offsets[index] + line.len() as u64 + 1);
+    }
+    Ok((entries, offsets))
+}
+
+/// Reads the entries between two byte offsets of the file, starting at `seq`.
+fn read_range(path: &Path, seq: u64, start: u64, end: u64) -> Result<Vec<AuditEntry>, AuditError> {
+    let mut file = File::open(path).map_err(|e| storage_error(path, "open", e))?;
+    file.seek(SeekFrom::Start(start)).map_err(|e| storage_error(path, "read", e))?;
+    let mut entries = Vec::new();
+    for (index, line) in BufReader::new(file.take(end - start)).lines().enumerate() {
+        let line = line.map_err(|e| storage_error(path, "read", e))?;
+        let entry = serde_json::from_str(&line).map_err(|_| AuditError::Tampered(seq + index as u64))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+impl AuditLog {
+    /// Log kept in memory only, lost on restart.
+    pub fn new() -> Self {
+        AuditLog {
+            state: Mutex::new(AuditState { head: AuditHead::default(), entries: Vec::new(), offsets: vec![0] }),
+            path: None,
+        }
+    }
+
+    /// Log backed by a file of JSON lines and a `<path>.head` file.
+    ///
+    /// The existing chain is verified and must reach the recorded head; a
+    /// log without its head file is refused.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, AuditError> {
+        let path = path.as_ref().to_path_buf();
+        let (entries, offsets) = read_entries(&path)?;
+        let recorded = match fs::read_to_string(head_path(&path)) {
+            Ok(head) => serde_json::from_str::<AuditHead>(&head).map_err(|e| storage_error(&head_path(&path), "parse", e))?,
+            Err(_) if entries.is_empty() => AuditHead::default(),
+            Err(e) => return Err(storage_error(&head_path(&path), "read", e)),
+        };
+        let head = verify_chain(&entries, Some(&recorded))?;
+        info!("Audit log {} verified with {} entries", path.display(), head.count);
+        Ok(AuditLog { state: Mutex::new(AuditState { head, entries: Vec::new(), offsets }), path: Some(path) })
+    }
+
+    /// Builds the log from the `AUDIT_LOG_PATH` environment variable, or in
+    /// memory when it is not set.
+    pub fn from_env() -> Result<Self, AuditError> {
+        AuditLog::from_vars(|name| env::var(name).ok())
+    }
+
+    /// Builds the log like `from_env`, reading the variable through `var`.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, AuditError> {
+        match var("AUDIT_LOG_PATH") {
+            Some(path) => AuditLog::open(path),
+            None => Ok(AuditLog::new()),
+        }
+    }
+
+    /// Appends `record`, issued at `timestamp`, and returns the new entry.
+    ///
+    /// An error means the signature must not be handed out. When only the
+    /// head file could not be written, the entry stays in the chain: the head
+    /// file then lags behind, which `open` accepts, as the chain may grow
+    /// past a recorded head.
+    pub fn append(&self, record: AuditRecord, timestamp: u64) -> Result<AuditEntry, AuditError> {
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut entry = AuditEntry {
+            seq: state.head.count,
+            timestamp,
+            kid: record.kid,
+            tenant: record.tenant,
+            format: record.format,
+            payload_digest: record.payload_digest,
+            signature: record.signature,
+            prev_hash: state.head.hash.clone(),
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash();
+        let head = AuditHead { count: entry.seq + 1, hash: entry.hash.clone() };
+        let Some(path) = &self.path else {
+            state.entries.push(entry.clone());
+            state.head = head;
+            return Ok(entry);
+        };
+        let line = serde_json::to_string(&entry).map_err(|e| AuditError::Storage(e.to_string()))?;
+        let start = state.offsets.last().copied().unwrap_or(0);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| storage_error(path, "append to", e))?;
+        if let Err(e) = writeln!(file, "{}", line) {
+            // Drop a partly written line, so the next entry starts on its own
+            let _ = file.set_len(start);
+            return Err(storage_error(path, "append to", e));
+        }
+        // The entry is on disk and part of the chain from now on, whether or
+        // not the head file below can be written
+        state.offsets.push(start + line.len() as u64 + 1);
+        state.head = head.clone();
+        // Replace the head file atomically, so a crash leaves the old or the new head
+        let head_file = head_path(path);
+        let mut temporary = head_file.clone().into_os_string();
+        temporary.push(".tmp");
+        let head_json = serde_json::to_string(&head).map_err(|e| AuditError::Storage(e.to_string()))?;
+        fs::write(&temporary, head_json)
+            .and_then(|_| fs::rename(&temporary, &head_file))
+            .map_err(|e| storage_error(&head_file, "write", e))?;
+        Ok(entry)
+    }
+
+    /// The current head of the chain.
+    pub fn head(&self) -> AuditHead {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).head.clone()
+    }
+
+    /// Up to `limit` entries starting at `seq` `from`, plus the current head.
+    ///
+    /// A file-backed log is read after releasing the lock, from the offsets
+    /// of the requested entries, so appends are not held up by readers.
+    pub fn entries(&self, from: u64, limit: usize) -> Result<(Vec<AuditEntry>, AuditHead), AuditError> {
+        let state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let head = state.head.clone();
+        let Some(path) = &self.path else {
+            let entries = state.entries.iter().skip(from as usize).take(limit).cloned().collect();
+            return Ok((entries, head));
+        };
+        let first = (from as usize).min(state.offsets.len() - 1);
+        let last = first.saturating_add(limit).min(state.offsets.len() - 1);
+        let (start, end) = (state.offsets[first], state.offsets[last]);
+        drop(state);
+        Ok((read_range(path, first as u64, start, end)?, head))
+    }
+
+    /// Re-reads and verifies the whole chain against the current head.
+    pub fn verify(&self) -> Result<AuditHead, AuditError> {
+        let (entries, head) = self.entries(0, usize::MAX)?;
+        verify_chain(&entries, Some(&head))
+    }
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        AuditLog::new()
+    }
+}
@@ -0,0 +1,399 @@
+//! Runtime settings for the signing endpoints, the HTTP signature middleware,
+//! webhook verification and the admin API.
+//! Values are read from environment variables at startup and bundled in
+//! `Settings`, which `LiveSettings` shares between workers and swaps on
+//! reload; handlers fall back to `Settings::default()` when none is registered.
+
+use crate::crypto::{compute, verify_bytes, DuplicateKeyPolicy, SecretBytes, WebhookProfile, WebhookScheme};
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+/// Settings shared by `/sign` and `/verify`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigningConfig {
+    /// How duplicate object keys in request bodies are handled.
+    pub duplicate_keys: DuplicateKeyPolicy,
+    /// Clock drift tolerated when checking `exp` and `nbf`, in seconds.
+    pub clock_skew_secs: u64,
+    /// Property that holds the signature in embedded-signature documents.
+    pub signature_property: String,
+    /// Largest body accepted by `/sign/raw` and `/verify/raw`, in bytes.
+    pub max_raw_body_bytes: usize,
+    /// Largest body accepted by `/sign` and `/verify`, in bytes.
+    pub max_json_body_bytes: usize,
+    /// Key ids that sign with `/sign?format=set`, and the only keys whose
+    /// set members count in `/verify`.
+    pub signers: Vec<String>,
+    /// How many `signers` must have signed a set for `/verify` to accept it;
+    /// all of them when `None`.
+    pub signature_set_threshold: Option<usize>,
+    /// How long clients may cache `/.well-known/jwks.json`, in seconds.
+    pub jwks_max_age_secs: u64,
+}
+
+impl Default for SigningConfig {
+    fn default() -> Self {
+        SigningConfig {
+            duplicate_keys: DuplicateKeyPolicy::default(),
+            clock_skew_secs: 60,
+            signature_property: "_signature".to_string(),
+            max_raw_body_bytes: 10 * 1024 * 1024,
+            max_json_body_bytes: 32 * 1024,
+            signers: Vec::new(),
+            signature_set_threshold: None,
+            jwks_max_age_secs: 300,
+        }
+    }
+}
+
+impl SigningConfig {
+    /// Builds the configuration from environment variables.
+    ///
+    /// - `DUPLICATE_KEY_POLICY`: `reject` (default) or `last-wins`.
+    /// - `SIGNATURE_CLOCK_SKEW_SECS`: tolerance for `exp`/`nbf` (default 60).
+    /// - `EMBEDDED_SIGNATURE_PROPERTY`: signature property of embedded
+    ///   documents (default `_signature`).
+    /// - `RAW_BODY_LIMIT_BYTES`: largest body of the raw-bytes endpoints
+    ///   (default 10 MiB).
+    /// - `JSON_BODY_LIMIT_BYTES`: largest body of `/sign` and `/verify`
+    ///   (default 32 KiB).
+    /// - `SIGNATURE_SET_SIGNERS`: comma-separated key ids that sign
+    ///   signature sets (none by default).
+    /// - `SIGNATURE_SET_THRESHOLD`: how many of them must sign a set
+    ///   (default all).
+    /// - `JWKS_MAX_AGE_SECS`: `Cache-Control` max-age of the JWKS (default 300).
+    pub fn from_env() -> Result<Self, String> {
+        SigningConfig::from_vars(|name| env::var(name).ok())
+    }
+
+    /// Builds the configuration like `from_env`, reading variables through `var`.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let mut config = SigningConfig::default();
+        if let Some(policy) = var("DUPLICATE_KEY_POLICY") {
+            config.duplicate_keys = policy.parse()?;
+        }
+        if let Some(skew) = var("SIGNATURE_CLOCK_SKEW_SECS") {
+            config.clock_skew_secs = skew
+                .parse()
+                .map_err(|_| "SIGNATURE_CLOCK_SKEW_SECS must be a number of seconds".to_string())?;
+        }
+        if let Some(property) = var("EMBEDDED_SIGNATURE_PROPERTY") {
+            if property.is_empty() {
+                return Err("EMBEDDED_SIGNATURE_PROPERTY must not be empty".to_string());
+            }
+            config.signature_property = property;
+        }
+        if let Some(limit) = var("RAW_BODY_LIMIT_BYTES") {
+            config.max_raw_body_bytes = limit
+                .parse()
+                .map_err(|_| "RAW_BODY_LIMIT_BYTES must be a number of bytes".to_string())?;
+        }
+        if let Some(limit) = var("JSON_BODY_LIMIT_BYTES") {
+            config.max_json_body_bytes = limit
+                .parse()
+                .map_err(|_| "JSON_BODY_LIMIT_BYTES must be a number of bytes".to_string())?;
+        }
+        if let Some(signers) = var("SIGNATURE_SET_SIGNERS") {
+            config.signers = signers
+                .split(',')
+                .map(str::trim)
+                .filter(|kid| !kid.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(threshold) = var("SIGNATURE_SET_THRESHOLD") {
+            let threshold: usize = threshold
+                .parse()
+                .map_err(|_| "SIGNATURE_SET_THRESHOLD must be a number of signers".to_string())?;
+            if threshold == 0 || threshold > config.signers.len() {
+                return Err(format!(
+                    "SIGNATURE_SET_THRESHOLD must be between 1 and the {} SIGNATURE_SET_SIGNERS",
+                    config.signers.len()
+                ));
+            }
+            config.signature_set_threshold = Some(threshold);
+        }
+        if let Some(max_age) = var("JWKS_MAX_AGE_SECS") {
+            config.jwks_max_age_secs = max_age
+                .parse()
+                .map_err(|_| "JWKS_MAX_AGE_SECS must be a number of seconds".to_string())?;
+        }
+        Ok(config)
+    }
+}
+
+/// When the HTTP message signature middleware demands a signature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HttpSignatureMode {
+    /// Signature headers are ignored.
+    Off,
+    /// Signed requests are verified; unsigned requests pass through.
+    #[default]
+    Optional,
+    /// Every request outside the exempt paths must be signed.
+    Required,
+}
+
+impl fmt::Display for HttpSignatureMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            HttpSignatureMode::Off => "off",
+            HttpSignatureMode::Optional => "optional",
+            HttpSignatureMode::Required => "required",
+        })
+    }
+}
+
+impl FromStr for HttpSignatureMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(HttpSignatureMode::Off),
+            "optional" => Ok(HttpSignatureMode::Optional),
+            "required" => Ok(HttpSignatureMode::Required),
+            other => Err(format!("Unknown HTTP signature mode: {}", other)),
+        }
+    }
+}
+
+/// Settings of the HTTP message signature (RFC 9421) middleware.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpSignatureConfig {
+    /// Whether requests must be signed.
+    pub mode: HttpSignatureMode,
+    /// Oldest accepted `created` time, in seconds before now.
+    pub max_age_secs: u64,
+    /// Paths that never require a signature, even in `Required` mode.
+    pub exempt_paths: Vec<String>,
+}
+
+impl Default for HttpSignatureConfig {
+    fn default() -> Self {
+        HttpSignatureConfig {
+            mode: HttpSignatureMode::default(),
+            max_age_secs: 300,
+            // Webhook senders authenticate with their own signature scheme
+            exempt_paths: vec![
+                "/health".to_string(),
+                "/.well-known/jwks.json".to_string(),
+                "/webhooks/verify".to_string(),
+            ],
+        }
+    }
+}
+
+impl HttpSignatureConfig {
+    /// Builds the configuration from environment variables.
+    ///
+    /// - `HTTP_SIGNATURES`: `off`, `optional` (default) or `required`.
+    /// - `HTTP_SIGNATURE_MAX_AGE_SECS`: oldest accepted `created` (default 300).
+    /// - `HTTP_SIGNATURE_EXEMPT_PATHS`: comma-separated paths that never
+    ///   require a signature (default
+    ///   `/health,/.well-known/jwks.json,/webhooks/verify`).
+    pub fn from_env() -> Result<Self, String> {
+        HttpSignatureConfig::from_vars(|name| env::var(name).ok())
+    }
+
+    /// Builds the configuration like `from_env`, reading variables through `var`.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let mut config = HttpSignatureConfig::default();
+        if let Some(mode) = var("HTTP_SIGNATURES") {
+            config.mode = mode.parse()?;
+        }
+        if let Some(max_age) = var("HTTP_SIGNATURE_MAX_AGE_SECS") {
+            config.max_age_secs = max_age
+                .parse()
+                .map_err(|_| "HTTP_SIGNATURE_MAX_AGE_SECS must be a number of seconds".to_string())?;
+        }
+        if let Some(paths) = var("HTTP_SIGNATURE_EXEMPT_PATHS") {
+            config.exempt_paths = paths
+                .split(',')
+                .map(str::trim)
+                .filter(|path| !path.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        Ok(config)
+    }
+}
+
+/// Provider profiles accepted by `/webhooks/verify`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WebhookConfig {
+    pub profiles: Vec<WebhookProfile>,
+}
+
+impl WebhookConfig {
+    /// Builds the profiles from environment variables.
+    ///
+    /// `WEBHOOK_PROFILES` lists profile names, e.g. `stripe,github`. For each
+    /// name `<NAME>` (upper-cased):
+    /// - `WEBHOOK_<NAME>_SECRET`: the signing secret (required).
+    /// - `WEBHOOK_<NAME>_SCHEME`: `timestamped` (alias `stripe`), `slack`,
+    ///   `github` or `standard-webhooks` (alias `svix`). Defaults to the
+    ///   profile name when that is a scheme, `timestamped` otherwise.
+    /// - `WEBHOOK_<NAME>_HEADER`: header carrying the signature.
+    /// - `WEBHOOK_<NAME>_TOLERANCE_SECS`: timestamp tolerance (default 300).
+    pub fn from_env() -> Result<Self, String> {
+        WebhookConfig::from_vars(|name| env::var(name).ok())
+    }
+
+    /// Builds the profiles like `from_env`, reading variables through `var`.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let Some(names) = var("WEBHOOK_PROFILES") else {
+            return Ok(WebhookConfig::default());
+        };
+        let mut profiles: Vec<WebhookProfile> = Vec::new();
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            if profiles.iter().any(|p| p.name == name) {
+                return Err(format!("Duplicate webhook profile: {}", name));
+            }
+            let setting = |suffix: &str| var(&format!("WEBHOOK_{}_{}", name.to_ascii_uppercase().replace('-', "_"), suffix));
+            let secret = setting("SECRET").ok_or_else(|| format!("Webhook profile {} has no secret", name))?;
+            let scheme = match setting("SCHEME") {
+                Some(scheme) => scheme.parse()?,
+                None => name.parse().unwrap_or(WebhookScheme::Timestamped),
+            };
+            let mut profile = WebhookProfile::new(name, scheme, secret.into_bytes());
+            if let Some(header) = setting("HEADER") {
+                profile.header = header;
+            }
+            if let Some(tolerance) = setting("TOLERANCE_SECS") {
+                profile.tolerance_secs = tolerance
+                    .parse()
+                    .map_err(|_| format!("Tolerance of webhook profile {} must be a number of seconds", name))?;
+            }
+            profiles.push(profile);
+        }
+        Ok(WebhookConfig { profiles })
+    }
+}
+
+/// Access to the `/admin` endpoints.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdminConfig {
+    /// Bearer token admin requests must present; the admin API is disabled
+    /// without one.
+    pub token: Option<SecretBytes>,
+}
+
+impl AdminConfig {
+    /// Reads the token from `ADMIN_TOKEN`, which must be at least 16
+    /// characters when set.
+    pub fn from_env() -> Result<Self, String> {
+        AdminConfig::from_vars(|name| env::var(name).ok())
+    }
+
+    /// Reads the token like `from_env`, through `var`.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        match var("ADMIN_TOKEN") {
+            Some(token) if token.len() < 16 => Err("ADMIN_TOKEN must be at least 16 characters".to_string()),
+            Some(token) => Ok(AdminConfig { token: Some(SecretBytes::from(token)) }),
+            None => Ok(AdminConfig::default()),
+        }
+    }
+
+    /// Checks a presented token in constant time, by comparing MACs.
+    pub fn authorizes(&self, presented: &str) -> bool {
+        let Some(token) = &self.token else {
+            return false;
+        };
+        compute(token, token)
+            .and_then(|expected| verify_bytes(presented.as_bytes(), &expected, token))
+            .unwrap_or(false)
+    }
+}
+
+/// Every reloadable setting.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    pub signing: SigningConfig,
+    pub http_signatures: HttpSignatureConfig,
+    pub webhooks: WebhookConfig,
+    pub admin: AdminConfig,
+}
+
+impl Settings {
+    /// Reads all settings from environment variables.
+    pub fn from_env() -> Result<Self, String> {
+        Settings::from_vars(|name| env::var(name).ok())
+    }
+
+    /// Reads all settings through `var`.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        Ok(Settings {
+            signing: SigningConfig::from_vars(&var)?,
+            http_signatures: HttpSignatureConfig::from_vars(&var)?,
+            webhooks: WebhookConfig::from_vars(&var)?,
+            admin: AdminConfig::from_vars(&var)?,
+        })
+    }
+
+    /// Describes what differs in `other`, without revealing secrets.
+    pub fn changes(&self, other: &Settings) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.signing != other.signing {
+            changes.push(format!("signing settings: {:?} -> {:?}", self.signing, other.signing));
+        }
+        if self.http_signatures != other.http_signatures {
+            changes.push(format!("HTTP signature settings: {:?} -> {:?}", self.http_signatures, other.http_signatures));
+        }
+        for profile in &other.webhooks.profiles {
+            match self.webhooks.profiles.iter().find(|p| p.name == profile.name) {
+                None => changes.push(format!("webhook profile {} added", profile.name)),
+                Some(old) if old != profile => changes.push(format!("webhook profile {} changed", profile.name)),
+                Some(_) => {}
+            }
+        }
+        for profile in &self.webhooks.profiles {
+            if !other.webhooks.profiles.iter().any(|p| p.name == profile.name) {
+                changes.push(format!("webhook profile {} removed", profile.name));
+            }
+        }
+        if self.admin != other.admin {
+            changes.push(match other.admin.token {
+                Some(_) if self.admin.token.is_some() => "admin token changed".to_string(),
+                Some(_) => "admin API enabled".to_string(),
+                None => "admin API disabled".to_string(),
+            });
+        }
+        changes
+    }
+}
+
+/// The current `Settings`, shared by all workers and replaced as a whole
+/// on reload.
+///
+/// Like the `KeyManager`, create it once and register the same `web::Data`
+/// in every `App`; a request reads `current()` once.
+#[derive(Default)]
+pub struct LiveSettings {
+    current: RwLock<Arc<Settings>>,
+}
+
+impl LiveSettings {
+    pub fn new(settings: Settings) -> Self {
+        LiveSettings { current: RwLock::new(Arc::new(settings)) }
+    }
+
+    pub fn current(&self) -> Arc<Settings> {
+        self.current.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    /// Makes `settings` current and returns what changed.
+    pub fn replace(&self, settings: Settings) -> Vec<String> {
+        let mut current = self.current.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let changes = current.changes(&settings);
+        *current = Arc::new(settings);
+        changes
+    }
+}
+
+impl From<Settings> for LiveSettings {
+    fn from(settings: Settings) -> Self {
+        LiveSettings::new(settings)
+    }
+}
@@ -0,0 +1,449 @@
+//! Layered configuration file.
+//! `CONFIG_FILE` names a TOML file with the sections `[server]`, `[limits]`,
+//! `[signing]`, `[logging]`, `[keys]`, `[storage]` and `[admin]`. Tables of
+//! the same shape under `[profiles.dev]`, `[profiles.staging]` and
+//! `[profiles.prod]` override them for the profile picked by
+//! `CONFIG_PROFILE` (default `dev`).
+//!
+//! Every value of the file stands for an environment variable (see
+//! `FIELDS`), which it only supplies when the variable is not set in the
+//! environment, the `.env` file or a secret file. Outside the `dev`
+//! profile, the file may not hold secrets.
+
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+use crate::config::HttpSignatureMode;
+use crate::crypto::{DuplicateKeyPolicy, KeyAlgorithm};
+use crate::secrets::SECRET_VARIABLES;
+
+/// The environment a configuration is for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    #[default]
+    Dev,
+    Staging,
+    Prod,
+}
+
+impl Profile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Profile::Dev => "dev",
+            Profile::Staging => "staging",
+            Profile::Prod => "prod",
+        }
+    }
+
+    /// The profile named by `CONFIG_PROFILE`, `dev` when unset.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        var("CONFIG_PROFILE").map_or(Ok(Profile::Dev), |name| name.parse())
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dev" => Ok(Profile::Dev),
+            "staging" => Ok(Profile::Staging),
+            "prod" => Ok(Profile::Prod),
+            other => Err(format!("Unknown profile {}; expected dev, staging or prod", other)),
+        }
+    }
+}
+
+/// A secret value, which is printed as `[redacted]`.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(Zeroizing<String>);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("[redacted]")
+    }
+}
+
+/// (De)serializes an optional value through its `FromStr` and `Display`
+/// implementations, so that the file takes the spellings of the variables.
+mod parsed {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    pub fn serialize<T: Display, S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.collect_str(value),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: FromStr<Err = String>,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?.parse().map(Some).map_err(de::Error::custom)
+    }
+}
+
+/// `env_logger` write styles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStyle {
+    Auto,
+    Always,
+    Never,
+}
+
+/// `[server]`: where and how the server listens.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    pub bind: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub workers: Option<usize>,
+    pub reload_poll_secs: Option<u64>,
+}
+
+/// `[limits]`: sizes and time windows.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsSection {
+    pub raw_body_bytes: Option<usize>,
+    pub json_body_bytes: Option<usize>,
+    pub clock_skew_secs: Option<u64>,
+    pub http_signature_max_age_secs: Option<u64>,
+    pub nonce_ttl_secs: Option<u64>,
+}
+
+/// `[signing]`: algorithms and signature formats.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SigningSection {
+    pub algorithms: Option<Vec<KeyAlgorithm>>,
+    #[serde(with = "parsed")]
+    pub duplicate_keys: Option<DuplicateKeyPolicy>,
+    pub signature_property: Option<String>,
+    pub signers: Option<Vec<String>>,
+    pub signature_set_threshold: Option<usize>,
+    pub jwks_max_age_secs: Option<u64>,
+    #[serde(with = "parsed")]
+    pub http_signatures: Option<HttpSignatureMode>,
+    pub http_signature_exempt_paths: Option<Vec<String>>,
+}
+
+/// `[logging]`: the `env_logger` filter and write style.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSection {
+    pub level: Option<String>,
+    pub style: Option<LogStyle>,
+}
+
+/// `[keys]`: where the keys come from.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeysSection {
+    pub keyring_file: Option<String>,
+    pub keyring_passphrase: Option<Secret>,
+    pub hmac_secret_key: Option<Secret>,
+    pub hmac_key_id: Option<String>,
+    pub hmac_keys: Option<Secret>,
+    pub hmac_active_key_id: Option<String>,
+    pub hmac_retired_key_ids: Option<Vec<String>>,
+    pub jws_keys: Option<Secret>,
+    pub tenant_master_key: Option<Secret>,
+    pub tenant_master_key_id: Option<String>,
+    pub tenant_master_provider_key: Option<String>,
+    pub provider: Option<String>,
+    pub provider_file: Option<String>,
+    pub lock_memory: Option<bool>,
+}
+
+/// `[storage]`: files the server keeps state in.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSection {
+    pub nonce_store_path: Option<String>,
+    pub audit_log_path: Option<String>,
+}
+
+/// `[admin]`: access to the admin API.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminSection {
+    pub token: Option<Secret>,
+}
+
+/// The whole configuration, as found in a file or in effect once the
+/// environment is applied. Unset values keep their defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    #[serde(skip_deserializing)]
+    pub profile: Profile,
+    pub server: ServerSection,
+    pub limits: LimitsSection,
+    pub signing: SigningSection,
+    pub logging: LoggingSection,
+    pub keys: KeysSection,
+    pub storage: StorageSection,
+    pub admin: AdminSection,
+}
+
+/// Type of a value in the file.
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Text,
+    Integer,
+    Flag,
+    /// An array in the file, a comma-separated list in the variable.
+    List,
+}
+
+/// The section, key, environment variable and type of every value.
+const FIELDS: &[(&str, &str, &str, Kind)] = &[
+    ("server", "bind", "BIND_ADDRESS", Kind::Text),
+    ("server", "port", "PORT", Kind::Integer),
+    ("server", "workers", "WORKERS", Kind::Integer),
+    ("server", "reload_poll_secs", "RELOAD_POLL_SECS", Kind::Integer),
+    ("limits", "raw_body_bytes", "RAW_BODY_LIMIT_BYTES", Kind::Integer),
+    ("limits", "json_body_bytes", "JSON_BODY_LIMIT_BYTES", Kind::Integer),
+    ("limits", "clock_skew_secs", "SIGNATURE_CLOCK_SKEW_SECS", Kind::Integer),
+    ("limits", "http_signature_max_age_secs", "HTTP_SIGNATURE_MAX_AGE_SECS", Kind::Integer),
+    ("limits", "nonce_ttl_secs", "NONCE_TTL_SECS", Kind::Integer),
+    ("signing", "algorithms", "SIGNING_ALGORITHMS", Kind::List),
+    ("signing", "duplicate_keys", "DUPLICATE_KEY_POLICY", Kind::Text),
+    ("signing", "signature_property", "EMBEDDED_SIGNATURE_PROPERTY", Kind::Text),
+    ("signing", "signers", "SIGNATURE_SET_SIGNERS", Kind::List),
+    ("signing", "signature_set_threshold", "SIGNATURE_SET_THRESHOLD", Kind::Integer),
+    ("signing", "jwks_max_age_secs", "JWKS_MAX_AGE_SECS", Kind::Integer),
+    ("signing", "http_signatures", "HTTP_SIGNATURES", Kind::Text),
+    ("signing", "http_signature_exempt_paths", "HTTP_SIGNATURE_EXEMPT_PATHS", Kind::List),
+    ("logging", "level", "RUST_LOG", Kind::Text),
+    ("logging", "style", "RUST_LOG_STYLE", Kind::Text),
+    ("keys", "keyring_file", "KEYRING_FILE", Kind::Text),
+    ("keys", "keyring_passphrase", "KEYRING_PASSPHRASE", Kind::Text),
+    ("keys", "hmac_secret_key", "HMAC_SECRET_KEY", Kind::Text),
+    ("keys", "hmac_key_id", "HMAC_KEY_ID", Kind::Text),
+    ("keys", "hmac_keys", "HMAC_KEYS", Kind::Text),
+    ("keys", "hmac_active_key_id", "HMAC_ACTIVE_KEY_ID", Kind::Text),
+    ("keys", "hmac_retired_key_ids", "HMAC_RETIRED_KEY_IDS", Kind::List),
+    ("keys", "jws_keys", "JWS_KEYS", Kind::Text),
+    ("keys", "tenant_master_key", "TENANT_MASTER_KEY", Kind::Text),
+    ("keys", "tenant_master_key_id", "TENANT_MASTER_KEY_ID", Kind::Text),
+    ("keys", "tenant_master_provider_key", "TENANT_MASTER_PROVIDER_KEY", Kind::Text),
+    ("keys", "provider", "KEY_PROVIDER", Kind::Text),
+    ("keys", "provider_file", "KEY_PROVIDER_FILE", Kind::Text),
+    ("keys", "lock_memory", "LOCK_KEY_MEMORY", Kind::Flag),
+    ("storage", "nonce_store_path", "NONCE_STORE_PATH", Kind::Text),
+    ("storage", "audit_log_path", "AUDIT_LOG_PATH", Kind::Text),
+    ("admin", "token", "ADMIN_TOKEN", Kind::Text),
+];
+
+/// Filter levels `env_logger` understands.
+const LOG_LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+
+impl Config {
+    /// The configuration in effect, read through `var` like the rest of the
+    /// settings. Values that cannot be parsed are reported by variable name.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let profile = Profile::from_vars(&var)?;
+        let mut root = toml::Table::new();
+        for (section, key, name, kind) in FIELDS {
+            let Some(value) = var(name) else {
+                continue;
+            };
+            let value = match kind {
+                Kind::Text => toml::Value::String(value),
+                Kind::Integer => toml::Value::Integer(value.trim().parse().map_err(|_| format!("{} must be a number", name))?),
+                Kind::Flag => toml::Value::Boolean(value.trim().parse().map_err(|_| format!("{} must be true or false", name))?),
+                Kind::List => toml::Value::Array(
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|item| !item.is_empty())
+                        .map(|item| toml::Value::String(item.to_string()))
+                        .collect(),
+                ),
+            };
+            // Deserialized alone first, so that an invalid value is reported by name
+            let mut single = toml::Table::new();
+            section_mut(&mut single, section).insert(key.to_string(), value.clone());
+            Config::deserialize(toml::Value::Table(single)).map_err(|e| format!("Invalid {}: {}", name, e.message()))?;
+            section_mut(&mut root, section).insert(key.to_string(), value);
+        }
+        let config: Config = toml::Value::Table(root).try_into().map_err(|e| format!("Invalid configuration: {}", e))?;
+        Ok(Config { profile, ..config })
+    }
+
+    /// Checks what deserialization cannot. Values are typed, so a malformed
+    /// one is already refused by `from_vars`; keys and settings are checked
+    /// further when they are loaded.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.server.workers == Some(0) {
+            return Err("WORKERS must be at least 1".to_string());
+        }
+        if let Some(level) = &self.logging.level {
+            // A bare word may be a module name, so only `module=level` is checked
+            for directive in level.split(',') {
+                if let Some((_, level)) = directive.split_once('=') {
+                    if !LOG_LEVELS.contains(&level.trim().to_ascii_lowercase().as_str()) {
+                        return Err(format!("Unknown log level {} in RUST_LOG", level.trim()));
+                    }
+                }
+            }
+        }
+        if self.signing.algorithms.as_ref().is_some_and(Vec::is_empty) {
+            return Err("SIGNING_ALGORITHMS must list at least one algorithm".to_string());
+        }
+        Ok(())
+    }
+
+    /// Address the server binds to, `0.0.0.0:8080` by default.
+    pub fn bind_address(&self) -> (String, u16) {
+        (
+            self.server.bind.map_or_else(|| "0.0.0.0".to_string(), |bind| bind.to_string()),
+            self.server.port.unwrap_or(8080),
+        )
+    }
+
+
+    /// The configuration as TOML, with secrets redacted.
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string_pretty(self).map_err(|e| format!("Failed to print configuration: {}", e))
+    }
+}
+
+/// The values of a configuration file for one profile, by variable name.
+pub struct ConfigFile {
+    path: PathBuf,
+    profile: Profile,
+    values: HashMap<&'static str, Zeroizing<String>>,
+}
+
+impl ConfigFile {
+    /// Reads the file named by `CONFIG_FILE`, if set, for the profile named
+    /// by `CONFIG_PROFILE`.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>, String> {
+        let Some(path) = var("CONFIG_FILE") else {
+            return Ok(None);
+        };
+        ConfigFile::load(path, Profile::from_vars(&var)?).map(Some)
+    }
+
+    /// Reads `path` and applies the overrides of `profile`.
+    pub fn load(path: impl AsRef<Path>, profile: Profile) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let values = ConfigFile::parse(&text, profile).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(ConfigFile { path: path.to_path_buf(), profile, values })
+    }
+
+    /// Parses a configuration file and returns the values `profile` ends
+    /// up with, by variable name.
+    fn parse(text: &str, profile: Profile) -> Result<HashMap<&'static str, Zeroizing<String>>, String> {
+        let mut root: toml::Table = toml::from_str(text).map_err(|e| format!("Invalid TOML: {}", e))?;
+        let profiles = match root.remove("profiles") {
+            Some(toml::Value::Table(profiles)) => profiles,
+            Some(_) => return Err("profiles must be a table".to_string()),
+            None => toml::Table::new(),
+        };
+        // Every profile is checked, not only the selected one, so that a
+        // mistake shows up before it reaches production
+        let mut selected = root.clone();
+        for (name, overrides) in profiles {
+            let name: Profile = name.parse()?;
+            let toml::Value::Table(overrides) = overrides else {
+                return Err(format!("profiles.{} must be a table", name));
+            };
+            let mut merged = root.clone();
+            merge(&mut merged, overrides);
+            Config::deserialize(toml::Value::Table(merged.clone())).map_err(|e| format!("Invalid profiles.{}: {}", name, e))?;
+            if name == profile {
+                selected = merged;
+            }
+        }
+        Config::deserialize(toml::Value::Table(selected.clone())).map_err(|e| format!("Invalid configuration: {}", e))?;
+
+        let mut values = HashMap::new();
+        for (section, key, name, _) in FIELDS {
+            let Some(value) = selected.get(*section).and_then(|section| section.get(*key)) else {
+                continue;
+            };
+            if profile != Profile::Dev && SECRET_VARIABLES.contains(name) {
+                return Err(format!(
+                    "{}.{} is a secret, which the {} profile does not take from the configuration file; set {}, {}_FILE or SECRETS_DIR instead",
+                    section, key, profile, name, name
+                ));
+            }
+            let value = match value {
+                toml::Value::String(text) => text.clone(),
+                toml::Value::Array(items) => items.iter().filter_map(toml::Value::as_str).collect::<Vec<_>>().join(","),
+                other => other.to_string(),
+            };
+            values.insert(*name, Zeroizing::new(value));
+        }
+        Ok(values)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn profile(&self) -> Profile {
+        self.profile
+    }
+
+    /// The value the file gives variable `name`.
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.values.get(name).map(|value| value.to_string())
+    }
+}
+
+/// The table of `section`, created if missing.
+fn section_mut<'a>(root: &'a mut toml::Table, section: &str) -> &'a mut toml::Table {
+    match root.entry(section).or_insert_with(|| toml::Value::Table(toml::Table::new())) {
+        toml::Value::Table(table) => table,
+        _ => unreachable!("sections are only created as tables"),
+    }
+}
+
+/// Overlays `overrides` on `base`, table by table.
+fn merge(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overrides)) => merge(base, overrides),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use serde_json::{Number, Value};
+use std::collections::HashSet;
+
+/// Deepest nesting accepted when decoding.
+const MAX_DEPTH: usize = 64;
+
+/// A CBOR data item (RFC 8949), as far as Riot needs them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CborValue {
+    /// Major type 0.
+    Unsigned(u64),
+    /// Major type 1: the value `-1 - n`.
+    Negative(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<CborValue>),
+    /// Entries in any order; encoding sorts them.
+    Map(Vec<(CborValue, CborValue)>),
+    Tag(u64, Box<CborValue>),
+    Bool(bool),
+    Null,
+    Float(f64),
+}
+
+impl CborValue {
+    /// An integer item, unsigned or negative.
+    pub fn integer(i: i64) -> Self {
+        if i >= 0 {
+            CborValue::Unsigned(i as u64)
+        } else {
+            CborValue::Negative(!i as u64)
+        }
+    }
+
+    /// The value of an integer item that fits an `i64`.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            CborValue::Unsigned(n) => i64::try_from(*n).ok(),
+            CborValue::Negative(n) => i64::try_from(*n).ok().map(|n| -1 - n),
+            _ => None,
+        }
+    }
+
+    /// The entry of a map item under `key`.
+    pub fn get(&self, key: &CborValue) -> Option<&CborValue> {
+        match self {
+            CborValue::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+fn write_head(out: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    match n {
+        0..=23 => out.push(major | n as u8),
+        24..=0xff => out.extend_from_slice(&[major | 24, n as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend_from_slice(&(n as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+/// Encodes `f` in the shortest of half, single or double precision that
+/// keeps its value exactly, as RFC 8949 section 4.2.1 requires.
+fn write_float(out: &mut Vec<u8>, f: f64) {
+    if let Some(half) = f64_to_f16(f) {
+        out.push(0xf9);
+        out.extend_from_slice(&half.to_be_bytes());
+    } else if (f as f32) as f64 == f || f.is_nan() {
+        out.push(0xfa);
+        out.extend_from_slice(&(f as f32).to_be_bytes());
+    } else {
+        out.push(0xfb);
+        out.extend_from_slice(&f.to_be_bytes());
+    }
+}
+
+/// The IEEE 754 half-precision bits of `f`, if it converts without loss.
+fn f64_to_f16(f: f64) -> Option<u16> {
+    if f.is_nan() {
+        return Some(0x7e00);
+    }
+    let sign = if f.is_sign_negative() { 0x8000u16 } else { 0 };
+    if f.is_infinite() {
+        return Some(sign | 0x7c00);
+    }
+    if f == 0.0 {
+        return Some(sign);
+    }
+    let magnitude = f.abs();
+    // Normal range: 2^-14 ..= 65504 with an 11-bit significand
+    let mut exponent = magnitude.log2().floor() as i32;
+    if magnitude < 2f64.powi(exponent) {
+        exponent -= 1;
+    }
+    if (-14..=15).contains(&exponent) {
+        let mantissa = magnitude / 2f64.powi(exponent) - 1.0;
+        let scaled = mantissa * 1024.0;
+        if scaled.fract() == 0.0 {
+            return Some(sign | (((exponent + 15) as u16) << 10) | scaled as u16);
+        }
+        return None;
+    }
+    // Subnormal range: multiples of 2^-24
+    let scaled = magnitude * 2f64.powi(24);
+    if exponent < -14 && scaled.fract() == 0.0 && scaled < 1024.0 {
+        return Some(sign | scaled as u16);
+    }
+    None
+}
+
+fn f16_to_f64(half: u16) -> f64 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f64;
+    sign * match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        0x1f if mantissa == 0.0 => f64::INFINITY,
+        0x1f => f64::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f64.powi(exponent - 15),
+    }
+}
+
+fn encode_into(value: &CborValue, out: &mut Vec<u8>) {
+    match value {
+        CborValue::Unsigned(n) => write_head(out, 0, *n),
+        CborValue::Negative(n) => write_head(out, 1, *n),
+        CborValue::Bytes(bytes) => {
+            write_head(out, 2, bytes.len() as u64);
+            out.extend_from_slice(bytes);
+        }
+        CborValue::Text(text) => {
+            write_head(out, 3, text.len() as u64);
+            out.extend_from_slice(text.as_bytes());
+        }
+        CborValue::Array(items) => {
+            write_head(out, 4, items.len() as u64);
+            for item in items {
+                encode_into(item, out);
+            }
+        }
+        CborValue::Map(entries) => {
+            // Keys are sorted by the bytewise order of their encodings
+            let mut encoded: Vec<(Vec<u8>, &CborValue)> = entries.iter().map(|(k, v)| (encode_cbor(k), v)).collect();
+            encoded.sort_by(|a, b| a.0.cmp(&b.0));
+            write_head(out, 5, encoded.len() as u64);
+            for (key, value) in encoded {
+                out.extend_from_slice(&key);
+                encode_into(value, out);
+            }
+        }
+        CborValue::Tag(tag, item) => {
+            write_head(out, 6, *tag);
+            encode_into(item, out);
+        }
+        CborValue::Bool(false) => out.push(0xf4),
+        CborValue::Bool(true) => out.push(0xf5),
+        CborValue::Null => out.push(0xf6),
+        CborValue::Float(f) => write_float(out, *f),
+    }
+}
+
+/// Encodes `value` with the core deterministic encoding of RFC 8949 section
+/// 4.2.1: shortest heads, definite lengths, shortest exact floats and map keys
+/// in bytewise lexicographic order of their encodings.
+pub fn encode_cbor(value: &CborValue) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_into(value, &mut out);
+    out
+}
+
+/// Converts JSON to the CBOR data model: integers stay integers, other
+/// numbers become floats and objects become maps with text keys.
+pub fn json_to_cbor(data: &Value) -> CborValue {
+    match data {
+        Value::Null => CborValue::Null,
+        Value::Bool(b) => CborValue::Bool(*b),
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(u), _) => CborValue::Unsigned(u),
+            (None, Some(i)) => CborValue::integer(i),
+            _ => CborValue::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => CborValue::Text(s.clone()),
+        Value::Array(items) => CborValue::Array(items.iter().map(json_to_cbor).collect()),
+        Value::Object(object) => CborValue::Map(
+            object.iter().map(|(k, v)| (CborValue::Text(k.clone()), json_to_cbor(v))).collect(),
+        ),
+    }
+}
+
+/// Deterministic CBOR of a JSON value: the binary counterpart of
+/// `canonicalize_json`. Equal JSON values always give equal bytes, whatever
+/// their property order.
+pub fn canonicalize_cbor(data: &Value) -> Vec<u8> {
+    encode_cbor(&json_to_cbor(data))
+}
+
+/// Converts CBOR back to JSON. Fails on items JSON cannot represent, such as
+/// byte strings, tags, non-text map keys or non-finite floats.
+pub fn cbor_to_json(value: &CborValue) -> Result<Value, String> {
+    Ok(match value {
+        CborValue::Null => Value::Null,
+        CborValue::Bool(b) => Value::Bool(*b),
+        CborValue::Unsigned(n) => Value::from(*n),
+        CborValue::Negative(n) => {
+            let i = i64::try_from(*n).map_err(|_| "CBOR integer out of range".to_string())?;
+            Value::from(-1 - i)
+        }
+        CborValue::Float(f) => Value::Number(Number::from_f64(*f).ok_or_else(|| "Non-finite CBOR float".to_string())?),
+        CborValue::Text(s) => Value::String(s.clone()),
+        CborValue::Array(items) => Value::Array(items.iter().map(cbor_to_json).collect::<Result<_, _>>()?),
+        CborValue::Map(entries) => {
+            let mut object = serde_json::Map::new();
+            for (key, value) in entries {
+                let CborValue::Text(key) = key else {
+                    return Err("CBOR map keys must be text".to_string());
+                };
+                object.insert(key.clone(), cbor_to_json(value)?);
+            }
+            Value::Object(object)
+        }
+        CborValue::Bytes(_) | CborValue::Tag(..) => return Err("CBOR item has no JSON equivalent".to_string()),
+    })
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Decoder<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        let end = self.pos.checked_add(n).filter(|end| *end <= self.input.len())
+            .ok_or_else(|| "Truncated CBOR".to_string())?;
+        let bytes = &self.input[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn argument(&mut self, info: u8) -> Result<u64, String> {
+        Ok(match info {
+            0..=23 => info as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().expect("two bytes")) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().expect("four bytes")) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().expect("eight bytes")),
+            _ => return Err("Indefinite-length or reserved CBOR items are not supported".to_string()),
+        })
+    }
+
+    fn length(&mut self, info: u8) -> Result<usize, String> {
+        let n = self.argument(info)?;
+        // Every element takes at least one byte, so longer lengths are bogus
+        usize::try_from(n)
+            .ok()
+            .filter(|n| *n <= self.input.len() - self.pos)
+            .ok_or_else(|| "Truncated CBOR".to_string())
+    }
+
+    fn item(&mut self, depth: usize) -> Result<CborValue, String> {
+        if depth > MAX_DEPTH {
+            return Err("CBOR nested too deeply".to_string());
+        }
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        Ok(match major {
+            0 => CborValue::Unsigned(self.argument(info)?),
+            1 => CborValue::Negative(self.argument(info)?),
+            2 => {
+                let len = self.length(info)?;
+                CborValue::Bytes(self.take(len)?.to_vec())
+            }
+            3 => {
+                let len = self.length(info)?;
+                let text = std::str::from_utf8(self.take(len)?).map_err(|_| "Invalid UTF-8 in CBOR text".to_string())?;
+                CborValue::Text(text.to_string())
+            }
+            4 => {
+                let len = self.length(info)?;
+                let mut items = Vec::with_capacity(len);
+                for _ in 0..len {
+                    items.push(self.item(depth + 1)?);
+                }
+                CborValue::Array(items)
+            }
+            5 => {
+                let len = self.length(info)?;
+                let mut entries: Vec<(CborValue, CborValue)> = Vec::with_capacity(len);
+                // Keys are compared by their deterministic encoding, so that
+                // checking a map of untrusted input stays linear in its size
+                let mut seen = HashSet::with_capacity(len);
+                for _ in 0..len {
+                    let key = self.item(depth + 1)?;
+                    if !seen.insert(encode_cbor(&key)) {
+                        return Err("Duplicate key in CBOR map".to_string());
+                    }
+                    let value = self.item(depth + 1)?;
+                    entries.push((key, value));
+                }
+                CborValue::Map(entries)
+            }
+            6 => {
+                let tag = self.argument(info)?;
+                CborValue::Tag(tag, Box::new(self.item(depth + 1)?))
+            }
+            _ => match info {
+                20 => CborValue::Bool(false),
+                21 => CborValue::Bool(true),
+                22 => CborValue::Null,
+                25 => CborValue::Float(f16_to_f64(u16::from_be_bytes(self.take(2)?.try_into().expect("two bytes")))),
+                26 => CborValue::Float(f32::from_be_bytes(self.take(4)?.try_into().expect("four bytes")) as f64),
+                27 => CborValue::Float(f64::from_be_bytes(self.take(8)?.try_into().expect("eight bytes"))),
+                _ => return Err(format!("Unsupported CBOR simple value {}", info)),
+            },
+        })
+    }
+}
+
+/// Decodes exactly one CBOR item from `bytes`.
+///
+/// Any well-formed definite-length encoding is accepted; use `encode_cbor`
+/// on the result to check whether it was deterministic.
+pub fn decode_cbor(bytes: &[u8]) -> Result<CborValue, String> {
+    let mut decoder = Decoder { input: bytes, pos: 0 };
+    let value = decoder.item(0)?;
+    if decoder.pos != bytes.len() {
+        return Err("Trailing bytes after CBOR item".to_string());
+    }
+    Ok(value)
+}
@@ -0,0 +1,65 @@
+use hmac::Mac;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use super::json::canonicalize_json;
+use super::jws::public_jwk;
+use super::keyring::{KeyAlgorithm, KeyEntry};
+use super::provider::{KeyProvider, ProviderError};
+use super::signing::create_signing_instance;
+
+/// Block an HMAC key check value is computed over: all zeros.
+const ZERO_BLOCK: [u8; 16] = [0; 16];
+
+/// Message an AES key check value is the HMAC-SHA256 of.
+const AES_CHECK_LABEL: &[u8] = b"riot.kcv.v1";
+
+/// A check value is the first 3 bytes of the key's fingerprint, as 6
+/// uppercase hex digits: enough to tell keys apart, too little to help an
+/// attacker.
+fn format_check_value(fingerprint: &[u8]) -> String {
+    hex::encode_upper(&fingerprint[..3])
+}
+
+/// Key check value (KCV) of a 256-bit AES key: the HMAC-SHA256 of
+/// `riot.kcv.v1` under the key.
+///
+/// Not the classic AES encryption of a zero block, which is the GHASH key of
+/// AES-GCM under the same key: publishing part of it helps forge ciphertexts.
+pub fn aes_check_value(key: &[u8]) -> Result<String, String> {
+    if key.len() != 32 {
+        return Err("AES keys must be 32 bytes".to_string());
+    }
+    let mut instance = create_signing_instance(key)?;
+    instance.update(AES_CHECK_LABEL);
+    Ok(format_check_value(&instance.finalize().into_bytes()))
+}
+
+/// Key check value of a signing key, a non-secret fingerprint that tells
+/// whether two deployments hold the same key:
+/// - HMAC keys: the HMAC-SHA256 of a zero block.
+/// - ES256 and EdDSA keys: the RFC 7638 JWK thumbprint of the public key.
+pub fn key_check_value(key: &KeyEntry) -> Result<String, String> {
+    if key.algorithm == KeyAlgorithm::Hs256 {
+        let mut instance = create_signing_instance(&key.secret)?;
+        instance.update(&ZERO_BLOCK);
+        return Ok(format_check_value(&instance.finalize().into_bytes()));
+    }
+    let jwk = public_jwk(key)?.ok_or_else(|| format!("Key {} has no public key", key.kid))?;
+    // The thumbprint covers the required members only, in lexicographic order
+    let members = match jwk["kty"].as_str() {
+        Some("EC") => json!({"crv": jwk["crv"], "kty": "EC", "x": jwk["x"], "y": jwk["y"]}),
+        _ => json!({"crv": jwk["crv"], "kty": jwk["kty"], "x": jwk["x"]}),
+    };
+    Ok(format_check_value(&Sha256::digest(canonicalize_json(&members).to_string().as_bytes())))
+}
+
+/// Key check value of master key `kid` of `provider`, computed by the
+/// provider like that of an HMAC key.
+pub fn provider_check_value(provider: &dyn KeyProvider, kid: &str) -> Result<String, ProviderError> {
+    let tag = provider.sign(kid, &ZERO_BLOCK)?;
+    if tag.len() < 3 {
+        return Err(ProviderError::Failed("the key provider returned a short tag".to_string()));
+    }
+    Ok(format_check_value(&tag))
+}
@@ -0,0 +1,134 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::json::canonicalize_json;
+
+/// Prefix of the signed bytes when claims are present.
+///
+/// Canonical JSON never starts with `r`, so a payload signed with claims can
+/// never produce the same bytes as a plain payload.
+const CLAIMS_DOMAIN: &[u8] = b"riot.claims.v1.";
+
+/// Optional metadata bound into a signature alongside the payload.
+///
+/// Times are Unix timestamps in seconds, as in JWT (RFC 7519).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignatureClaims {
+    /// When the signature was issued.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<u64>,
+    /// The signature is not valid at or after this time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>,
+    /// The signature is not valid before this time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<u64>,
+    /// One-time value; `/verify` accepts each nonce only once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// JSON Pointers (RFC 6901) of the only fields covered by the signature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coverage: Option<Vec<String>>,
+}
+
+/// Why a correctly signed payload is not valid right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClaimsError {
+    Expired,
+    NotYetValid,
+}
+
+impl ClaimsError {
+    /// Stable machine-readable code returned by the API.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ClaimsError::Expired => "signature_expired",
+            ClaimsError::NotYetValid => "signature_not_yet_valid",
+        }
+    }
+}
+
+impl fmt::Display for ClaimsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClaimsError::Expired => write!(f, "Signature expired"),
+            ClaimsError::NotYetValid => write!(f, "Signature not yet valid"),
+        }
+    }
+}
+
+impl SignatureClaims {
+    /// True when no claim is set, in which case the plain payload is signed.
+    pub fn is_empty(&self) -> bool {
+        *self == SignatureClaims::default()
+    }
+
+    /// Checks `exp` and `nbf` against `now`, tolerating `skew` seconds of clock drift.
+    pub fn check_time(&self, now: u64, skew: u64) -> Result<(), ClaimsError> {
+        if let Some(exp) = self.exp {
+            if now >= exp.saturating_add(skew) {
+                return Err(ClaimsError::Expired);
+            }
+        }
+        if let Some(nbf) = self.nbf {
+            if now.saturating_add(skew) < nbf {
+                return Err(ClaimsError::NotYetValid);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Current Unix time in seconds.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Extracts the fields named by `pointers` into an object keyed by pointer.
+///
+/// Fails with the first pointer that is malformed or does not resolve.
+pub fn project_coverage(data: &Value, pointers: &[String]) -> Result<Value, String> {
+    if pointers.is_empty() {
+        return Err("Coverage must list at least one JSON Pointer".to_string());
+    }
+    let mut projection = serde_json::Map::new();
+    for pointer in pointers {
+        if !pointer.starts_with('/') {
+            return Err(format!("Invalid JSON Pointer: '{}'", pointer));
+        }
+        let value = data
+            .pointer(pointer)
+            .ok_or_else(|| format!("Covered field {} is missing", pointer))?;
+        projection.insert(pointer.clone(), value.clone());
+    }
+    Ok(Value::Object(projection))
+}
+
+/// Builds the exact bytes that get signed for `data` and `claims`.
+///
+/// Without claims this is the canonical JSON of `data`, so existing signatures
+/// are unchanged. With claims, the canonical JSON of `{"claims", "data"}` is
+/// signed behind a domain prefix. When `coverage` is set, `data` is replaced
+/// by the projection of the covered fields.
+pub fn signing_input(data: &Value, claims: &SignatureClaims) -> Result<Vec<u8>, String> {
+    if claims.is_empty() {
+        return Ok(canonicalize_json(data).to_string().into_bytes());
+    }
+    let data = match &claims.coverage {
+        Some(pointers) => project_coverage(data, pointers)?,
+        None => data.clone(),
+    };
+    let claims = serde_json::to_value(claims).map_err(|e| format!("Failed to encode claims: {}", e))?;
+    let envelope = serde_json::json!({
+        "claims": claims,
+        "data": data,
+    });
+    let mut input = CLAIMS_DOMAIN.to_vec();
+    input.extend_from_slice(canonicalize_json(&envelope).to_string().as_bytes());
+    Ok(input)
+}
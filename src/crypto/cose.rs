@@ -0,0 +1,124 @@
+use serde_json::Value;
+
+use super::cbor::{canonicalize_cbor, cbor_to_json, decode_cbor, encode_cbor, CborValue};
+use super::keyring::{KeyAlgorithm, KeyEntry, Keyring};
+use super::jws::{sign_with_key, verify_with_key};
+
+/// CBOR tags of the single-signer COSE structures (RFC 9052 section 2).
+const COSE_SIGN1_TAG: u64 = 18;
+const COSE_MAC0_TAG: u64 = 17;
+
+/// Common header labels.
+const HEADER_ALG: i64 = 1;
+const HEADER_KID: i64 = 4;
+
+/// COSE algorithm identifier of a key algorithm (RFC 9053).
+pub fn cose_algorithm(algorithm: KeyAlgorithm) -> i64 {
+    match algorithm {
+        // HMAC 256/256
+        KeyAlgorithm::Hs256 => 5,
+        KeyAlgorithm::Es256 => -7,
+        KeyAlgorithm::EdDsa => -8,
+    }
+}
+
+fn key_algorithm(id: i64) -> Result<KeyAlgorithm, String> {
+    match id {
+        5 => Ok(KeyAlgorithm::Hs256),
+        -7 => Ok(KeyAlgorithm::Es256),
+        -8 => Ok(KeyAlgorithm::EdDsa),
+        other => Err(format!("Unsupported COSE algorithm {}", other)),
+    }
+}
+
+/// Outcome of a successful COSE verification.
+#[derive(Debug)]
+pub struct VerifiedCose {
+    /// Id of the key that verified the message.
+    pub kid: String,
+    /// The signed payload, decoded from canonical CBOR.
+    pub payload: Value,
+}
+
+/// The `Sig_structure` or `MAC_structure` that gets signed (RFC 9052
+/// sections 4.4 and 6.3), with no external AAD.
+fn to_be_signed(mac: bool, protected: &[u8], payload: &[u8]) -> Vec<u8> {
+    let context = if mac { "MAC0" } else { "Signature1" };
+    encode_cbor(&CborValue::Array(vec![
+        CborValue::Text(context.to_string()),
+        CborValue::Bytes(protected.to_vec()),
+        CborValue::Bytes(Vec::new()),
+        CborValue::Bytes(payload.to_vec()),
+    ]))
+}
+
+/// Produces a tagged COSE message over the deterministic CBOR of `data`.
+///
+/// HMAC keys give a `COSE_Mac0`, ES256 and EdDSA keys a `COSE_Sign1`. The
+/// protected header holds `alg` and `kid`, and the payload is embedded.
+pub fn sign_cose(data: &Value, key: &KeyEntry) -> Result<Vec<u8>, String> {
+    let mac = key.algorithm == KeyAlgorithm::Hs256;
+    let protected = encode_cbor(&CborValue::Map(vec![
+        (CborValue::integer(HEADER_ALG), CborValue::integer(cose_algorithm(key.algorithm))),
+        (CborValue::integer(HEADER_KID), CborValue::Bytes(key.kid.as_bytes().to_vec())),
+    ]));
+    let payload = canonicalize_cbor(data);
+    let signature = sign_with_key(key, &to_be_signed(mac, &protected, &payload))?;
+    let tag = if mac { COSE_MAC0_TAG } else { COSE_SIGN1_TAG };
+    Ok(encode_cbor(&CborValue::Tag(tag, Box::new(CborValue::Array(vec![
+        CborValue::Bytes(protected),
+        CborValue::Map(Vec::new()),
+        CborValue::Bytes(payload),
+        CborValue::Bytes(signature),
+    ])))))
+}
+
+/// Verifies a `COSE_Sign1` or `COSE_Mac0` message against the keyring.
+///
+/// Untagged messages are accepted as `COSE_Sign1` for asymmetric algorithms
+/// and `COSE_Mac0` for HMAC. `alg` must be protected and match the key;
+/// `kid` may be protected or unprotected. A detached (nil) payload is taken
+/// from `detached_payload`, encoded as deterministic CBOR.
+pub fn verify_cose(message: &[u8], detached_payload: Option<&Value>, keyring: &Keyring) -> Result<Option<VerifiedCose>, String> {
+    let (tag, structure) = match decode_cbor(message)? {
+        CborValue::Tag(tag @ (COSE_SIGN1_TAG | COSE_MAC0_TAG), inner) => (Some(tag), *inner),
+        CborValue::Tag(tag, _) => return Err(format!("Unsupported COSE tag {}", tag)),
+        untagged => (None, untagged),
+    };
+    let CborValue::Array(items) = structure else {
+        return Err("COSE message must be an array".to_string());
+    };
+    let [CborValue::Bytes(protected), unprotected @ CborValue::Map(_), payload, CborValue::Bytes(signature)] = items.as_slice() else {
+        return Err("Malformed COSE message".to_string());
+    };
+
+    let protected_map = if protected.is_empty() { CborValue::Map(Vec::new()) } else { decode_cbor(protected)? };
+    let algorithm = protected_map
+        .get(&CborValue::integer(HEADER_ALG))
+        .and_then(CborValue::as_i64)
+        .ok_or_else(|| "COSE message has no protected alg".to_string())
+        .and_then(key_algorithm)?;
+    let mac = algorithm == KeyAlgorithm::Hs256;
+    if tag.is_some_and(|tag| (tag == COSE_MAC0_TAG) != mac) {
+        return Err("COSE tag does not match the algorithm".to_string());
+    }
+    let kid = match protected_map.get(&CborValue::integer(HEADER_KID)).or(unprotected.get(&CborValue::integer(HEADER_KID))) {
+        Some(CborValue::Bytes(kid)) => Some(String::from_utf8(kid.clone()).map_err(|_| "COSE kid is not UTF-8".to_string())?),
+        Some(_) => return Err("COSE kid must be a byte string".to_string()),
+        None => None,
+    };
+
+    let payload = match payload {
+        CborValue::Bytes(payload) => payload.clone(),
+        CborValue::Null => canonicalize_cbor(detached_payload.ok_or_else(|| "Detached COSE payload requires data".to_string())?),
+        _ => return Err("COSE payload must be a byte string or nil".to_string()),
+    };
+    let input = to_be_signed(mac, protected, &payload);
+    for key in keyring.verification_keys_for(algorithm, kid.as_deref())? {
+        if verify_with_key(key, &input, signature)? {
+            let payload = cbor_to_json(&decode_cbor(&payload)?)?;
+            return Ok(Some(VerifiedCose { kid: key.kid.clone(), payload }));
+        }
+    }
+    Ok(None)
+}
@@ -1,4 +1,18 @@
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+
+/// Accepts standard Base64 with or without `=` padding.
+const STANDARD_LENIENT: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Accepts URL-safe Base64 with or without `=` padding.
+const URL_SAFE_LENIENT: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
 
 /// Helper function to encode data
 pub fn encode(data: &[u8]) -> String {
@@ -8,4 +22,21 @@ pub fn encode(data: &[u8]) -> String {
 /// Helper function to decode data
 pub fn decode(data: &str) -> Result<Vec<u8>, String> {
     BASE64.decode(data).map_err(|e| format!("Failed to decode: {}", e))
-} 
\ No newline at end of file
+}
+
+/// Decodes a signature supplied by a client into raw bytes.
+///
+/// Hex is tried first for strings made only of hex digits, then standard and
+/// URL-safe Base64 (padding optional). Returns `None` if nothing matches.
+pub fn decode_signature(signature: &str) -> Option<Vec<u8>> {
+    let signature = signature.trim();
+    if signature.len().is_multiple_of(2) && signature.bytes().all(|b| b.is_ascii_hexdigit()) {
+        if let Ok(bytes) = hex::decode(signature) {
+            return Some(bytes);
+        }
+    }
+    STANDARD_LENIENT
+        .decode(signature)
+        .or_else(|_| URL_SAFE_LENIENT.decode(signature))
+        .ok()
+}
@@ -1,3 +1,6 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use rand::RngCore;
 use serde_json::Value;
 use super::encoding::{encode, decode};
 
@@ -52,4 +55,52 @@ pub fn decrypt_data(data: &Value) -> Result<Value, String> {
         }
         _ => Err("Input must be a JSON object".to_string()),
     }
-} 
\ No newline at end of file
+}
+
+/// Encrypts each top-level value of `data` with AES-256-GCM under `key`.
+///
+/// Every value becomes the Base64 of a random 12-byte nonce followed by the
+/// ciphertext of its JSON text. The property name is authenticated too, so
+/// a value cannot be moved to another property.
+pub fn encrypt_data_with_key(data: &Value, key: &[u8; 32]) -> Result<Value, String> {
+    let Value::Object(obj) = data else {
+        return Err("Input must be a JSON object".to_string());
+    };
+    let cipher = Aes256Gcm::new(key.into());
+    let mut result = serde_json::Map::new();
+    for (name, value) in obj {
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let plaintext = value.to_string();
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext.as_bytes(), aad: name.as_bytes() })
+            .map_err(|_| "Encryption failed".to_string())?;
+        result.insert(name.clone(), Value::String(encode(&[&nonce[..], &ciphertext].concat())));
+    }
+    Ok(Value::Object(result))
+}
+
+/// Decrypts the output of `encrypt_data_with_key`, trying each of `keys` in
+/// turn. Unlike `decrypt_data`, every value must decrypt.
+pub fn decrypt_data_with_key(data: &Value, keys: &[[u8; 32]]) -> Result<Value, String> {
+    let Value::Object(obj) = data else {
+        return Err("Input must be a JSON object".to_string());
+    };
+    let mut result = serde_json::Map::new();
+    for (name, value) in obj {
+        let undecryptable = || format!("Property {} could not be decrypted", name);
+        let bytes = value.as_str().and_then(|s| decode(s).ok()).filter(|b| b.len() > 12).ok_or_else(undecryptable)?;
+        let (nonce, ciphertext) = bytes.split_at(12);
+        let plaintext = keys
+            .iter()
+            .find_map(|key| {
+                Aes256Gcm::new(key.into())
+                    .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: name.as_bytes() })
+                    .ok()
+            })
+            .ok_or_else(undecryptable)?;
+        let parsed = serde_json::from_slice(&plaintext).map_err(|_| undecryptable())?;
+        result.insert(name.clone(), parsed);
+    }
+    Ok(Value::Object(result))
+}
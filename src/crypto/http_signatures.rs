@@ -0,0 +1,618 @@
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use sha2::{Digest, Sha256, Sha512};
+use std::fmt;
+
+use super::jws::{sign_with_key, verify_with_key};
+use super::keyring::{KeyAlgorithm, KeyEntry, Keyring};
+
+/// Derived components (RFC 9421 section 2.2) supported for requests.
+const DERIVED_COMPONENTS: &[&str] = &[
+    "@method",
+    "@target-uri",
+    "@authority",
+    "@scheme",
+    "@request-target",
+    "@path",
+    "@query",
+];
+
+/// Why an HTTP message signature was not accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HttpSignatureError {
+    /// The request carries no `Signature`/`Signature-Input` headers.
+    Missing,
+    /// A header or signature parameter could not be parsed.
+    Malformed(String),
+    /// The signature does not cover the parts of the request it must.
+    Uncovered(String),
+    /// The signature is expired, too old or dated in the future.
+    Expired,
+    /// The `Content-Digest` header does not match the body.
+    DigestMismatch,
+    /// The signature does not verify, or its key is unknown or retired.
+    Invalid(String),
+    /// The body is larger than the given number of bytes, so it cannot be
+    /// checked against `Content-Digest`.
+    BodyTooLarge(usize),
+}
+
+impl HttpSignatureError {
+    /// Stable machine-readable code returned by the API.
+    pub fn code(&self) -> &'static str {
+        match self {
+            HttpSignatureError::Missing => "http_signature_missing",
+            HttpSignatureError::Malformed(_) => "http_signature_malformed",
+            HttpSignatureError::Uncovered(_) => "http_signature_uncovered",
+            HttpSignatureError::Expired => "http_signature_expired",
+            HttpSignatureError::DigestMismatch => "content_digest_mismatch",
+            HttpSignatureError::Invalid(_) => "http_signature_invalid",
+            HttpSignatureError::BodyTooLarge(_) => "http_signature_body_too_large",
+        }
+    }
+}
+
+impl fmt::Display for HttpSignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpSignatureError::Missing => write!(f, "Missing HTTP message signature"),
+            HttpSignatureError::Malformed(e) => write!(f, "Malformed HTTP message signature: {}", e),
+            HttpSignatureError::Uncovered(e) => write!(f, "HTTP message signature does not cover {}", e),
+            HttpSignatureError::Expired => write!(f, "HTTP message signature expired"),
+            HttpSignatureError::DigestMismatch => write!(f, "Content-Digest does not match the body"),
+            HttpSignatureError::Invalid(e) => write!(f, "Invalid HTTP message signature: {}", e),
+            HttpSignatureError::BodyTooLarge(limit) => write!(f, "Signed request body exceeds {} bytes", limit),
+        }
+    }
+}
+
+/// The parts of an HTTP request a signature can cover.
+#[derive(Debug, Clone, Default)]
+pub struct HttpRequestParts {
+    /// Request method, e.g. `POST`.
+    pub method: String,
+    /// URI scheme, e.g. `https`.
+    pub scheme: String,
+    /// Host and, when not the default, port.
+    pub authority: String,
+    /// Absolute path; `/` when empty.
+    pub path: String,
+    /// Query string without the leading `?`.
+    pub query: Option<String>,
+    /// Header fields in the order received; names are matched case-insensitively.
+    pub headers: Vec<(String, String)>,
+}
+
+impl HttpRequestParts {
+    /// The full target URI, as covered by `@target-uri`.
+    pub fn target_uri(&self) -> String {
+        format!("{}://{}{}", self.scheme.to_ascii_lowercase(), self.authority.to_ascii_lowercase(), self.request_target())
+    }
+
+    fn path_or_root(&self) -> &str {
+        if self.path.is_empty() { "/" } else { &self.path }
+    }
+
+    fn request_target(&self) -> String {
+        match &self.query {
+            Some(query) => format!("{}?{}", self.path_or_root(), query),
+            None => self.path_or_root().to_string(),
+        }
+    }
+
+    /// Combined value of every instance of a header field, or `None` if absent.
+    pub fn header(&self, name: &str) -> Option<String> {
+        let values: Vec<&str> = self.headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.trim())
+            .collect();
+        (!values.is_empty()).then(|| values.join(", "))
+    }
+
+    /// Value of a covered component (RFC 9421 section 2).
+    fn component(&self, name: &str) -> Result<String, HttpSignatureError> {
+        match name {
+            "@method" => Ok(self.method.clone()),
+            "@target-uri" => Ok(self.target_uri()),
+            "@authority" => Ok(self.authority.to_ascii_lowercase()),
+            "@scheme" => Ok(self.scheme.to_ascii_lowercase()),
+            "@request-target" => Ok(self.request_target()),
+            "@path" => Ok(self.path_or_root().to_string()),
+            "@query" => Ok(format!("?{}", self.query.as_deref().unwrap_or(""))),
+            derived if derived.starts_with('@') => {
+                Err(HttpSignatureError::Malformed(format!("unsupported component {}", derived)))
+            }
+            field => self.header(field).ok_or_else(|| {
+                HttpSignatureError::Malformed(format!("covered header {} is missing", field))
+            }),
+        }
+    }
+}
+
+/// A bare item of an RFC 8941 structured field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamValue {
+    Integer(i64),
+    String(String),
+    Token(String),
+    Bytes(Vec<u8>),
+    Boolean(bool),
+}
+
+impl ParamValue {
+    fn serialize(&self, out: &mut String) {
+        match self {
+            ParamValue::Integer(i) => out.push_str(&i.to_string()),
+            ParamValue::String(s) => {
+                out.push('"');
+                for c in s.chars() {
+                    if c == '"' || c == '\\' {
+                        out.push('\\');
+                    }
+                    out.push(c);
+                }
+                out.push('"');
+            }
+            ParamValue::Token(t) => out.push_str(t),
+            ParamValue::Bytes(b) => {
+                out.push(':');
+                out.push_str(&BASE64.encode(b));
+                out.push(':');
+            }
+            ParamValue::Boolean(b) => out.push_str(if *b { "?1" } else { "?0" }),
+        }
+    }
+}
+
+/// Covered components and parameters of one signature, as in `Signature-Input`.
+///
+/// Parameters keep the order they were received in, because that order is
+/// part of the signature base.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SignatureParams {
+    /// Covered component identifiers, e.g. `@method` or `content-digest`.
+    pub components: Vec<String>,
+    params: Vec<(String, ParamValue)>,
+}
+
+impl SignatureParams {
+    /// Parameters covering `components`, with no parameters yet.
+    pub fn new(components: Vec<String>) -> Self {
+        SignatureParams { components, params: Vec::new() }
+    }
+
+    /// Sets parameter `name`, replacing an earlier value.
+    pub fn with(mut self, name: &str, value: ParamValue) -> Self {
+        self.params.retain(|(n, _)| n != name);
+        self.params.push((name.to_string(), value));
+        self
+    }
+
+    fn integer(&self, name: &str) -> Option<i64> {
+        self.params.iter().find_map(|(n, v)| match v {
+            ParamValue::Integer(i) if n == name => Some(*i),
+            _ => None,
+        })
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        self.params.iter().find_map(|(n, v)| match v {
+            ParamValue::String(s) if n == name => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// The `created` parameter, as a Unix timestamp.
+    pub fn created(&self) -> Option<u64> {
+        self.integer("created").and_then(|i| u64::try_from(i).ok())
+    }
+
+    /// The `expires` parameter, as a Unix timestamp.
+    pub fn expires(&self) -> Option<u64> {
+        self.integer("expires").and_then(|i| u64::try_from(i).ok())
+    }
+
+    /// The `keyid` parameter.
+    pub fn keyid(&self) -> Option<&str> {
+        self.string("keyid")
+    }
+
+    /// The `alg` parameter.
+    pub fn alg(&self) -> Option<&str> {
+        self.string("alg")
+    }
+
+    /// The `nonce` parameter.
+    pub fn nonce(&self) -> Option<&str> {
+        self.string("nonce")
+    }
+
+    /// True when `component` is covered.
+    pub fn covers(&self, component: &str) -> bool {
+        self.components.iter().any(|c| c == component)
+    }
+
+    /// Checks `created` and `expires` against `now`.
+    ///
+    /// `created` is required, may not be more than `max_age` seconds old and,
+    /// like `expires`, is compared allowing `skew` seconds of clock drift.
+    pub fn check_time(&self, now: u64, skew: u64, max_age: u64) -> Result<(), HttpSignatureError> {
+        let created = self.created().ok_or_else(|| HttpSignatureError::Uncovered("a created time".to_string()))?;
+        if created > now.saturating_add(skew) || now > created.saturating_add(max_age).saturating_add(skew) {
+            return Err(HttpSignatureError::Expired);
+        }
+        if self.expires().is_some_and(|expires| now >= expires.saturating_add(skew)) {
+            return Err(HttpSignatureError::Expired);
+        }
+        Ok(())
+    }
+
+    /// Serialization used in `Signature-Input` and the signature base.
+    pub fn serialize(&self) -> String {
+        let mut out = String::from("(");
+        for (i, component) in self.components.iter().enumerate() {
+            if i > 0 {
+                out.push(' ');
+            }
+            ParamValue::String(component.clone()).serialize(&mut out);
+        }
+        out.push(')');
+        for (name, value) in &self.params {
+            out.push(';');
+            out.push_str(name);
+            if *value != ParamValue::Boolean(true) {
+                out.push('=');
+                value.serialize(&mut out);
+            }
+        }
+        out
+    }
+}
+
+/// A signature that verified, with the key that produced it.
+#[derive(Debug, Clone)]
+pub struct VerifiedHttpSignature {
+    /// Label of the signature in the `Signature` header.
+    pub label: String,
+    /// Id of the key that verified the signature.
+    pub kid: String,
+    /// Covered components and parameters.
+    pub params: SignatureParams,
+}
+
+/// RFC 9421 algorithm name of a key algorithm.
+pub fn http_signature_alg(algorithm: KeyAlgorithm) -> &'static str {
+    match algorithm {
+        KeyAlgorithm::Hs256 => "hmac-sha256",
+        KeyAlgorithm::Es256 => "ecdsa-p256-sha256",
+        KeyAlgorithm::EdDsa => "ed25519",
+    }
+}
+
+fn key_algorithm(alg: &str) -> Result<KeyAlgorithm, HttpSignatureError> {
+    match alg {
+        "hmac-sha256" => Ok(KeyAlgorithm::Hs256),
+        "ecdsa-p256-sha256" => Ok(KeyAlgorithm::Es256),
+        "ed25519" => Ok(KeyAlgorithm::EdDsa),
+        other => Err(HttpSignatureError::Malformed(format!("unsupported algorithm {}", other))),
+    }
+}
+
+/// `Content-Digest` header value (RFC 9530) for `body`, using SHA-256.
+pub fn content_digest(body: &[u8]) -> String {
+    format!("sha-256=:{}:", BASE64.encode(Sha256::digest(body)))
+}
+
+/// Checks a `Content-Digest` header against `body`.
+///
+/// Every `sha-256` and `sha-512` digest must match, and at least one must be present.
+pub fn check_content_digest(header: &str, body: &[u8]) -> Result<(), HttpSignatureError> {
+    let mut checked = false;
+    for (algorithm, value) in parse_dictionary(header)? {
+        let expected = match algorithm.as_str() {
+            "sha-256" => Sha256::digest(body).to_vec(),
+            "sha-512" => Sha512::digest(body).to_vec(),
+            _ => continue,
+        };
+        let Member::Item(ParamValue::Bytes(digest)) = value else {
+            return Err(HttpSignatureError::Malformed("digest is not a byte sequence".to_string()));
+        };
+        if digest != expected {
+            return Err(HttpSignatureError::DigestMismatch);
+        }
+        checked = true;
+    }
+    if !checked {
+        return Err(HttpSignatureError::Malformed("no supported Content-Digest algorithm".to_string()));
+    }
+    Ok(())
+}
+
+/// Builds the signature base (RFC 9421 section 2.5) that gets signed.
+pub fn signature_base(parts: &HttpRequestParts, params: &SignatureParams) -> Result<String, HttpSignatureError> {
+    let mut base = String::new();
+    for (i, component) in params.components.iter().enumerate() {
+        if params.components[..i].contains(component) {
+            return Err(HttpSignatureError::Malformed(format!("component {} is covered twice", component)));
+        }
+        if component.bytes().any(|b| b.is_ascii_uppercase())
+            || (component.starts_with('@') && !DERIVED_COMPONENTS.contains(&component.as_str()))
+        {
+            return Err(HttpSignatureError::Malformed(format!("unsupported component {}", component)));
+        }
+        let value = parts.component(component)?;
+        if value.contains('\n') {
+            return Err(HttpSignatureError::Malformed(format!("component {} contains a newline", component)));
+        }
+        base.push_str(&format!("\"{}\": {}\n", component, value));
+    }
+    base.push_str(&format!("\"@signature-params\": {}", params.serialize()));
+    Ok(base)
+}
+
+/// Signs `parts` with `key`, returning the `Signature-Input` and `Signature`
+/// header values for `label`.
+pub fn sign_message(
+    parts: &HttpRequestParts,
+    params: &SignatureParams,
+    key: &KeyEntry,
+    label: &str,
+) -> Result<(String, String), HttpSignatureError> {
+    if !is_key(label) {
+        return Err(HttpSignatureError::Malformed(format!("invalid label {}", label)));
+    }
+    let base = signature_base(parts, params)?;
+    let signature = sign_with_key(key, base.as_bytes()).map_err(HttpSignatureError::Invalid)?;
+    let mut signature_header = format!("{}=", label);
+    ParamValue::Bytes(signature).serialize(&mut signature_header);
+    Ok((format!("{}={}", label, params.serialize()), signature_header))
+}
+
+/// Verifies every signature of a request against the keyring.
+///
+/// `signature_input` and `signature` are the raw header values. The key is
+/// chosen from the `keyid` and `alg` parameters; without either, every
+/// non-retired HMAC key is tried. All labels must verify.
+pub fn verify_message(
+    parts: &HttpRequestParts,
+    signature_input: &str,
+    signature: &str,
+    keyring: &Keyring,
+) -> Result<Vec<VerifiedHttpSignature>, HttpSignatureError> {
+    let signatures = parse_dictionary(signature)?;
+    let inputs = parse_dictionary(signature_input)?;
+    if inputs.is_empty() {
+        return Err(HttpSignatureError::Missing);
+    }
+
+    let mut verified = Vec::with_capacity(inputs.len());
+    for (label, member) in inputs {
+        let Member::InnerList(items, params) = member else {
+            return Err(HttpSignatureError::Malformed(format!("{} is not an inner list", label)));
+        };
+        let mut components = Vec::with_capacity(items.len());
+        for (item, item_params) in items {
+            match item {
+                ParamValue::String(name) if item_params.is_empty() => components.push(name),
+                ParamValue::String(name) => {
+                    return Err(HttpSignatureError::Malformed(format!("unsupported parameters on {}", name)));
+                }
+                _ => return Err(HttpSignatureError::Malformed("component identifiers must be strings".to_string())),
+            }
+        }
+        let params = SignatureParams { components, params };
+        let Some(Member::Item(ParamValue::Bytes(signature))) =
+            signatures.iter().find(|(l, _)| *l == label).map(|(_, m)| m.clone())
+        else {
+            return Err(HttpSignatureError::Malformed(format!("no signature for label {}", label)));
+        };
+
+        let algorithm = match (params.alg(), params.keyid()) {
+            (Some(alg), _) => key_algorithm(alg)?,
+            (None, Some(kid)) => keyring.get(kid).map_or(KeyAlgorithm::Hs256, |key| key.algorithm),
+            (None, None) => KeyAlgorithm::Hs256,
+        };
+        let keys = keyring
+            .verification_keys_for(algorithm, params.keyid())
+            .map_err(HttpSignatureError::Invalid)?;
+        let base = signature_base(parts, &params)?;
+        let mut matched = None;
+        for key in keys {
+            if verify_with_key(key, base.as_bytes(), &signature).map_err(HttpSignatureError::Invalid)? {
+                matched = Some(key.kid.clone());
+                break;
+            }
+        }
+        let kid = matched.ok_or_else(|| HttpSignatureError::Invalid(format!("signature {} does not match", label)))?;
+        verified.push(VerifiedHttpSignature { label, kid, params });
+    }
+    Ok(verified)
+}
+
+/// A dictionary member: an item, whose parameters are not needed, or an
+/// inner list with parameters.
+#[derive(Debug, Clone)]
+enum Member {
+    Item(ParamValue),
+    InnerList(Vec<(ParamValue, Vec<(String, ParamValue)>)>, Vec<(String, ParamValue)>),
+}
+
+fn is_key(key: &str) -> bool {
+    let mut bytes = key.bytes();
+    bytes.next().is_some_and(|b| b.is_ascii_lowercase() || b == b'*')
+        && bytes.all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"_-.*".contains(&b))
+}
+
+/// Parses an RFC 8941 dictionary, keeping member order.
+fn parse_dictionary(input: &str) -> Result<Vec<(String, Member)>, HttpSignatureError> {
+    let mut parser = Parser { input: input.as_bytes(), pos: 0 };
+    let mut members: Vec<(String, Member)> = Vec::new();
+    parser.skip(b" \t");
+    while !parser.done() {
+        let key = parser.key()?;
+        let member = if parser.eat(b'=') {
+            parser.member()?
+        } else {
+            parser.params()?;
+            Member::Item(ParamValue::Boolean(true))
+        };
+        members.retain(|(k, _)| *k != key);
+        members.push((key, member));
+        parser.skip(b" \t");
+        if parser.done() {
+            break;
+        }
+        if !parser.eat(b',') {
+            return Err(parser.error("expected ','"));
+        }
+        parser.skip(b" \t");
+        if parser.done() {
+            return Err(parser.error("trailing ','"));
+        }
+    }
+    Ok(members)
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn done(&self) -> bool {
+        self.pos >= self.input.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let matched = self.peek() == Some(byte);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    fn skip(&mut self, bytes: &[u8]) {
+        while self.peek().is_some_and(|b| bytes.contains(&b)) {
+            self.pos += 1;
+        }
+    }
+
+    fn error(&self, message: &str) -> HttpSignatureError {
+        HttpSignatureError::Malformed(format!("{} at offset {}", message, self.pos))
+    }
+
+    fn take_while(&mut self, accept: impl Fn(u8) -> bool) -> &str {
+        let start = self.pos;
+        while self.peek().is_some_and(&accept) {
+            self.pos += 1;
+        }
+        // Only ASCII bytes are ever accepted
+        std::str::from_utf8(&self.input[start..self.pos]).unwrap_or_default()
+    }
+
+    fn key(&mut self) -> Result<String, HttpSignatureError> {
+        if !self.peek().is_some_and(|b| b.is_ascii_lowercase() || b == b'*') {
+            return Err(self.error("expected a key"));
+        }
+        Ok(self
+            .take_while(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"_-.*".contains(&b))
+            .to_string())
+    }
+
+    fn member(&mut self) -> Result<Member, HttpSignatureError> {
+        if !self.eat(b'(') {
+            let item = self.bare_item()?;
+            self.params()?;
+            return Ok(Member::Item(item));
+        }
+        let mut items = Vec::new();
+        loop {
+            self.skip(b" ");
+            if self.eat(b')') {
+                break;
+            }
+            let item = self.bare_item()?;
+            items.push((item, self.params()?));
+            if !matches!(self.peek(), Some(b' ') | Some(b')')) {
+                return Err(self.error("expected ' ' or ')'"));
+            }
+        }
+        Ok(Member::InnerList(items, self.params()?))
+    }
+
+    fn params(&mut self) -> Result<Vec<(String, ParamValue)>, HttpSignatureError> {
+        let mut params: Vec<(String, ParamValue)> = Vec::new();
+        while self.eat(b';') {
+            self.skip(b" ");
+            let key = self.key()?;
+            let value = if self.eat(b'=') { self.bare_item()? } else { ParamValue::Boolean(true) };
+            params.retain(|(k, _)| *k != key);
+            params.push((key, value));
+        }
+        Ok(params)
+    }
+
+    fn bare_item(&mut self) -> Result<ParamValue, HttpSignatureError> {
+        match self.peek() {
+            Some(b'"') => {
+                self.pos += 1;
+                let mut value = String::new();
+                loop {
+                    match self.peek() {
+                        Some(b'"') => {
+                            self.pos += 1;
+                            return Ok(ParamValue::String(value));
+                        }
+                        Some(b'\\') => {
+                            self.pos += 1;
+                            match self.peek() {
+                                Some(c @ (b'"' | b'\\')) => value.push(c as char),
+                                _ => return Err(self.error("invalid escape")),
+                            }
+                        }
+                        Some(c @ 0x20..=0x7e) => value.push(c as char),
+                        _ => return Err(self.error("unterminated string")),
+                    }
+                    self.pos += 1;
+                }
+            }
+            Some(b':') => {
+                self.pos += 1;
+                let encoded = self.take_while(|b| b.is_ascii_alphanumeric() || b"+/=".contains(&b)).to_string();
+                if !self.eat(b':') {
+                    return Err(self.error("unterminated byte sequence"));
+                }
+                BASE64.decode(encoded).map(ParamValue::Bytes).map_err(|_| self.error("invalid byte sequence"))
+            }
+            Some(b'?') => {
+                self.pos += 1;
+                let value = match self.peek() {
+                    Some(b'0') => false,
+                    Some(b'1') => true,
+                    _ => return Err(self.error("invalid boolean")),
+                };
+                self.pos += 1;
+                Ok(ParamValue::Boolean(value))
+            }
+            Some(b) if b == b'-' || b.is_ascii_digit() => {
+                let start = self.pos;
+                self.eat(b'-');
+                let digits = self.take_while(|b| b.is_ascii_digit()).len();
+                if digits == 0 || digits > 15 || self.peek() == Some(b'.') {
+                    return Err(self.error("unsupported number"));
+                }
+                let text = std::str::from_utf8(&self.input[start..self.pos]).unwrap_or_default();
+                text.parse().map(ParamValue::Integer).map_err(|_| self.error("invalid integer"))
+            }
+            Some(b) if b.is_ascii_alphabetic() || b == b'*' => Ok(ParamValue::Token(
+                self.take_while(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~:/".contains(&b))
+                    .to_string(),
+            )),
+            _ => Err(self.error("expected an item")),
+        }
+    }
+}
@@ -1,5 +1,8 @@
-use serde_json::Value;
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::{Map, Number, Value};
 use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
 
 /// Recursively sorts JSON objects by key to create a canonical representation.
 ///
@@ -31,4 +34,137 @@ pub fn canonicalize_json(value: &Value) -> Value {
         }
         _ => value.clone(),
     }
-} 
\ No newline at end of file
+}
+
+/// What to do when a JSON object repeats the same key.
+///
+/// RFC 8259 only says names SHOULD be unique, and `serde_json` silently keeps
+/// the last value. For signed payloads that means two different byte strings
+/// can map to the same canonical form, so rejecting is the default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateKeyPolicy {
+    /// Fail parsing as soon as a duplicate key is seen.
+    #[default]
+    Reject,
+    /// Keep the last value for the key (plain `serde_json` behaviour).
+    LastWins,
+}
+
+impl fmt::Display for DuplicateKeyPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            DuplicateKeyPolicy::Reject => "reject",
+            DuplicateKeyPolicy::LastWins => "last-wins",
+        })
+    }
+}
+
+impl FromStr for DuplicateKeyPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "reject" => Ok(DuplicateKeyPolicy::Reject),
+            "last-wins" | "last_wins" => Ok(DuplicateKeyPolicy::LastWins),
+            other => Err(format!("Unknown duplicate key policy: {}", other)),
+        }
+    }
+}
+
+/// Parses a JSON document, applying `policy` to duplicate object keys at any depth.
+///
+/// With `DuplicateKeyPolicy::Reject` the error names the offending key and the
+/// JSON Pointer of the object that contains it.
+pub fn parse_json(bytes: &[u8], policy: DuplicateKeyPolicy) -> Result<Value, String> {
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+    let value = ValueSeed { path: String::new(), policy }
+        .deserialize(&mut deserializer)
+        .map_err(|e| format!("Invalid JSON: {}", e))?;
+    deserializer.end().map_err(|e| format!("Invalid JSON: {}", e))?;
+    Ok(value)
+}
+
+/// Escapes a single reference token as described in RFC 6901.
+pub fn escape_pointer_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+/// Deserializes a `Value` while remembering where it sits in the document.
+struct ValueSeed {
+    path: String,
+    policy: DuplicateKeyPolicy,
+}
+
+impl<'de> DeserializeSeed<'de> for ValueSeed {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ValueSeed {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any valid JSON value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+        Ok(Value::Number(v.into()))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Value, E> {
+        Ok(Value::Number(v.into()))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Value, E> {
+        Ok(Number::from_f64(v).map_or(Value::Null, Value::Number))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element_seed(ValueSeed {
+            path: format!("{}/{}", self.path, items.len()),
+            policy: self.policy,
+        })? {
+            items.push(item);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut object = Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            if self.policy == DuplicateKeyPolicy::Reject && object.contains_key(&key) {
+                let location = if self.path.is_empty() { "/" } else { self.path.as_str() };
+                return Err(de::Error::custom(format!(
+                    "duplicate key '{}' in object at {}",
+                    key, location
+                )));
+            }
+            let value = map.next_value_seed(ValueSeed {
+                path: format!("{}/{}", self.path, escape_pointer_token(&key)),
+                policy: self.policy,
+            })?;
+            object.insert(key, value);
+        }
+        Ok(Value::Object(object))
+    }
+}
@@ -0,0 +1,213 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use ed25519_dalek::Signer as _;
+use p256::ecdsa::signature::Verifier as _;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::json::{canonicalize_json, parse_json, DuplicateKeyPolicy};
+use super::keyring::{KeyAlgorithm, KeyEntry, Keyring};
+use super::signing::create_signing_instance;
+use hmac::Mac;
+use zeroize::Zeroizing;
+
+/// JOSE protected header produced and understood by Riot.
+#[derive(Debug, Serialize, Deserialize)]
+struct JwsHeader {
+    alg: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kid: Option<String>,
+    /// RFC 7797 unencoded payload option.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    b64: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    crit: Option<Vec<String>>,
+}
+
+/// Header parameters `verify_jws` understands when they are listed in `crit`.
+const SUPPORTED_CRITICAL: &[&str] = &["b64"];
+
+/// Outcome of a successful JWS verification.
+#[derive(Debug)]
+pub struct VerifiedJws {
+    /// Id of the key that verified the signature.
+    pub kid: String,
+    /// The signed JSON payload.
+    pub payload: Value,
+}
+
+/// Signs `input` with `key`, as the raw JWS signature bytes of its algorithm.
+///
+/// ES256 signatures are the 64-byte `r || s` form required by RFC 7518.
+pub fn sign_with_key(key: &KeyEntry, input: &[u8]) -> Result<Vec<u8>, String> {
+    match key.algorithm {
+        KeyAlgorithm::Hs256 => {
+            let mut instance = create_signing_instance(&key.secret)?;
+            instance.update(input);
+            Ok(instance.finalize().into_bytes().to_vec())
+        }
+        KeyAlgorithm::Es256 => {
+            let signing_key = p256::ecdsa::SigningKey::from_slice(&key.secret)
+                .map_err(|e| format!("Invalid ES256 key {}: {}", key.kid, e))?;
+            let signature: p256::ecdsa::Signature = signing_key.sign(input);
+            Ok(signature.to_bytes().to_vec())
+        }
+        KeyAlgorithm::EdDsa => {
+            let signing_key = ed25519_signing_key(key)?;
+            Ok(signing_key.sign(input).to_bytes().to_vec())
+        }
+    }
+}
+
+/// Checks raw JWS signature bytes produced by `sign_with_key`.
+pub fn verify_with_key(key: &KeyEntry, input: &[u8], signature: &[u8]) -> Result<bool, String> {
+    match key.algorithm {
+        KeyAlgorithm::Hs256 => {
+            let mut instance = create_signing_instance(&key.secret)?;
+            instance.update(input);
+            Ok(instance.verify_slice(signature).is_ok())
+        }
+        KeyAlgorithm::Es256 => {
+            let signing_key = p256::ecdsa::SigningKey::from_slice(&key.secret)
+                .map_err(|e| format!("Invalid ES256 key {}: {}", key.kid, e))?;
+            let Ok(signature) = p256::ecdsa::Signature::from_slice(signature) else {
+                return Ok(false);
+            };
+            Ok(signing_key.verifying_key().verify(input, &signature).is_ok())
+        }
+        KeyAlgorithm::EdDsa => {
+            let verifying_key = ed25519_signing_key(key)?.verifying_key();
+            let Ok(signature) = ed25519_dalek::Signature::from_slice(signature) else {
+                return Ok(false);
+            };
+            Ok(verifying_key.verify_strict(input, &signature).is_ok())
+        }
+    }
+}
+
+/// The public JWK (RFC 7517) of an asymmetric key, with its `kid`, `alg`
+/// and `use`; `None` for HMAC keys, which have no public half.
+pub fn public_jwk(key: &KeyEntry) -> Result<Option<Value>, String> {
+    let mut jwk = match key.algorithm {
+        KeyAlgorithm::Hs256 => return Ok(None),
+        KeyAlgorithm::Es256 => {
+            let signing_key = p256::ecdsa::SigningKey::from_slice(&key.secret)
+                .map_err(|e| format!("Invalid ES256 key {}: {}", key.kid, e))?;
+            let point = signing_key.verifying_key().to_encoded_point(false);
+            let (Some(x), Some(y)) = (point.x(), point.y()) else {
+                return Err(format!("Invalid ES256 key {}", key.kid));
+            };
+            json!({"kty": "EC", "crv": "P-256", "x": URL_SAFE_NO_PAD.encode(x), "y": URL_SAFE_NO_PAD.encode(y)})
+        }
+        KeyAlgorithm::EdDsa => {
+            let verifying_key = ed25519_signing_key(key)?.verifying_key();
+            json!({"kty": "OKP", "crv": "Ed25519", "x": URL_SAFE_NO_PAD.encode(verifying_key.as_bytes())})
+        }
+    };
+    jwk["kid"] = Value::from(key.kid.as_str());
+    jwk["alg"] = Value::from(key.algorithm.as_str());
+    jwk["use"] = Value::from("sig");
+    Ok(Some(jwk))
+}
+
+fn ed25519_signing_key(key: &KeyEntry) -> Result<ed25519_dalek::SigningKey, String> {
+    let seed: Zeroizing<[u8; 32]> = Zeroizing::new(key.secret.expose()
+        .try_into()
+        .map_err(|_| format!("Invalid EdDSA key {}: expected a 32-byte seed", key.kid))?);
+    Ok(ed25519_dalek::SigningKey::from_bytes(&seed))
+}
+
+/// Produces a JWS (RFC 7515) over the canonical JSON of `data`.
+///
+/// The compact form embeds the payload; the detached form uses the RFC 7797
+/// unencoded payload option and leaves the payload segment empty, so the
+/// caller sends `data` alongside the token.
+pub fn sign_jws(data: &Value, key: &KeyEntry, detached: bool) -> Result<String, String> {
+    let header = JwsHeader {
+        alg: key.algorithm.as_str().to_string(),
+        kid: Some(key.kid.clone()),
+        b64: detached.then_some(false),
+        crit: detached.then(|| vec!["b64".to_string()]),
+    };
+    let header = serde_json::to_vec(&header).map_err(|e| format!("Failed to encode header: {}", e))?;
+    let encoded_header = URL_SAFE_NO_PAD.encode(header);
+    let payload = canonicalize_json(data).to_string();
+
+    if detached {
+        let mut input = format!("{}.", encoded_header).into_bytes();
+        input.extend_from_slice(payload.as_bytes());
+        let signature = sign_with_key(key, &input)?;
+        Ok(format!("{}..{}", encoded_header, URL_SAFE_NO_PAD.encode(signature)))
+    } else {
+        let input = format!("{}.{}", encoded_header, URL_SAFE_NO_PAD.encode(payload));
+        let signature = sign_with_key(key, input.as_bytes())?;
+        Ok(format!("{}.{}", input, URL_SAFE_NO_PAD.encode(signature)))
+    }
+}
+
+/// Verifies a compact or detached JWS against the keyring.
+///
+/// `detached_payload` must be given for RFC 7797 tokens and is canonicalized
+/// before verification. The header `alg` must match the algorithm of the key,
+/// and retired keys are never used.
+pub fn verify_jws(
+    jws: &str,
+    detached_payload: Option<&Value>,
+    keyring: &Keyring,
+    policy: DuplicateKeyPolicy,
+) -> Result<Option<VerifiedJws>, String> {
+    let mut parts = jws.trim().split('.');
+    let (Some(encoded_header), Some(encoded_payload), Some(encoded_signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err("JWS must have three segments".to_string());
+    };
+    let header_bytes = URL_SAFE_NO_PAD.decode(encoded_header).map_err(|_| "Invalid JWS header encoding".to_string())?;
+    let header: JwsHeader = serde_json::from_slice(&header_bytes).map_err(|e| format!("Invalid JWS header: {}", e))?;
+    let algorithm: KeyAlgorithm = header.alg.parse()?;
+    if let Some(crit) = &header.crit {
+        if crit.is_empty() {
+            return Err("JWS crit must not be empty".to_string());
+        }
+        if let Some(name) = crit.iter().find(|name| !SUPPORTED_CRITICAL.contains(&name.as_str())) {
+            return Err(format!("Unsupported critical JWS header parameter '{}'", name));
+        }
+    }
+    let b64_critical = header.crit.as_ref().is_some_and(|crit| crit.iter().any(|name| name == "b64"));
+    let unencoded = match (header.b64, b64_critical) {
+        (None, false) | (Some(true), _) => false,
+        (Some(false), true) => true,
+        (Some(false), false) => return Err("JWS b64=false must be listed in crit".to_string()),
+        (None, true) => return Err("JWS crit lists 'b64' but the header does not set it".to_string()),
+    };
+    let Ok(signature) = URL_SAFE_NO_PAD.decode(encoded_signature) else {
+        return Ok(None);
+    };
+
+    let (input, payload) = if unencoded {
+        if !encoded_payload.is_empty() {
+            return Err("Unencoded JWS payloads must be detached".to_string());
+        }
+        let data = detached_payload.ok_or_else(|| "Detached JWS requires data".to_string())?;
+        let mut input = format!("{}.", encoded_header).into_bytes();
+        input.extend_from_slice(canonicalize_json(data).to_string().as_bytes());
+        (input, data.clone())
+    } else if encoded_payload.is_empty() {
+        // RFC 7515 Appendix F: detached, but still Base64url encoded
+        let data = detached_payload.ok_or_else(|| "Detached JWS requires data".to_string())?;
+        let payload = URL_SAFE_NO_PAD.encode(canonicalize_json(data).to_string());
+        (format!("{}.{}", encoded_header, payload).into_bytes(), data.clone())
+    } else {
+        let payload_bytes = URL_SAFE_NO_PAD
+            .decode(encoded_payload)
+            .map_err(|_| "Invalid JWS payload encoding".to_string())?;
+        let input = format!("{}.{}", encoded_header, encoded_payload).into_bytes();
+        (input, parse_json(&payload_bytes, policy)?)
+    };
+
+    for key in keyring.verification_keys_for(algorithm, header.kid.as_deref())? {
+        if verify_with_key(key, &input, &signature)? {
+            return Ok(Some(VerifiedJws { kid: key.kid.clone(), payload }));
+        }
+    }
+    Ok(None)
+}
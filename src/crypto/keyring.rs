@@ -0,0 +1,324 @@
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+
+use super::encoding::decode_signature;
+use super::secret::{check_secret_strength, SecretBytes};
+
+/// What a key is used for, named after its JOSE algorithm (RFC 7518 / RFC 8037).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyAlgorithm {
+    /// HMAC-SHA256 with a shared secret.
+    #[serde(rename = "HS256")]
+    Hs256,
+    /// ECDSA over P-256 with SHA-256; the key holds the 32-byte private scalar.
+    #[serde(rename = "ES256")]
+    Es256,
+    /// Ed25519; the key holds the 32-byte private seed.
+    #[serde(rename = "EdDSA")]
+    EdDsa,
+}
+
+impl KeyAlgorithm {
+    /// Every supported algorithm.
+    pub const ALL: [KeyAlgorithm; 3] = [KeyAlgorithm::Hs256, KeyAlgorithm::Es256, KeyAlgorithm::EdDsa];
+
+    /// The JOSE `alg` name.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyAlgorithm::Hs256 => "HS256",
+            KeyAlgorithm::Es256 => "ES256",
+            KeyAlgorithm::EdDsa => "EdDSA",
+        }
+    }
+}
+
+impl fmt::Display for KeyAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for KeyAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "HS256" => Ok(KeyAlgorithm::Hs256),
+            "ES256" => Ok(KeyAlgorithm::Es256),
+            "EdDSA" => Ok(KeyAlgorithm::EdDsa),
+            other => Err(format!("Unsupported algorithm: {}", other)),
+        }
+    }
+}
+
+/// Lifecycle state of a signing key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyState {
+    /// Used to sign new payloads. Exactly one key is active.
+    Active,
+    /// Still accepted by `/verify`, never used to sign.
+    VerifyOnly,
+    /// Kept for reference only; signatures made with it no longer verify.
+    Retired,
+}
+
+/// A named secret: an HMAC key, or the private half of an asymmetric key.
+#[derive(Debug, Clone)]
+pub struct KeyEntry {
+    pub kid: String,
+    pub algorithm: KeyAlgorithm,
+    pub secret: SecretBytes,
+    pub state: KeyState,
+}
+
+impl KeyEntry {
+    /// An HMAC-SHA256 key.
+    pub fn new(kid: impl Into<String>, secret: impl Into<SecretBytes>, state: KeyState) -> Self {
+        KeyEntry::for_algorithm(kid, KeyAlgorithm::Hs256, secret, state)
+    }
+
+    /// A key for any supported algorithm.
+    pub fn for_algorithm(
+        kid: impl Into<String>,
+        algorithm: KeyAlgorithm,
+        secret: impl Into<SecretBytes>,
+        state: KeyState,
+    ) -> Self {
+        KeyEntry { kid: kid.into(), algorithm, secret: secret.into(), state }
+    }
+}
+
+/// The set of keys known to the service, addressed by key id (`kid`).
+///
+/// `/sign` always uses the active key; `/verify` accepts any key that is not
+/// retired, which lets old signatures keep verifying while a new key rolls out.
+/// There is exactly one active HMAC key, and at most one active key for each
+/// asymmetric algorithm.
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    keys: Vec<KeyEntry>,
+}
+
+/// Key id used when the keyring is built from a single `HMAC_SECRET_KEY`.
+pub const DEFAULT_KEY_ID: &str = "default";
+
+impl Keyring {
+    /// Builds a keyring, checking that key ids are unique and that each
+    /// algorithm has at most one active key, with exactly one active HMAC key.
+    pub fn new(keys: Vec<KeyEntry>) -> Result<Self, String> {
+        for (i, key) in keys.iter().enumerate() {
+            if key.kid.is_empty() {
+                return Err("Key id must not be empty".to_string());
+            }
+            if keys[..i].iter().any(|other| other.kid == key.kid) {
+                return Err(format!("Duplicate key id: {}", key.kid));
+            }
+        }
+        for algorithm in [KeyAlgorithm::Es256, KeyAlgorithm::EdDsa] {
+            let active = keys.iter().filter(|k| k.algorithm == algorithm && k.state == KeyState::Active).count();
+            if active > 1 {
+                return Err(format!("Keyring has more than one active {} key", algorithm));
+            }
+        }
+        match keys.iter().filter(|k| k.algorithm == KeyAlgorithm::Hs256 && k.state == KeyState::Active).count() {
+            1 => Ok(Keyring { keys }),
+            0 => Err("Keyring has no active key".to_string()),
+            _ => Err("Keyring has more than one active key".to_string()),
+        }
+    }
+
+    /// Wraps a single secret as the active key with id `default`.
+    pub fn single(secret: impl Into<SecretBytes>) -> Self {
+        Keyring { keys: vec![KeyEntry::new(DEFAULT_KEY_ID, secret, KeyState::Active)] }
+    }
+
+    /// Loads the keyring from environment variables.
+    ///
+    /// - `HMAC_KEYS`: comma-separated `kid:secret` pairs. When unset, the
+    ///   legacy `HMAC_SECRET_KEY` becomes the only key, with id `HMAC_KEY_ID`
+    ///   (default `default`). Both read secrets as `decode_secret` does.
+    /// - `HMAC_ACTIVE_KEY_ID`: the key used for signing. Defaults to the first
+    ///   key listed; every other key is verify-only.
+    /// - `HMAC_RETIRED_KEY_IDS`: comma-separated ids that no longer verify.
+    /// - `JWS_KEYS`: optional comma-separated `kid:alg:private-key` triples for
+    ///   `ES256` and `EdDSA`, the 32-byte private key in Base64 or hex. The
+    ///   first key of each algorithm is active, later ones verify-only.
+    ///
+    /// Secrets of keys that are not retired must pass `check_secret_strength`.
+    pub fn from_env() -> Result<Self, String> {
+        Keyring::from_vars(|name| env::var(name).ok())
+    }
+
+    /// Builds the keyring like `from_env`, reading variables through `var`.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let mut keys = match var("HMAC_KEYS") {
+            Some(spec) => parse_key_list(&spec)?,
+            None => {
+                let secret = var("HMAC_SECRET_KEY")
+                    .ok_or_else(|| "HMAC_KEYS or HMAC_SECRET_KEY must be set".to_string())?;
+                let kid = var("HMAC_KEY_ID").unwrap_or_else(|| DEFAULT_KEY_ID.to_string());
+                let secret = decode_secret(&secret).map_err(|e| format!("HMAC_SECRET_KEY: {}", e))?;
+                vec![KeyEntry::new(kid, secret, KeyState::VerifyOnly)]
+            }
+        };
+
+        let active = match var("HMAC_ACTIVE_KEY_ID") {
+            Some(kid) => kid,
+            None => keys.first().map(|k| k.kid.clone()).unwrap_or_default(),
+        };
+        let retired: Vec<String> = var("HMAC_RETIRED_KEY_IDS")
+            .map(|ids| ids.split(',').map(|id| id.trim().to_string()).filter(|id| !id.is_empty()).collect())
+            .unwrap_or_default();
+
+        for key in &mut keys {
+            if key.kid == active {
+                key.state = KeyState::Active;
+            } else if retired.contains(&key.kid) {
+                key.state = KeyState::Retired;
+            }
+        }
+        if !keys.iter().any(|k| k.kid == active) {
+            return Err(format!("Active key id {} does not match any configured key", active));
+        }
+        if retired.contains(&active) {
+            return Err(format!("Active key {} cannot be retired", active));
+        }
+        if let Some(spec) = var("JWS_KEYS") {
+            for mut key in parse_asymmetric_key_list(&spec)? {
+                if retired.contains(&key.kid) {
+                    key.state = KeyState::Retired;
+                } else if !keys.iter().any(|k| k.algorithm == key.algorithm && k.state == KeyState::Active) {
+                    key.state = KeyState::Active;
+                }
+                keys.push(key);
+            }
+        }
+        for key in keys.iter().filter(|k| k.state != KeyState::Retired) {
+            check_secret_strength(&key.kid, &key.secret)?;
+        }
+        Keyring::new(keys)
+    }
+
+    /// The HMAC key used to sign new payloads.
+    pub fn active(&self) -> &KeyEntry {
+        self.active_for(KeyAlgorithm::Hs256)
+            .expect("keyring always holds an active HMAC key")
+    }
+
+    /// The active key for `algorithm`, if one is configured.
+    pub fn active_for(&self, algorithm: KeyAlgorithm) -> Option<&KeyEntry> {
+        self.keys
+            .iter()
+            .find(|k| k.algorithm == algorithm && k.state == KeyState::Active)
+    }
+
+    /// Looks up a key by id, whatever its state.
+    pub fn get(&self, kid: &str) -> Option<&KeyEntry> {
+        self.keys.iter().find(|k| k.kid == kid)
+    }
+
+    /// All keys, in configuration order.
+    pub fn keys(&self) -> &[KeyEntry] {
+        &self.keys
+    }
+
+    /// HMAC keys a signature may be checked against.
+    ///
+    /// With a `kid`, only that key is returned, and only if it is not retired.
+    /// Without one, every non-retired key is a candidate, active key first.
+    pub fn verification_keys(&self, kid: Option<&str>) -> Result<Vec<&KeyEntry>, String> {
+        self.verification_keys_for(KeyAlgorithm::Hs256, kid)
+    }
+
+    /// Like `verification_keys`, for keys of `algorithm`.
+    pub fn verification_keys_for(&self, algorithm: KeyAlgorithm, kid: Option<&str>) -> Result<Vec<&KeyEntry>, String> {
+        match kid {
+            Some(kid) => match self.get(kid) {
+                Some(key) if key.algorithm != algorithm => Err(format!("Key {} is not an {} key", kid, algorithm)),
+                Some(key) if key.state != KeyState::Retired => Ok(vec![key]),
+                Some(_) => Err(format!("Key {} is retired", kid)),
+                None => Err(format!("Unknown key id: {}", kid)),
+            },
+            None => {
+                let mut keys: Vec<&KeyEntry> = self.keys
+                    .iter()
+                    .filter(|k| k.algorithm == algorithm && k.state != KeyState::Retired)
+                    .collect();
+                keys.sort_by_key(|k| k.state != KeyState::Active);
+                Ok(keys)
+            }
+        }
+    }
+}
+
+/// Decodes an HMAC secret from configuration.
+///
+/// A value starting with `hex:` or `base64:` is decoded from that encoding;
+/// any other value is used as its raw bytes. The rule is the same for
+/// `HMAC_SECRET_KEY` and `HMAC_KEYS`, so a value means the same key in either.
+fn decode_secret(value: &str) -> Result<Vec<u8>, String> {
+    let bytes = if let Some(encoded) = value.strip_prefix("hex:") {
+        hex::decode(encoded.trim()).map_err(|_| "the value after hex: is not hex".to_string())?
+    } else if let Some(encoded) = value.strip_prefix("base64:") {
+        BASE64.decode(encoded.trim()).map_err(|_| "the value after base64: is not Base64".to_string())?
+    } else {
+        value.as_bytes().to_vec()
+    };
+    if bytes.is_empty() {
+        return Err("the secret is empty".to_string());
+    }
+    Ok(bytes)
+}
+
+/// Parses `kid:secret,kid:secret` into verify-only entries, each secret read
+/// by `decode_secret`. A raw secret cannot contain a comma; give such a
+/// secret as `hex:` or `base64:`.
+fn parse_key_list(spec: &str) -> Result<Vec<KeyEntry>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .enumerate()
+        .map(|(i, item)| {
+            // Never echo the item itself: it may be a bare secret
+            let (kid, secret) = item
+                .split_once(':')
+                .ok_or_else(|| format!("HMAC_KEYS entry {} is not of the form kid:secret", i + 1))?;
+            let kid = kid.trim();
+            if kid.is_empty() {
+                return Err(format!("HMAC_KEYS entry {} has no key id", i + 1));
+            }
+            if secret.is_empty() {
+                return Err(format!("Empty secret for key {}", kid));
+            }
+            let secret = decode_secret(secret).map_err(|e| format!("Key {}: {}", kid, e))?;
+            Ok(KeyEntry::new(kid, secret, KeyState::VerifyOnly))
+        })
+        .collect()
+}
+
+/// Parses `kid:alg:private-key` triples into verify-only entries.
+fn parse_asymmetric_key_list(spec: &str) -> Result<Vec<KeyEntry>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .enumerate()
+        .map(|(i, item)| {
+            let mut parts = item.splitn(3, ':');
+            let (Some(kid), Some(alg), Some(private_key)) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(format!("JWS_KEYS entry {} is not of the form kid:alg:private-key", i + 1));
+            };
+            let algorithm: KeyAlgorithm = alg.parse()?;
+            if algorithm == KeyAlgorithm::Hs256 {
+                return Err(format!("Key {}: HMAC keys belong in HMAC_KEYS", kid));
+            }
+            let secret = decode_signature(private_key)
+                .filter(|bytes| bytes.len() == 32)
+                .ok_or_else(|| format!("Key {}: private key must be 32 bytes of Base64 or hex", kid))?;
+            Ok(KeyEntry::for_algorithm(kid.trim(), algorithm, secret, KeyState::VerifyOnly))
+        })
+        .collect()
+}
@@ -0,0 +1,228 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use super::json::{canonicalize_json, escape_pointer_token};
+use super::signing::create_signing_instance;
+use hmac::Mac;
+
+/// Prefix of the bytes signed for a Merkle root.
+const MERKLE_DOMAIN: &str = "riot.merkle.v1.";
+
+/// Domain separation between leaf and interior hashes (RFC 6962 style).
+const LEAF_TAG: u8 = 0x00;
+const NODE_TAG: u8 = 0x01;
+
+type Hash = [u8; 32];
+
+/// Which side of the running hash a sibling sits on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// One step from a leaf up to the root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofStep {
+    /// Hex-encoded sibling hash.
+    pub hash: String,
+    /// Side of the sibling.
+    pub side: Side,
+}
+
+/// Proof that one leaf of a document is part of a signed Merkle root.
+///
+/// Only the leaf itself is disclosed: siblings are hashes, and every leaf
+/// hash includes a secret-derived salt so low-entropy values cannot be
+/// guessed from them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    /// JSON Pointer of the leaf.
+    pub pointer: String,
+    /// The disclosed value: a scalar, or an empty object or array.
+    pub value: Value,
+    /// Hex-encoded salt of the leaf.
+    pub salt: String,
+    /// Position of the leaf among all leaves, sorted by pointer.
+    pub index: usize,
+    /// Sibling hashes from the leaf up to the root.
+    pub path: Vec<ProofStep>,
+}
+
+struct Leaf {
+    pointer: String,
+    value: Value,
+    salt: Hash,
+}
+
+/// Merkle tree over the leaves of a JSON document.
+///
+/// Leaves are the scalars and empty containers of the document, addressed by
+/// JSON Pointer and sorted by pointer, so property order does not matter.
+/// An odd node at the end of a level is promoted unchanged.
+pub struct MerkleTree {
+    leaves: Vec<Leaf>,
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    /// Builds the tree for `data`, deriving leaf salts from `secret_key`.
+    pub fn build(data: &Value, secret_key: &[u8]) -> Result<Self, String> {
+        let document_digest: Hash = Sha256::digest(canonicalize_json(data).to_string().as_bytes()).into();
+        let mut flat = Vec::new();
+        collect_leaves(data, String::new(), &mut flat);
+        flat.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut leaves = Vec::with_capacity(flat.len());
+        for (pointer, value) in flat {
+            let mut instance = create_signing_instance(secret_key)?;
+            instance.update(b"riot.merkle.salt.");
+            instance.update(&document_digest);
+            instance.update(pointer.as_bytes());
+            let salt: Hash = instance.finalize().into_bytes().into();
+            leaves.push(Leaf { pointer, value, salt });
+        }
+
+        let mut levels = vec![leaves.iter().map(|l| leaf_hash(&l.pointer, &l.value, &l.salt)).collect::<Vec<_>>()];
+        while levels.last().is_some_and(|level| level.len() > 1) {
+            let below = levels.last().expect("at least one level");
+            let above = below
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node_hash(left, right),
+                    [single] => *single,
+                    _ => unreachable!("chunks of two"),
+                })
+                .collect();
+            levels.push(above);
+        }
+        Ok(MerkleTree { leaves, levels })
+    }
+
+    /// Hex-encoded root hash.
+    pub fn root(&self) -> String {
+        hex::encode(self.levels.last().expect("at least one level")[0])
+    }
+
+    /// Number of leaves.
+    pub fn leaf_count(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Inclusion proofs for every leaf at or below `pointer`.
+    pub fn prove(&self, pointer: &str) -> Result<Vec<InclusionProof>, String> {
+        let prefix = format!("{}/", pointer);
+        let proofs: Vec<InclusionProof> = self.leaves
+            .iter()
+            .enumerate()
+            .filter(|(_, leaf)| pointer.is_empty() || leaf.pointer == pointer || leaf.pointer.starts_with(&prefix))
+            .map(|(index, leaf)| InclusionProof {
+                pointer: leaf.pointer.clone(),
+                value: leaf.value.clone(),
+                salt: hex::encode(leaf.salt),
+                index,
+                path: self.path(index),
+            })
+            .collect();
+        if proofs.is_empty() {
+            return Err(format!("Nothing to prove at {}", pointer));
+        }
+        Ok(proofs)
+    }
+
+    fn path(&self, mut index: usize) -> Vec<ProofStep> {
+        let mut path = Vec::new();
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = index ^ 1;
+            if sibling < level.len() {
+                let side = if sibling < index { Side::Left } else { Side::Right };
+                path.push(ProofStep { hash: hex::encode(level[sibling]), side });
+            }
+            index /= 2;
+        }
+        path
+    }
+}
+
+/// Checks that `proof` leads to the hex-encoded `root` of a tree with
+/// `leaf_count` leaves, along the path its `index` takes in such a tree.
+pub fn verify_inclusion(proof: &InclusionProof, root: &str, leaf_count: usize) -> bool {
+    let Some(sides) = path_sides(proof.index, leaf_count) else { return false };
+    if sides.len() != proof.path.len() || sides.iter().zip(&proof.path).any(|(side, step)| *side != step.side) {
+        return false;
+    }
+    let Some(salt) = decode_hash(&proof.salt) else { return false };
+    let mut hash = leaf_hash(&proof.pointer, &proof.value, &salt);
+    for step in &proof.path {
+        let Some(sibling) = decode_hash(&step.hash) else { return false };
+        hash = match step.side {
+            Side::Left => node_hash(&sibling, &hash),
+            Side::Right => node_hash(&hash, &sibling),
+        };
+    }
+    hex::encode(hash).eq_ignore_ascii_case(root)
+}
+
+/// Sides of the siblings on the path from leaf `index` to the root of a tree
+/// with `leaf_count` leaves, as `MerkleTree::path` lays them out.
+///
+/// Returns `None` if the tree has no such leaf.
+fn path_sides(mut index: usize, leaf_count: usize) -> Option<Vec<Side>> {
+    if index >= leaf_count {
+        return None;
+    }
+    let mut sides = Vec::new();
+    let mut width = leaf_count;
+    while width > 1 {
+        let sibling = index ^ 1;
+        if sibling < width {
+            sides.push(if sibling < index { Side::Left } else { Side::Right });
+        }
+        index /= 2;
+        width = width.div_ceil(2);
+    }
+    Some(sides)
+}
+
+/// The bytes signed for a Merkle root, binding the number of leaves too.
+pub fn merkle_signing_input(root: &str, leaf_count: usize) -> Vec<u8> {
+    format!("{}{}.{}", MERKLE_DOMAIN, root.to_ascii_lowercase(), leaf_count).into_bytes()
+}
+
+fn collect_leaves(value: &Value, pointer: String, leaves: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(object) if !object.is_empty() => {
+            for (key, child) in object {
+                collect_leaves(child, format!("{}/{}", pointer, escape_pointer_token(key)), leaves);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for (i, child) in items.iter().enumerate() {
+                collect_leaves(child, format!("{}/{}", pointer, i), leaves);
+            }
+        }
+        _ => leaves.push((pointer, value.clone())),
+    }
+}
+
+fn leaf_hash(pointer: &str, value: &Value, salt: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_TAG]);
+    hasher.update(salt);
+    hasher.update(serde_json::json!([pointer, value]).to_string().as_bytes());
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_TAG]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+fn decode_hash(encoded: &str) -> Option<Hash> {
+    hex::decode(encoded).ok()?.try_into().ok()
+}
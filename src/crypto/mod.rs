@@ -1,17 +1,62 @@
 //! This module handles the core cryptographic operations:
 //! - Encoding/decoding for the /encrypt and /decrypt endpoints.
 //! - Signing and verification for the /sign and /verify endpoints.
+//! - The keyring of named HMAC keys used for signing and key rotation.
+//! - Time claims (`iat`, `exp`, `nbf`) bound into signatures.
+//! - JWS (RFC 7515 / RFC 7797) output with HS256, ES256 and EdDSA.
+//! - Deterministic CBOR (RFC 8949) and COSE_Sign1 / COSE_Mac0 (RFC 9052) output.
+//! - Merkle trees over JSON documents for selective disclosure.
+//! - Signature sets from several keys, verified against a threshold policy.
+//! - HTTP Message Signatures (RFC 9421) over whole requests.
+//! - Verification of provider webhooks signed over the raw body.
+//! - Per-tenant keys derived from a master key with HKDF.
+//! - Zeroizing, optionally memory-locked storage for key material.
+//! - Key providers holding master keys outside the service, like a KMS.
+//! - Key check values, non-secret fingerprints of keys.
+//!
 //! It also includes JSON canonicalization logic to ensure signatures are consistent.
 
 mod encoding;
 mod signing;
 mod json;
 mod encryption;
+mod keyring;
+mod claims;
+mod jws;
+mod cbor;
+mod cose;
+mod merkle;
+mod multisig;
+mod http_signatures;
+mod webhooks;
+mod tenants;
+mod secret;
+mod provider;
+mod check_value;
 
-pub use encoding::{encode, decode};
-pub use signing::{create_signing_instance, compute, sign_data, verify_signature};
-pub use json::canonicalize_json;
-pub use encryption::{encrypt_data, decrypt_data};
+pub use encoding::{encode, decode, decode_signature};
+pub use signing::{
+    create_signing_instance, compute, sign_data, sign_raw_bytes, sign_with_claims, verify_bytes, verify_raw_bytes, verify_signature,
+    verify_with_claims,
+};
+pub use json::{canonicalize_json, parse_json, DuplicateKeyPolicy};
+pub use encryption::{encrypt_data, decrypt_data, encrypt_data_with_key, decrypt_data_with_key};
+pub use claims::{project_coverage, signing_input, unix_now, ClaimsError, SignatureClaims};
+pub use keyring::{Keyring, KeyAlgorithm, KeyEntry, KeyState, DEFAULT_KEY_ID};
+pub use multisig::{sign_set, verify_set, FailedSignature, SetReport, SetSignature, ThresholdPolicy};
+pub use merkle::{merkle_signing_input, verify_inclusion, InclusionProof, MerkleTree, ProofStep, Side};
+pub use http_signatures::{
+    check_content_digest, content_digest, http_signature_alg, sign_message, signature_base, verify_message,
+    HttpRequestParts, HttpSignatureError, ParamValue, SignatureParams, VerifiedHttpSignature,
+};
+pub use secret::{check_secret_strength, lock_secret_memory, SecretBytes, MIN_SECRET_BYTES, MIN_SECRET_ENTROPY_BITS};
+pub use check_value::{aes_check_value, key_check_value, provider_check_value};
+pub use provider::{FileKeyProvider, KeyProvider, ObjectHandle, ProviderError, SoftToken};
+pub use tenants::{derive_provider_tenant_key, derive_tenant_key, derive_tenant_key_v2, validate_tenant_id, TenantKeyUse, MAX_TENANT_ID_LEN};
+pub use webhooks::{sign_webhook, verify_webhook, WebhookError, WebhookProfile, WebhookScheme};
+pub use cbor::{canonicalize_cbor, cbor_to_json, decode_cbor, encode_cbor, json_to_cbor, CborValue};
+pub use cose::{cose_algorithm, sign_cose, verify_cose, VerifiedCose};
+pub use jws::{public_jwk, sign_jws, sign_with_key, verify_jws, verify_with_key, VerifiedJws};
 
 #[cfg(test)]
 mod tests; 
\ No newline at end of file
@@ -0,0 +1,172 @@
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::claims::{signing_input, SignatureClaims};
+use super::encoding::decode_signature;
+use super::jws::{sign_with_key, verify_with_key};
+use super::keyring::{KeyAlgorithm, KeyEntry, KeyState, Keyring};
+
+/// Prefix of the bytes signed by set members, so that a member can never
+/// pass for a plain signature of the same key, nor the other way round.
+const SET_DOMAIN: &[u8] = b"riot.set.v1.";
+
+/// One member of a signature set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetSignature {
+    /// Id of the key that produced the signature.
+    pub kid: String,
+    /// Algorithm of the key.
+    pub alg: KeyAlgorithm,
+    /// Base64 signature over the bytes of a plain signature with these claims,
+    /// behind the set domain prefix.
+    pub signature: String,
+}
+
+/// How many signatures of a set must verify, and from which keys.
+///
+/// The server's policy comes from `ThresholdPolicy::for_signers`; a policy
+/// sent by a client can only tighten it, with `tightened_by`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThresholdPolicy {
+    /// Number of distinct keys whose signature must be valid. Defaults to
+    /// every key of `kids`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<usize>,
+    /// Key ids allowed to count towards the threshold. Required by `verify_set`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kids: Option<Vec<String>>,
+}
+
+impl ThresholdPolicy {
+    /// The policy under which `signers` may sign and `threshold` of them,
+    /// all by default, must.
+    pub fn for_signers(signers: &[String], threshold: Option<usize>) -> Self {
+        ThresholdPolicy {
+            threshold: Some(threshold.unwrap_or(signers.len())),
+            kids: Some(signers.to_vec()),
+        }
+    }
+
+    /// Applies `client` on top of this policy: the higher threshold wins, and
+    /// only keys allowed by both count. `client` can never loosen the policy.
+    pub fn tightened_by(&self, client: &ThresholdPolicy) -> Self {
+        let kids = match (&self.kids, &client.kids) {
+            (Some(allowed), Some(requested)) => Some(allowed.iter().filter(|kid| requested.contains(kid)).cloned().collect()),
+            (allowed, None) => allowed.clone(),
+            (None, requested) => requested.clone(),
+        };
+        let threshold = match (self.threshold, client.threshold) {
+            (Some(required), Some(requested)) => Some(required.max(requested)),
+            (required, requested) => required.or(requested),
+        };
+        ThresholdPolicy { threshold, kids }
+    }
+}
+
+/// A signature of a set that did not count towards the threshold.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedSignature {
+    pub kid: String,
+    pub reason: String,
+}
+
+/// Outcome of verifying a signature set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetReport {
+    /// True when at least `threshold` signatures passed.
+    pub valid: bool,
+    /// The threshold that was applied.
+    pub threshold: usize,
+    /// Key ids whose signature verified, in set order.
+    pub passed: Vec<String>,
+    /// Signatures that did not count, with the reason.
+    pub failed: Vec<FailedSignature>,
+}
+
+/// Signs `data` and `claims` once with each of `keys`.
+///
+/// Every member signs the bytes a plain signature with the same claims would
+/// cover, behind the set domain prefix.
+pub fn sign_set(data: &Value, claims: &SignatureClaims, keys: &[&KeyEntry]) -> Result<Vec<SetSignature>, String> {
+    let input = set_signing_input(data, claims)?;
+    keys.iter()
+        .map(|key| {
+            Ok(SetSignature {
+                kid: key.kid.clone(),
+                alg: key.algorithm,
+                signature: BASE64.encode(sign_with_key(key, &input)?),
+            })
+        })
+        .collect()
+}
+
+/// Verifies each member of a signature set and applies `policy`.
+///
+/// A key counts at most once, however often it appears in the set. Members
+/// whose key is unknown, retired, of another algorithm or outside
+/// `policy.kids` are reported as failed rather than rejected outright.
+/// The threshold never depends on the set itself: a policy without `kids`
+/// is an error.
+pub fn verify_set(
+    data: &Value,
+    claims: &SignatureClaims,
+    signatures: &[SetSignature],
+    policy: &ThresholdPolicy,
+    keyring: &Keyring,
+) -> Result<SetReport, String> {
+    if signatures.is_empty() {
+        return Err("Signature set is empty".to_string());
+    }
+    let Some(kids) = &policy.kids else {
+        return Err("Signature set policy must list the allowed keys".to_string());
+    };
+    let threshold = policy.threshold.unwrap_or(kids.len());
+    if threshold == 0 {
+        return Err("Threshold must be at least 1".to_string());
+    }
+    let input = set_signing_input(data, claims)?;
+    let mut passed: Vec<String> = Vec::new();
+    let mut failed = Vec::new();
+    for member in signatures {
+        let outcome = check_member(member, &input, policy, keyring, &passed)?;
+        match outcome {
+            None => passed.push(member.kid.clone()),
+            Some(reason) => failed.push(FailedSignature { kid: member.kid.clone(), reason: reason.to_string() }),
+        }
+    }
+    Ok(SetReport { valid: passed.len() >= threshold, threshold, passed, failed })
+}
+
+/// The bytes set members sign: the plain signing input behind `SET_DOMAIN`.
+fn set_signing_input(data: &Value, claims: &SignatureClaims) -> Result<Vec<u8>, String> {
+    let mut input = SET_DOMAIN.to_vec();
+    input.extend_from_slice(&signing_input(data, claims)?);
+    Ok(input)
+}
+
+/// Returns why `member` does not count, or `None` if it does.
+fn check_member(
+    member: &SetSignature,
+    input: &[u8],
+    policy: &ThresholdPolicy,
+    keyring: &Keyring,
+    passed: &[String],
+) -> Result<Option<&'static str>, String> {
+    if policy.kids.as_ref().is_some_and(|kids| !kids.contains(&member.kid)) {
+        return Ok(Some("key not allowed by policy"));
+    }
+    if passed.contains(&member.kid) {
+        return Ok(Some("duplicate key"));
+    }
+    let key = match keyring.get(&member.kid) {
+        None => return Ok(Some("unknown key")),
+        Some(key) if key.state == KeyState::Retired => return Ok(Some("retired key")),
+        Some(key) if key.algorithm != member.alg => return Ok(Some("algorithm mismatch")),
+        Some(key) => key,
+    };
+    let Some(signature) = decode_signature(&member.signature) else {
+        return Ok(Some("invalid signature"));
+    };
+    Ok((!verify_with_key(key, input, &signature)?).then_some("invalid signature"))
+}
@@ -0,0 +1,340 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use hmac::Mac;
+use rand::RngCore;
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::{Mutex, RwLock};
+use zeroize::Zeroizing;
+
+use super::secret::SecretBytes;
+use super::signing::create_signing_instance;
+
+/// Holds master keys and uses them on the service's behalf, so that the
+/// service never sees them: a KMS, an HSM, or a local stand-in.
+///
+/// Master keys are 256-bit keys named by an id. `wrap` and `unwrap` protect
+/// other keys with AES-256-GCM, `sign` computes HMAC-SHA256 tags. An adapter
+/// for a remote KMS maps these onto its own encrypt, decrypt and MAC calls.
+///
+/// The service uses a provider for two things only: deriving tenant keys
+/// from a master it holds, and wrapping the key of an encrypted keyring
+/// file. Signing keys, including those of `/sign`, are unwrapped into the
+/// service's memory and used there.
+pub trait KeyProvider: Send + Sync {
+    /// Short name for logs, such as `file`.
+    fn name(&self) -> &str;
+
+    /// True when the provider holds a master key `kid`.
+    fn has_key(&self, kid: &str) -> bool;
+
+    /// Encrypts `plaintext`, typically a data key, under master key `kid`.
+    fn wrap(&self, kid: &str, plaintext: &[u8]) -> Result<Vec<u8>, ProviderError>;
+
+    /// Decrypts what `wrap` returned for the same `kid`.
+    fn unwrap(&self, kid: &str, wrapped: &[u8]) -> Result<SecretBytes, ProviderError>;
+
+    /// HMAC-SHA256 of `data` under master key `kid`.
+    fn sign(&self, kid: &str, data: &[u8]) -> Result<Vec<u8>, ProviderError>;
+}
+
+/// Why a key provider refused an operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProviderError {
+    /// The provider holds no key with this id.
+    UnknownKey(String),
+    /// The token requires a login first.
+    NotLoggedIn,
+    /// The PIN given to log in is wrong.
+    IncorrectPin,
+    /// The operation failed, for instance on a corrupted wrapped key.
+    Failed(String),
+}
+
+impl ProviderError {
+    /// Stable machine-readable code.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProviderError::UnknownKey(_) => "provider_key_not_found",
+            ProviderError::NotLoggedIn => "provider_not_logged_in",
+            ProviderError::IncorrectPin => "provider_pin_incorrect",
+            ProviderError::Failed(_) => "provider_failed",
+        }
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProviderError::UnknownKey(kid) => write!(f, "Key provider has no key {}", kid),
+            ProviderError::NotLoggedIn => write!(f, "Key provider token is not logged in"),
+            ProviderError::IncorrectPin => write!(f, "Incorrect key provider PIN"),
+            ProviderError::Failed(e) => write!(f, "Key provider operation failed: {}", e),
+        }
+    }
+}
+
+/// AES-256-GCM under `key`, returning nonce ‖ ciphertext. The key id is
+/// authenticated, so a key wrapped under one id never unwraps under another.
+fn seal(key: &[u8], kid: &str, plaintext: &[u8]) -> Result<Vec<u8>, ProviderError> {
+    let cipher = Aes256Gcm::new_from_slice(key).map_err(|e| ProviderError::Failed(e.to_string()))?;
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: kid.as_bytes() })
+        .map_err(|_| ProviderError::Failed("wrapping failed".to_string()))?;
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+fn open(key: &[u8], kid: &str, wrapped: &[u8]) -> Result<SecretBytes, ProviderError> {
+    if wrapped.len() < 12 {
+        return Err(ProviderError::Failed("wrapped key is truncated".to_string()));
+    }
+    let cipher = Aes256Gcm::new_from_slice(key).map_err(|e| ProviderError::Failed(e.to_string()))?;
+    let (nonce, ciphertext) = wrapped.split_at(12);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: kid.as_bytes() })
+        .map(SecretBytes::from)
+        .map_err(|_| ProviderError::Failed(format!("cannot unwrap with key {}: wrong key or corrupted data", kid)))
+}
+
+fn mac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, ProviderError> {
+    let mut instance = create_signing_instance(key).map_err(ProviderError::Failed)?;
+    instance.update(data);
+    Ok(instance.finalize().into_bytes().to_vec())
+}
+
+/// Master keys read from a local file: a stand-in for a KMS in development
+/// and tests. The keys are in the service's memory, but not in its keyring
+/// or environment, and the file can live on a separate, tighter-permissioned
+/// volume.
+///
+/// The file is TOML or JSON, with a `keys` array of `kid` and `secret`
+/// entries, each secret 32 bytes in standard Base64:
+///
+/// ```toml
+/// [[keys]]
+/// kid = "master-1"
+/// secret = "q5Rk0Cw0kDnG3m1oDqz9Kk4V8m0r1Zr4m6yJQ8c2d4E="
+/// ```
+pub struct FileKeyProvider {
+    keys: Vec<(String, SecretBytes)>,
+}
+
+#[derive(Deserialize)]
+struct MasterKeyFile {
+    keys: Vec<MasterKeyRecord>,
+}
+
+#[derive(Deserialize)]
+struct MasterKeyRecord {
+    kid: String,
+    secret: Zeroizing<String>,
+}
+
+impl FileKeyProvider {
+    /// Reads the master keys from `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = Zeroizing::new(fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?);
+        let file: MasterKeyFile = if text.trim_start().starts_with('{') {
+            serde_json::from_str(&text).map_err(|e| format!("Invalid master key file {}: {}", path.display(), e))?
+        } else {
+            toml::from_str(&text).map_err(|e| format!("Invalid master key file {}: {}", path.display(), e))?
+        };
+        let keys = file
+            .keys
+            .into_iter()
+            .map(|record| {
+                let secret = BASE64
+                    .decode(record.secret.trim())
+                    .map_err(|_| format!("Master key {}: secret must be standard Base64", record.kid))?;
+                Ok((record.kid, SecretBytes::from(secret)))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        FileKeyProvider::new(keys)
+    }
+
+    /// A provider holding `keys`, which must be 32 bytes each, with unique ids.
+    pub fn new(keys: Vec<(String, SecretBytes)>) -> Result<Self, String> {
+        for (i, (kid, secret)) in keys.iter().enumerate() {
+            if secret.len() != 32 {
+                return Err(format!("Master key {} must be 32 bytes", kid));
+            }
+            if keys[..i].iter().any(|(other, _)| other == kid) {
+                return Err(format!("Duplicate master key id: {}", kid));
+            }
+        }
+        Ok(FileKeyProvider { keys })
+    }
+
+    fn key(&self, kid: &str) -> Result<&SecretBytes, ProviderError> {
+        self.keys
+            .iter()
+            .find(|(id, _)| id == kid)
+            .map(|(_, secret)| secret)
+            .ok_or_else(|| ProviderError::UnknownKey(kid.to_string()))
+    }
+}
+
+impl KeyProvider for FileKeyProvider {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn has_key(&self, kid: &str) -> bool {
+        self.key(kid).is_ok()
+    }
+
+    fn wrap(&self, kid: &str, plaintext: &[u8]) -> Result<Vec<u8>, ProviderError> {
+        seal(self.key(kid)?, kid, plaintext)
+    }
+
+    fn unwrap(&self, kid: &str, wrapped: &[u8]) -> Result<SecretBytes, ProviderError> {
+        open(self.key(kid)?, kid, wrapped)
+    }
+
+    fn sign(&self, kid: &str, data: &[u8]) -> Result<Vec<u8>, ProviderError> {
+        mac(self.key(kid)?, data)
+    }
+}
+
+/// Handle of a key object on a `SoftToken`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjectHandle(pub u64);
+
+struct TokenObject {
+    handle: ObjectHandle,
+    label: String,
+    value: SecretBytes,
+}
+
+/// An in-memory token that behaves like a PKCS#11 device, for tests: keys
+/// are generated or imported as objects, found by label, and never leave the
+/// token; every operation requires a login with the user PIN.
+///
+/// As a `KeyProvider`, key ids are object labels.
+pub struct SoftToken {
+    label: String,
+    pin: SecretBytes,
+    logged_in: Mutex<bool>,
+    objects: RwLock<Vec<TokenObject>>,
+}
+
+impl SoftToken {
+    /// An empty token, logged out, with user PIN `pin`.
+    pub fn new(label: impl Into<String>, pin: &str) -> Self {
+        SoftToken {
+            label: label.into(),
+            pin: SecretBytes::from(pin),
+            logged_in: Mutex::new(false),
+            objects: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Opens the token for use, like `C_Login` with the user PIN.
+    pub fn login(&self, pin: &str) -> Result<(), ProviderError> {
+        if SecretBytes::from(pin) != self.pin {
+            return Err(ProviderError::IncorrectPin);
+        }
+        *self.logged_in.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = true;
+        Ok(())
+    }
+
+    /// Closes the token, like `C_Logout`.
+    pub fn logout(&self) {
+        *self.logged_in.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = false;
+    }
+
+    fn check_login(&self) -> Result<(), ProviderError> {
+        if *self.logged_in.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) {
+            Ok(())
+        } else {
+            Err(ProviderError::NotLoggedIn)
+        }
+    }
+
+    /// Generates a random 256-bit key labelled `label` on the token, like
+    /// `C_GenerateKey` of a sensitive, non-extractable secret key.
+    pub fn generate_key(&self, label: &str) -> Result<ObjectHandle, ProviderError> {
+        let mut value = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut value);
+        self.import_key(label, SecretBytes::from(value))
+    }
+
+    /// Stores an existing 256-bit key on the token, like `C_CreateObject`.
+    /// The token keeps the only copy the service should hold.
+    pub fn import_key(&self, label: &str, value: impl Into<SecretBytes>) -> Result<ObjectHandle, ProviderError> {
+        self.check_login()?;
+        let value = value.into();
+        if value.len() != 32 {
+            return Err(ProviderError::Failed(format!("key {} must be 32 bytes", label)));
+        }
+        let mut objects = self.objects.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if objects.iter().any(|object| object.label == label) {
+            return Err(ProviderError::Failed(format!("the token already holds a key {}", label)));
+        }
+        let handle = ObjectHandle(objects.len() as u64 + 1);
+        objects.push(TokenObject { handle, label: label.to_string(), value });
+        Ok(handle)
+    }
+
+    /// The handle of the key labelled `label`, like `C_FindObjects`.
+    pub fn find(&self, label: &str) -> Result<ObjectHandle, ProviderError> {
+        self.check_login()?;
+        self.with_object(label, |object| Ok(object.handle))
+    }
+
+    /// Destroys the key labelled `label`, like `C_DestroyObject`.
+    pub fn destroy(&self, label: &str) -> Result<(), ProviderError> {
+        self.check_login()?;
+        let mut objects = self.objects.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let before = objects.len();
+        objects.retain(|object| object.label != label);
+        if objects.len() == before {
+            return Err(ProviderError::UnknownKey(label.to_string()));
+        }
+        Ok(())
+    }
+
+    fn with_object<T>(&self, label: &str, f: impl FnOnce(&TokenObject) -> Result<T, ProviderError>) -> Result<T, ProviderError> {
+        let objects = self.objects.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let object = objects
+            .iter()
+            .find(|object| object.label == label)
+            .ok_or_else(|| ProviderError::UnknownKey(label.to_string()))?;
+        f(object)
+    }
+}
+
+impl KeyProvider for SoftToken {
+    fn name(&self) -> &str {
+        "soft-token"
+    }
+
+    fn has_key(&self, kid: &str) -> bool {
+        self.find(kid).is_ok()
+    }
+
+    fn wrap(&self, kid: &str, plaintext: &[u8]) -> Result<Vec<u8>, ProviderError> {
+        self.check_login()?;
+        self.with_object(kid, |object| seal(&object.value, kid, plaintext))
+    }
+
+    fn unwrap(&self, kid: &str, wrapped: &[u8]) -> Result<SecretBytes, ProviderError> {
+        self.check_login()?;
+        self.with_object(kid, |object| open(&object.value, kid, wrapped))
+    }
+
+    fn sign(&self, kid: &str, data: &[u8]) -> Result<Vec<u8>, ProviderError> {
+        self.check_login()?;
+        self.with_object(kid, |object| mac(&object.value, data))
+    }
+}
@@ -0,0 +1,272 @@
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Once};
+use zeroize::{Zeroize, Zeroizing};
+
+use super::encoding::decode_signature;
+
+/// Whether new secrets are locked into memory, see `lock_secret_memory`.
+static LOCK_MEMORY: AtomicBool = AtomicBool::new(false);
+
+/// Locks the memory of every secret created from now on, so that it is never
+/// swapped to disk, and on Linux excludes the process from core dumps.
+///
+/// Call it at startup, before keys are loaded. Locking is best effort: when
+/// the `RLIMIT_MEMLOCK` limit is reached, secrets stay unlocked and a warning
+/// is logged once.
+pub fn lock_secret_memory() {
+    LOCK_MEMORY.store(true, Ordering::SeqCst);
+    #[cfg(target_os = "linux")]
+    {
+        // SAFETY: PR_SET_DUMPABLE takes an integer argument and touches no memory
+        if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0) } != 0 {
+            log::warn!("Cannot exclude the process from core dumps: {}", std::io::Error::last_os_error());
+        }
+    }
+}
+
+/// Page size assumed for locked secrets; a secret never shares a page of
+/// this size with other data, so unlocking it unlocks nothing else.
+const PAGE: usize = 4096;
+
+#[repr(C, align(4096))]
+struct Page([u8; PAGE]);
+
+enum Storage {
+    Plain(Box<[u8]>),
+    /// Whole pages, locked with `mlock` when `locked` is set.
+    Paged { pages: Box<[Page]>, len: usize, locked: bool },
+}
+
+struct Buffer(Storage);
+
+impl Buffer {
+    fn new(bytes: &[u8]) -> Self {
+        if !LOCK_MEMORY.load(Ordering::Relaxed) {
+            return Buffer(Storage::Plain(bytes.into()));
+        }
+        let mut pages: Box<[Page]> = (0..bytes.len().div_ceil(PAGE).max(1)).map(|_| Page([0; PAGE])).collect();
+        let locked = lock(&pages);
+        for (page, chunk) in pages.iter_mut().zip(bytes.chunks(PAGE)) {
+            page.0[..chunk.len()].copy_from_slice(chunk);
+        }
+        Buffer(Storage::Paged { pages, len: bytes.len(), locked })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match &self.0 {
+            Storage::Plain(bytes) => bytes,
+            Storage::Paged { pages, len, .. } => {
+                // SAFETY: the pages are contiguous, `repr(C)` arrays of bytes
+                // without padding, and `len` never exceeds their total size
+                unsafe { std::slice::from_raw_parts(pages.as_ptr().cast::<u8>(), *len) }
+            }
+        }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        match &mut self.0 {
+            Storage::Plain(bytes) => bytes.zeroize(),
+            Storage::Paged { pages, locked, .. } => {
+                for page in pages.iter_mut() {
+                    page.0.zeroize();
+                }
+                if *locked {
+                    unlock(pages);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn lock(pages: &[Page]) -> bool {
+    static WARNED: Once = Once::new();
+    // SAFETY: the range is a live allocation owned by the caller
+    let result = unsafe { libc::mlock(pages.as_ptr().cast(), std::mem::size_of_val(pages)) };
+    if result != 0 {
+        let error = std::io::Error::last_os_error();
+        WARNED.call_once(|| log::warn!("Cannot lock key material into memory: {}", error));
+    }
+    result == 0
+}
+
+#[cfg(unix)]
+fn unlock(pages: &[Page]) {
+    // SAFETY: the range is a live allocation, locked by `lock`
+    unsafe { libc::munlock(pages.as_ptr().cast(), std::mem::size_of_val(pages)) };
+}
+
+#[cfg(not(unix))]
+fn lock(_: &[Page]) -> bool {
+    static WARNED: Once = Once::new();
+    WARNED.call_once(|| log::warn!("Locking key material into memory is not supported on this platform"));
+    false
+}
+
+#[cfg(not(unix))]
+fn unlock(_: &[Page]) {}
+
+/// Key material: wiped when the last clone is dropped, and never printed.
+///
+/// Clones share the same buffer, so a key held by several keyrings exists
+/// only once in memory. `Debug` shows the length only. Equality runs in
+/// constant time.
+#[derive(Clone)]
+pub struct SecretBytes(Arc<Buffer>);
+
+impl SecretBytes {
+    /// Copies `bytes` into a new secret; wiping the source is up to the caller.
+    pub fn new(bytes: &[u8]) -> Self {
+        SecretBytes(Arc::new(Buffer::new(bytes)))
+    }
+
+    /// The key material.
+    pub fn expose(&self) -> &[u8] {
+        self.0.bytes()
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.expose()
+    }
+}
+
+impl AsRef<[u8]> for SecretBytes {
+    fn as_ref(&self) -> &[u8] {
+        self.expose()
+    }
+}
+
+impl From<Vec<u8>> for SecretBytes {
+    /// Takes over `bytes`, wiping the vector once copied.
+    fn from(mut bytes: Vec<u8>) -> Self {
+        let secret = SecretBytes::new(&bytes);
+        bytes.zeroize();
+        secret
+    }
+}
+
+impl From<String> for SecretBytes {
+    /// Takes over the UTF-8 bytes of `text`, wiping the string once copied.
+    fn from(text: String) -> Self {
+        SecretBytes::from(text.into_bytes())
+    }
+}
+
+impl From<&str> for SecretBytes {
+    fn from(text: &str) -> Self {
+        SecretBytes::new(text.as_bytes())
+    }
+}
+
+impl From<&[u8]> for SecretBytes {
+    fn from(bytes: &[u8]) -> Self {
+        SecretBytes::new(bytes)
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for SecretBytes {
+    /// Takes over `bytes`, wiping the array once copied.
+    fn from(mut bytes: [u8; N]) -> Self {
+        let secret = SecretBytes::new(&bytes);
+        bytes.zeroize();
+        secret
+    }
+}
+
+impl<const N: usize> From<&[u8; N]> for SecretBytes {
+    fn from(bytes: &[u8; N]) -> Self {
+        SecretBytes::new(bytes)
+    }
+}
+
+impl PartialEq for SecretBytes {
+    fn eq(&self, other: &Self) -> bool {
+        let (a, b) = (self.expose(), other.expose());
+        a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+    }
+}
+
+impl Eq for SecretBytes {}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretBytes([REDACTED; {}])", self.expose().len())
+    }
+}
+
+/// Shortest symmetric secret accepted from configuration, in bytes: the
+/// output size of SHA-256, as RFC 2104 recommends for HMAC keys.
+pub const MIN_SECRET_BYTES: usize = 32;
+
+/// Least estimated entropy of a secret from configuration, in bits.
+pub const MIN_SECRET_ENTROPY_BITS: f64 = 96.0;
+
+/// Rejects secrets that are too short or obviously weak, such as a
+/// passphrase, a repeated word or a run of identical bytes.
+///
+/// A secret made only of printable ASCII is text, and must be the hex or
+/// Base64 encoding of random bytes: a passphrase, even a long one, is
+/// refused, as is Base64-decodable text without digits or mixed case. The
+/// checks then apply to the decoded bytes, which must number at least
+/// `MIN_SECRET_BYTES`; the key itself is used as given.
+///
+/// The entropy estimate is the length times the Shannon entropy of the byte
+/// frequencies. It cannot prove a secret random, only catch poor ones: a
+/// random 32-byte key scores well above the minimum. Errors name the key,
+/// never the secret.
+pub fn check_secret_strength(kid: &str, secret: &[u8]) -> Result<(), String> {
+    let decoded;
+    let secret = if secret.iter().all(|byte| byte.is_ascii_graphic() || *byte == b' ') {
+        let text = std::str::from_utf8(secret).unwrap_or_default();
+        decoded = Zeroizing::new(decode_signature(text).filter(|_| !is_words(text)).ok_or_else(|| {
+            format!("Key {} is text: give random bytes in hex or Base64, e.g. from `openssl rand -hex 32`", kid)
+        })?);
+        &decoded[..]
+    } else {
+        secret
+    };
+    if secret.len() < MIN_SECRET_BYTES {
+        return Err(format!(
+            "Key {} is too short: {} bytes, at least {} required",
+            kid,
+            secret.len(),
+            MIN_SECRET_BYTES
+        ));
+    }
+    let mut counts = [0usize; 256];
+    for byte in secret {
+        counts[*byte as usize] += 1;
+    }
+    let len = secret.len() as f64;
+    let entropy: f64 = counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / len;
+            -p * p.log2() * len
+        })
+        .sum();
+    if entropy < MIN_SECRET_ENTROPY_BITS {
+        return Err(format!(
+            "Key {} is too predictable: about {:.0} bits of entropy, at least {:.0} required; generate it randomly",
+            kid, entropy, MIN_SECRET_ENTROPY_BITS
+        ));
+    }
+    Ok(())
+}
+
+/// Whether `text` reads like words rather than an encoding of random bytes:
+/// no digits, and letters of a single case. Random hex or Base64 of 32 bytes
+/// is practically never so.
+fn is_words(text: &str) -> bool {
+    let has = |class: fn(&u8) -> bool| text.as_bytes().iter().any(class);
+    !has(u8::is_ascii_digit) && (!has(u8::is_ascii_uppercase) || !has(u8::is_ascii_lowercase))
+}
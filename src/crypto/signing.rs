@@ -1,10 +1,16 @@
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use super::encoding::decode_signature;
+use super::claims::{signing_input, SignatureClaims};
 
 // Create alias for HMAC-SHA256
 type HmacSha256 = Hmac<Sha256>;
 
+/// Prefix of the bytes signed for a raw body, so that a raw signature never
+/// passes for a JSON, claims, Merkle or HTTP message signature.
+const RAW_DOMAIN: &[u8] = b"riot.raw.v1.";
+
 /// Helper function to create a new instance
 pub fn create_signing_instance(secret_key: &[u8]) -> Result<HmacSha256, String> {
     HmacSha256::new_from_slice(secret_key)
@@ -19,6 +25,25 @@ pub fn compute(data: &[u8], secret_key: &[u8]) -> Result<String, String> {
     Ok(BASE64.encode(&result.into_bytes()))
 }
 
+/// Signs a raw body byte for byte, behind the raw domain prefix.
+pub fn sign_raw_bytes(body: &[u8], secret_key: &[u8]) -> Result<String, String> {
+    let mut instance = create_signing_instance(secret_key)?;
+    instance.update(RAW_DOMAIN);
+    instance.update(body);
+    Ok(BASE64.encode(instance.finalize().into_bytes()))
+}
+
+/// Verifies a signature produced by `sign_raw_bytes` in constant time.
+pub fn verify_raw_bytes(body: &[u8], signature: &str, secret_key: &[u8]) -> Result<bool, String> {
+    let Some(signature_bytes) = decode_signature(signature) else {
+        return Ok(false);
+    };
+    let mut instance = create_signing_instance(secret_key)?;
+    instance.update(RAW_DOMAIN);
+    instance.update(body);
+    Ok(instance.verify_slice(&signature_bytes).is_ok())
+}
+
 pub fn sign_data(data: &serde_json::Value, secret_key: &[u8]) -> Result<String, String> {
     // Canonicalize the JSON to ensure consistent property ordering
     let canonical = super::json::canonicalize_json(data);
@@ -30,10 +55,41 @@ pub fn sign_data(data: &serde_json::Value, secret_key: &[u8]) -> Result<String,
     compute(json_str.as_bytes(), secret_key)
 }
 
+/// Helper function to check a signature against `data` in constant time
+///
+/// The signature may be standard Base64, URL-safe Base64 or hex. Anything
+/// that does not decode is reported as an invalid signature, not an error.
+pub fn verify_bytes(data: &[u8], signature: &str, secret_key: &[u8]) -> Result<bool, String> {
+    let Some(signature_bytes) = decode_signature(signature) else {
+        return Ok(false);
+    };
+    let mut instance = create_signing_instance(secret_key)?;
+    instance.update(data);
+    Ok(instance.verify_slice(&signature_bytes).is_ok())
+}
+
 pub fn verify_signature(data: &serde_json::Value, signature: &str, secret_key: &[u8]) -> Result<bool, String> {
-    // Generate signature for the provided data
-    let expected_signature = sign_data(data, secret_key)?;
-    
-    // Compare the signatures
-    Ok(expected_signature == signature)
-} 
\ No newline at end of file
+    // Canonicalize the JSON exactly as sign_data does
+    let canonical = super::json::canonicalize_json(data);
+    let json_str = canonical.to_string();
+
+    // Let the MAC compare the signatures without leaking timing
+    verify_bytes(json_str.as_bytes(), signature, secret_key)
+}
+
+/// Signs `data` with `claims` bound into the signed bytes.
+///
+/// With empty claims this is the same as `sign_data`.
+pub fn sign_with_claims(data: &serde_json::Value, claims: &SignatureClaims, secret_key: &[u8]) -> Result<String, String> {
+    let input = signing_input(data, claims)?;
+    compute(&input, secret_key)
+}
+
+/// Verifies a signature produced by `sign_with_claims`.
+///
+/// Only checks the signature; time claims are checked separately with
+/// `SignatureClaims::check_time`.
+pub fn verify_with_claims(data: &serde_json::Value, claims: &SignatureClaims, signature: &str, secret_key: &[u8]) -> Result<bool, String> {
+    let input = signing_input(data, claims)?;
+    verify_bytes(&input, signature, secret_key)
+}
@@ -0,0 +1,93 @@
+use hkdf::Hkdf;
+use hmac::Mac;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use super::provider::{KeyProvider, ProviderError};
+use super::signing::create_signing_instance;
+
+/// What a tenant key is derived for. Each use has its own label, so a
+/// tenant's signing and encryption keys are independent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TenantKeyUse {
+    Signing,
+    Encryption,
+}
+
+impl TenantKeyUse {
+    /// The HKDF salt of version 1 of the derivation.
+    fn salt(&self) -> &'static [u8] {
+        match self {
+            TenantKeyUse::Signing => b"riot/tenant-signing/v1",
+            TenantKeyUse::Encryption => b"riot/tenant-encryption/v1",
+        }
+    }
+
+    /// The message HMAC'd under the master in version 2 of the derivation.
+    fn label(&self) -> &'static [u8] {
+        match self {
+            TenantKeyUse::Signing => b"riot/tenant-signing/v2",
+            TenantKeyUse::Encryption => b"riot/tenant-encryption/v2",
+        }
+    }
+}
+
+/// Longest tenant id accepted.
+pub const MAX_TENANT_ID_LEN: usize = 64;
+
+/// Checks that `tenant` is 1 to 64 ASCII letters, digits, `.`, `_` or `-`.
+pub fn validate_tenant_id(tenant: &str) -> Result<(), String> {
+    if tenant.is_empty() || tenant.len() > MAX_TENANT_ID_LEN {
+        return Err(format!("Tenant id must be 1 to {} characters", MAX_TENANT_ID_LEN));
+    }
+    if !tenant.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-')) {
+        return Err("Tenant id may only contain letters, digits, '.', '_' and '-'".to_string());
+    }
+    Ok(())
+}
+
+/// Derives the 256-bit key of `tenant` from `master` with HKDF-SHA256
+/// (RFC 5869), the tenant id as info. This is version 1 of the derivation,
+/// used for masters the service holds.
+pub fn derive_tenant_key(master: &[u8], tenant: &str, key_use: TenantKeyUse) -> Zeroizing<[u8; 32]> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(Some(key_use.salt()), master)
+        .expand(tenant.as_bytes(), key.as_mut_slice())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    key
+}
+
+/// Derives the key of `tenant` with version 2 of the derivation, used for
+/// masters held by a `KeyProvider`: the HKDF-SHA256 pseudorandom key is the
+/// HMAC-SHA256 of the use's label under `master`, which a provider computes
+/// without releasing the master, see `derive_provider_tenant_key`.
+///
+/// The two versions give different keys, so moving a master into or out of
+/// a provider changes the keys of every tenant.
+pub fn derive_tenant_key_v2(master: &[u8], tenant: &str, key_use: TenantKeyUse) -> Zeroizing<[u8; 32]> {
+    let mut instance = create_signing_instance(master).expect("HMAC accepts keys of any length");
+    instance.update(key_use.label());
+    let prk = Zeroizing::new(instance.finalize().into_bytes().to_vec());
+    expand_tenant_key(&prk, tenant).expect("an HMAC-SHA256 tag is a valid HKDF-SHA256 PRK")
+}
+
+/// Derives the key of `tenant` like `derive_tenant_key_v2`, from the master
+/// key `kid` held by `provider`.
+pub fn derive_provider_tenant_key(
+    provider: &dyn KeyProvider,
+    kid: &str,
+    tenant: &str,
+    key_use: TenantKeyUse,
+) -> Result<Zeroizing<[u8; 32]>, ProviderError> {
+    let prk = Zeroizing::new(provider.sign(kid, key_use.label())?);
+    expand_tenant_key(&prk, tenant)
+}
+
+fn expand_tenant_key(prk: &[u8], tenant: &str) -> Result<Zeroizing<[u8; 32]>, ProviderError> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::from_prk(prk)
+        .map_err(|_| ProviderError::Failed("the key provider returned a short tag".to_string()))?
+        .expand(tenant.as_bytes(), key.as_mut_slice())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    Ok(key)
+}
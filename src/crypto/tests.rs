@@ -150,4 +150,796 @@ fn test_sign_verify_nested() {
     // Test verification
     let is_valid = verify_signature(&input, &signature, &key).unwrap();
     assert!(is_valid);
-} 
\ No newline at end of file
+} 
+#[test]
+fn test_parse_json_rejects_nested_duplicate_keys() {
+    let body = br#"{"user": {"name": "John", "name": "Jane"}, "age": 30}"#;
+    let result = parse_json(body, DuplicateKeyPolicy::Reject);
+    assert!(result.is_err());
+    let error = result.unwrap_err();
+    assert!(error.contains("duplicate key 'name'"));
+    assert!(error.contains("/user"));
+
+    // Duplicates inside objects nested in arrays are found as well
+    let body = br#"{"items": [{"id": 1}, {"id": 2, "id": 3}]}"#;
+    let error = parse_json(body, DuplicateKeyPolicy::Reject).unwrap_err();
+    assert!(error.contains("/items/1"));
+}
+
+#[test]
+fn test_parse_json_last_wins() {
+    let body = br#"{"amount": 10, "amount": 1000}"#;
+    let value = parse_json(body, DuplicateKeyPolicy::LastWins).unwrap();
+    assert_eq!(value, json!({"amount": 1000}));
+
+    // Documents without duplicates parse identically under both policies
+    let body = br#"{"b": [1, 2.5, "x", null, true], "a": {"c": -3}}"#;
+    assert_eq!(
+        parse_json(body, DuplicateKeyPolicy::Reject).unwrap(),
+        parse_json(body, DuplicateKeyPolicy::LastWins).unwrap()
+    );
+    assert!(parse_json(b"{\"a\": 1} trailing", DuplicateKeyPolicy::Reject).is_err());
+}
+
+#[test]
+fn test_verify_accepts_equivalent_encodings() {
+    use base64::{Engine as _, engine::general_purpose::{STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD}};
+
+    let input = json!({"message": "Hello World"});
+    let key = get_test_secret_key();
+    let signature = sign_data(&input, &key).unwrap();
+    let raw = decode(&signature).unwrap();
+
+    for encoded in [
+        STANDARD_NO_PAD.encode(&raw),
+        URL_SAFE.encode(&raw),
+        URL_SAFE_NO_PAD.encode(&raw),
+        hex::encode(&raw),
+        hex::encode_upper(&raw),
+    ] {
+        assert!(verify_signature(&input, &encoded, &key).unwrap(), "rejected {}", encoded);
+    }
+
+    // Truncated or altered signatures still fail
+    assert!(!verify_signature(&input, &hex::encode(&raw[..16]), &key).unwrap());
+    let mut altered = raw.clone();
+    altered[0] ^= 1;
+    assert!(!verify_signature(&input, &encode(&altered), &key).unwrap());
+    assert_eq!(decode_signature("not base64 at all!"), None);
+}
+
+#[test]
+fn test_keyring_validation_and_lookup() {
+    let keyring = Keyring::new(vec![
+        KeyEntry::new("k3", "third-secret", KeyState::VerifyOnly),
+        KeyEntry::new("k2", "second-secret", KeyState::Active),
+        KeyEntry::new("k1", "first-secret", KeyState::Retired),
+    ]).unwrap();
+    assert_eq!(keyring.active().kid, "k2");
+
+    // Active key first, retired keys excluded
+    let kids: Vec<&str> = keyring.verification_keys(None).unwrap().iter().map(|k| k.kid.as_str()).collect();
+    assert_eq!(kids, vec!["k2", "k3"]);
+    assert_eq!(keyring.verification_keys(Some("k3")).unwrap().len(), 1);
+    assert!(keyring.verification_keys(Some("k1")).is_err());
+    assert!(keyring.verification_keys(Some("nope")).is_err());
+
+    // Invalid keyrings
+    assert!(Keyring::new(vec![KeyEntry::new("a", "s", KeyState::VerifyOnly)]).is_err());
+    assert!(Keyring::new(vec![
+        KeyEntry::new("a", "s", KeyState::Active),
+        KeyEntry::new("a", "t", KeyState::VerifyOnly),
+    ]).is_err());
+    assert_eq!(Keyring::single("s").active().kid, DEFAULT_KEY_ID);
+
+    // Secrets are raw bytes unless prefixed with hex: or base64:
+    let vars = |spec: &'static str| move |name: &str| (name == "HMAC_KEYS").then(|| spec.to_string());
+    let raw = Keyring::from_vars(vars("a:90cc6becdb4eb49553c70f6fb2e25adbe5746a9eca53a6ae180c904076a45367")).unwrap();
+    let hex = Keyring::from_vars(vars("a:hex:90cc6becdb4eb49553c70f6fb2e25adbe5746a9eca53a6ae180c904076a45367")).unwrap();
+    let base64 = Keyring::from_vars(vars("a:base64:U+/PVTHxSJXKVrRJJkAHa9v0NIfetTBuWVsuY58wcMc=")).unwrap();
+    assert_eq!(raw.active().secret.len(), 64);
+    assert_eq!(hex.active().secret.len(), 32);
+    assert_eq!(base64.active().secret.len(), 32);
+    assert!(Keyring::from_vars(vars("a:hex:not hex")).unwrap_err().contains("not hex"));
+    assert!(Keyring::from_vars(vars("a:base64:!!")).unwrap_err().contains("not Base64"));
+
+    // The same value is the same key through HMAC_SECRET_KEY and HMAC_KEYS
+    for value in [
+        "90cc6becdb4eb49553c70f6fb2e25adbe5746a9eca53a6ae180c904076a45367",
+        "hex:90cc6becdb4eb49553c70f6fb2e25adbe5746a9eca53a6ae180c904076a45367",
+        "base64:U+/PVTHxSJXKVrRJJkAHa9v0NIfetTBuWVsuY58wcMc=",
+    ] {
+        let single = Keyring::from_vars(|name| (name == "HMAC_SECRET_KEY").then(|| value.to_string())).unwrap();
+        let list = Keyring::from_vars(|name| (name == "HMAC_KEYS").then(|| format!("default:{}", value))).unwrap();
+        let signature = compute(b"payload", &single.active().secret).unwrap();
+        assert!(verify_bytes(b"payload", &signature, &list.active().secret).unwrap(), "{}", value);
+    }
+    assert!(Keyring::from_vars(vars("a:90cc6becdb4eb49553c70f6fb2e25adbe5746a9eca53a6ae180c904076a45367,b")).unwrap_err().contains("entry 2"));
+    assert!(Keyring::from_vars(vars(":90cc6becdb4eb49553c70f6fb2e25adbe5746a9eca53a6ae180c904076a45367")).is_err());
+}
+
+#[test]
+fn test_claims_are_bound_and_checked() {
+    let input = json!({"message": "Hello World"});
+    let key = get_test_secret_key();
+    let claims = SignatureClaims { iat: Some(1000), exp: Some(1300), ..Default::default() };
+
+    // Empty claims keep the legacy signature
+    assert_eq!(
+        sign_with_claims(&input, &SignatureClaims::default(), &key).unwrap(),
+        sign_data(&input, &key).unwrap()
+    );
+
+    let signature = sign_with_claims(&input, &claims, &key).unwrap();
+    assert_ne!(signature, sign_data(&input, &key).unwrap());
+    assert!(verify_with_claims(&input, &claims, &signature, &key).unwrap());
+    let extended = SignatureClaims { exp: Some(9999), ..claims.clone() };
+    assert!(!verify_with_claims(&input, &extended, &signature, &key).unwrap());
+
+    // Clock skew is applied on both bounds
+    assert_eq!(claims.check_time(1299, 0), Ok(()));
+    assert_eq!(claims.check_time(1300, 0), Err(ClaimsError::Expired));
+    assert_eq!(claims.check_time(1330, 60), Ok(()));
+    let future = SignatureClaims { nbf: Some(2000), ..Default::default() };
+    assert_eq!(future.check_time(1900, 60), Err(ClaimsError::NotYetValid));
+    assert_eq!(future.check_time(1950, 60), Ok(()));
+}
+
+#[test]
+fn test_jws_rfc8037_ed25519_vector() {
+    use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+
+    // RFC 8037 Appendix A.4
+    let seed = URL_SAFE_NO_PAD.decode("nWGxne_9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A").unwrap();
+    let key = KeyEntry::for_algorithm("ed", KeyAlgorithm::EdDsa, seed, KeyState::Active);
+    let input = b"eyJhbGciOiJFZERTQSJ9.RXhhbXBsZSBvZiBFZDI1NTE5IHNpZ25pbmc";
+    let expected = URL_SAFE_NO_PAD
+        .decode("hgyY0il_MGCjP0JzlnLWG1PPOt7-09PGcvMg3AIbQR6dWbhijcNR4ki4iylGjg5BhVsPt9g7sVvpAr_MuM0KAg")
+        .unwrap();
+    assert_eq!(sign_with_key(&key, input).unwrap(), expected);
+    assert!(verify_with_key(&key, input, &expected).unwrap());
+}
+
+#[test]
+fn test_public_jwks() {
+    use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+
+    // RFC 8037 Appendix A.2
+    let seed = URL_SAFE_NO_PAD.decode("nWGxne_9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A").unwrap();
+    let key = KeyEntry::for_algorithm("ed", KeyAlgorithm::EdDsa, seed, KeyState::Active);
+    assert_eq!(public_jwk(&key).unwrap().unwrap(), json!({
+        "kty": "OKP", "crv": "Ed25519", "x": "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo",
+        "kid": "ed", "alg": "EdDSA", "use": "sig"
+    }));
+
+    // A consumer can verify ES256 signatures with the published point alone
+    let key = KeyEntry::for_algorithm("ec", KeyAlgorithm::Es256, [7u8; 32], KeyState::VerifyOnly);
+    let jwk = public_jwk(&key).unwrap().unwrap();
+    assert_eq!((&jwk["kty"], &jwk["crv"], &jwk["alg"], &jwk["use"]), (&json!("EC"), &json!("P-256"), &json!("ES256"), &json!("sig")));
+    assert!(jwk.get("d").is_none());
+    let mut point = vec![0x04];
+    point.extend(URL_SAFE_NO_PAD.decode(jwk["x"].as_str().unwrap()).unwrap());
+    point.extend(URL_SAFE_NO_PAD.decode(jwk["y"].as_str().unwrap()).unwrap());
+    let verifying_key = p256::ecdsa::VerifyingKey::from_sec1_bytes(&point).unwrap();
+    let signature = p256::ecdsa::Signature::from_slice(&sign_with_key(&key, b"payload").unwrap()).unwrap();
+    use p256::ecdsa::signature::Verifier as _;
+    assert!(verifying_key.verify(b"payload", &signature).is_ok());
+
+    let hmac = KeyEntry::new("hmac", get_test_secret_key(), KeyState::Active);
+    assert_eq!(public_jwk(&hmac).unwrap(), None);
+}
+
+#[test]
+fn test_jws_round_trips() {
+    let data = json!({"b": 2, "a": [1, {"z": true, "y": null}]});
+    let keyring = Keyring::new(vec![
+        KeyEntry::new("hmac", get_test_secret_key(), KeyState::Active),
+        KeyEntry::for_algorithm("p256", KeyAlgorithm::Es256, [7u8; 32], KeyState::Active),
+        KeyEntry::for_algorithm("ed", KeyAlgorithm::EdDsa, [9u8; 32], KeyState::Active),
+    ]).unwrap();
+
+    for key in keyring.keys() {
+        let compact = sign_jws(&data, key, false).unwrap();
+        let verified = verify_jws(&compact, None, &keyring, DuplicateKeyPolicy::Reject).unwrap().unwrap();
+        assert_eq!(verified.kid, key.kid);
+        assert_eq!(verified.payload, data);
+
+        let detached = sign_jws(&data, key, true).unwrap();
+        assert!(detached.contains(".."));
+        let reordered = json!({"a": [1, {"y": null, "z": true}], "b": 2});
+        assert!(verify_jws(&detached, Some(&reordered), &keyring, DuplicateKeyPolicy::Reject).unwrap().is_some());
+        assert!(verify_jws(&detached, Some(&json!({"b": 3})), &keyring, DuplicateKeyPolicy::Reject).unwrap().is_none());
+        assert!(verify_jws(&detached, None, &keyring, DuplicateKeyPolicy::Reject).is_err());
+    }
+
+    // A token claiming HS256 for an ES256 key id is refused
+    let es_key = keyring.get("p256").unwrap();
+    let forged = KeyEntry::new("p256", es_key.secret.clone(), KeyState::Active);
+    let token = sign_jws(&data, &forged, false).unwrap();
+    assert!(verify_jws(&token, None, &keyring, DuplicateKeyPolicy::Reject).is_err());
+
+    // Only an explicitly supported, non-empty crit is accepted
+    let hmac = keyring.get("hmac").unwrap();
+    let with_header = |header: serde_json::Value| {
+        use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+        let input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(canonicalize_json(&data).to_string())
+        );
+        let signature = sign_with_key(hmac, input.as_bytes()).unwrap();
+        format!("{}.{}", input, URL_SAFE_NO_PAD.encode(signature))
+    };
+    let plain = with_header(json!({"alg": "HS256", "kid": "hmac"}));
+    assert!(verify_jws(&plain, None, &keyring, DuplicateKeyPolicy::Reject).unwrap().is_some());
+    for header in [
+        json!({"alg": "HS256", "kid": "hmac", "b64": true, "crit": ["b64", "exp"], "exp": 1}),
+        json!({"alg": "HS256", "kid": "hmac", "crit": ["exp"], "exp": 1}),
+        json!({"alg": "HS256", "kid": "hmac", "crit": []}),
+        json!({"alg": "HS256", "kid": "hmac", "crit": ["b64"]}),
+    ] {
+        let token = with_header(header.clone());
+        assert!(verify_jws(&token, None, &keyring, DuplicateKeyPolicy::Reject).is_err(), "{}", header);
+    }
+}
+
+#[test]
+fn test_merkle_tree_proofs() {
+    let key = get_test_secret_key();
+    let doc = json!({
+        "name": "export-7",
+        "rows": [{"id": 1, "ok": true}, {"id": 2, "ok": false}, []],
+        "meta": {}
+    });
+    let tree = MerkleTree::build(&doc, &key).unwrap();
+    assert_eq!(tree.leaf_count(), 7);
+
+    // Property order does not change the root
+    let reordered = json!({
+        "meta": {},
+        "rows": [{"ok": true, "id": 1}, {"ok": false, "id": 2}, []],
+        "name": "export-7"
+    });
+    assert_eq!(MerkleTree::build(&reordered, &key).unwrap().root(), tree.root());
+
+    // Every leaf proves against the root, whatever its position
+    for proof in tree.prove("").unwrap() {
+        assert!(verify_inclusion(&proof, &tree.root(), tree.leaf_count()), "{}", proof.pointer);
+    }
+    let row = tree.prove("/rows/1").unwrap();
+    assert_eq!(row.len(), 2);
+    assert_eq!(row[0].pointer, "/rows/1/id");
+
+    // Altered values or paths do not
+    let mut forged = row[0].clone();
+    forged.value = json!(3);
+    assert!(!verify_inclusion(&forged, &tree.root(), tree.leaf_count()));
+    let mut moved = row[0].clone();
+    moved.pointer = "/rows/0/id".to_string();
+    assert!(!verify_inclusion(&moved, &tree.root(), tree.leaf_count()));
+
+    // The path must have the shape the index takes in a tree of that size
+    let mut renumbered = row[0].clone();
+    renumbered.index ^= 1;
+    assert!(!verify_inclusion(&renumbered, &tree.root(), tree.leaf_count()));
+    renumbered.index = tree.leaf_count();
+    assert!(!verify_inclusion(&renumbered, &tree.root(), tree.leaf_count()));
+    assert!(!verify_inclusion(&row[0], &tree.root(), tree.leaf_count() * 2));
+    assert!(tree.prove("/nope").is_err());
+
+    // Salts depend on the key, so the root does too
+    assert_ne!(MerkleTree::build(&doc, b"another-key").unwrap().root(), tree.root());
+}
+
+fn rfc9421_request() -> HttpRequestParts {
+    // The test request of RFC 9421 Appendix B.2
+    HttpRequestParts {
+        method: "POST".to_string(),
+        scheme: "https".to_string(),
+        authority: "example.com".to_string(),
+        path: "/foo".to_string(),
+        query: Some("param=Value&Pet=dog".to_string()),
+        headers: vec![
+            ("Host".to_string(), "example.com".to_string()),
+            ("Date".to_string(), "Tue, 20 Apr 2021 02:07:55 GMT".to_string()),
+            ("Content-Type".to_string(), "application/json".to_string()),
+            (
+                "Content-Digest".to_string(),
+                "sha-512=:WZDPaVn/7XgHaAy8pmojAkGWoRx2UFChF41A2svX+TaPm+AbwAgBWnrIiYllu7BNNyealdVLvRwEmTHWXvJwew==:".to_string(),
+            ),
+            ("Content-Length".to_string(), "18".to_string()),
+        ],
+    }
+}
+
+#[test]
+fn test_http_signature_rfc9421_vector() {
+    // RFC 9421 Appendix B.2.5, HMAC-SHA256 with a shared secret
+    let secret = decode_signature("uzvJfB4u3N0Jy4T7NZ75MDVcr8zSTInedJtkgcu46YW4XByzNJjxBdtjUkdJPBtbmHhIDi6pcl8jsasjlTMtDQ==").unwrap();
+    let keyring = Keyring::new(vec![KeyEntry::new("test-shared-secret", secret, KeyState::Active)]).unwrap();
+    let parts = rfc9421_request();
+    let input = r#"sig-b25=("date" "@authority" "content-type");created=1618884473;keyid="test-shared-secret""#;
+    let signature = "sig-b25=:pxcQw6G3AjtMBQjwo8XzkZf/bws5LelbaMk5rGIGtE8=:";
+
+    let verified = verify_message(&parts, input, signature, &keyring).unwrap();
+    assert_eq!(verified[0].label, "sig-b25");
+    assert_eq!(verified[0].kid, "test-shared-secret");
+    assert_eq!(verified[0].params.created(), Some(1618884473));
+    assert_eq!(
+        signature_base(&parts, &verified[0].params).unwrap(),
+        "\"date\": Tue, 20 Apr 2021 02:07:55 GMT\n\"@authority\": example.com\n\"content-type\": application/json\n\
+         \"@signature-params\": (\"date\" \"@authority\" \"content-type\");created=1618884473;keyid=\"test-shared-secret\""
+    );
+
+    // Signing the same parameters reproduces the vector
+    let key = keyring.get("test-shared-secret").unwrap();
+    let (signed_input, signed) = sign_message(&parts, &verified[0].params, key, "sig-b25").unwrap();
+    assert_eq!(signed_input, input);
+    assert_eq!(signed, signature);
+
+    // A changed covered header breaks the signature
+    let mut altered = parts.clone();
+    altered.headers[2].1 = "text/plain".to_string();
+    assert_eq!(verify_message(&altered, input, signature, &keyring).unwrap_err().code(), "http_signature_invalid");
+
+    // RFC 9530 digests of the request body
+    let body = br#"{"hello": "world"}"#;
+    let digest = parts.header("content-digest").unwrap();
+    assert!(check_content_digest(&digest, body).is_ok());
+    assert_eq!(content_digest(body), "sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:");
+    assert_eq!(check_content_digest(&digest, b"{}"), Err(HttpSignatureError::DigestMismatch));
+}
+
+#[test]
+fn test_http_signature_asymmetric_and_time() {
+    let seed = [7u8; 32];
+    let keyring = Keyring::new(vec![
+        KeyEntry::new("hmac", get_test_secret_key(), KeyState::Active),
+        KeyEntry::for_algorithm("ed", KeyAlgorithm::EdDsa, seed.to_vec(), KeyState::Active),
+    ])
+    .unwrap();
+    let parts = rfc9421_request();
+    let params = SignatureParams::new(vec!["@method".to_string(), "@target-uri".to_string()])
+        .with("created", ParamValue::Integer(1_000))
+        .with("expires", ParamValue::Integer(1_100))
+        .with("keyid", ParamValue::String("ed".to_string()))
+        .with("alg", ParamValue::String(http_signature_alg(KeyAlgorithm::EdDsa).to_string()));
+    let (input, signature) = sign_message(&parts, &params, keyring.get("ed").unwrap(), "sig1").unwrap();
+    assert!(input.starts_with(r#"sig1=("@method" "@target-uri");created=1000;expires=1100;keyid="ed";alg="ed25519""#));
+
+    let verified = verify_message(&parts, &input, &signature, &keyring).unwrap();
+    assert_eq!(verified[0].kid, "ed");
+    assert_eq!(signature_base(&parts, &params).unwrap().lines().nth(1), Some("\"@target-uri\": https://example.com/foo?param=Value&Pet=dog"));
+
+    // Claiming the HMAC algorithm for the Ed25519 key is refused
+    let forged = input.replace("ed25519", "hmac-sha256");
+    assert!(verify_message(&parts, &forged, &signature, &keyring).is_err());
+
+    let params = &verified[0].params;
+    assert!(params.check_time(1_050, 0, 300).is_ok());
+    assert_eq!(params.check_time(1_100, 0, 300), Err(HttpSignatureError::Expired));
+    assert_eq!(params.check_time(990, 0, 300), Err(HttpSignatureError::Expired));
+    assert!(params.check_time(990, 60, 300).is_ok());
+    assert!(SignatureParams::new(vec![]).check_time(1_000, 60, 300).is_err());
+
+    // Malformed headers are reported as such
+    for (input, signature) in [
+        ("sig1=(\"@method\"", signature.as_str()),
+        (input.as_str(), "sig2=:AAAA:"),
+        ("sig1=(\"@status\");created=1", "sig1=:AAAA:"),
+        ("sig1=(\"@method\" \"@method\");created=1", "sig1=:AAAA:"),
+    ] {
+        let error = verify_message(&parts, input, signature, &keyring).unwrap_err();
+        assert_eq!(error.code(), "http_signature_malformed", "{}", input);
+    }
+}
+
+#[test]
+fn test_webhook_provider_vectors() {
+    let headers = |pairs: Vec<(&'static str, &'static str)>| {
+        move |name: &str| pairs.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| *v)
+    };
+
+    // GitHub documentation example
+    let github = WebhookProfile::new("github", WebhookScheme::GitHub, "It's a Secret to Everybody");
+    let lookup = headers(vec![("X-Hub-Signature-256", "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17")]);
+    assert_eq!(verify_webhook(&github, &lookup, b"Hello, World!", 0), Ok(()));
+    assert_eq!(verify_webhook(&github, &lookup, b"Hello, World?", 0), Err(WebhookError::Invalid));
+
+    // Slack documentation example
+    let slack = WebhookProfile::new("slack", WebhookScheme::Slack, "8f742231b10e8888abcd99yyyzzz85a5");
+    let body = b"token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J&team_domain=testteamnow&channel_id=G8PSS9T3V&channel_name=foobar&user_id=U2CERLKJA&user_name=roadrunner&command=%2Fwebhook-collect&text=&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands%2FT1DC2JH3J%2F397700885554%2F96rGlfmibIGlgcZRskXaIFfN&trigger_id=398738663015.47445629121.803a0bc887a14d10d2c447fce8b6703c";
+    let lookup = headers(vec![
+        ("X-Slack-Request-Timestamp", "1531420618"),
+        ("X-Slack-Signature", "v0=a2114d57b48eac39b9ad189dd8316235a7b4a8d21a10bd27519666489c69b503"),
+    ]);
+    assert_eq!(verify_webhook(&slack, &lookup, body, 1531420618 + 10), Ok(()));
+    assert_eq!(verify_webhook(&slack, &lookup, body, 1531420618 + 301), Err(WebhookError::OutsideTolerance));
+
+    // Standard Webhooks specification example
+    let svix = WebhookProfile::new("svix", WebhookScheme::StandardWebhooks, "whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw");
+    let lookup = headers(vec![
+        ("webhook-id", "msg_p5jXN8AQM9LWM0D4loKWxJek"),
+        ("webhook-timestamp", "1614265330"),
+        ("webhook-signature", "v1,bm9ldHZhbGlk v1,g0hM9SsE+OTPJTGt/tmIKtSyZlE3uFJELVlNIOLJ1OE="),
+    ]);
+    assert_eq!(verify_webhook(&svix, &lookup, br#"{"test": 2432232314}"#, 1614265330), Ok(()));
+    assert_eq!(
+        sign_webhook(&svix, br#"{"test": 2432232314}"#, 1614265330, "msg_p5jXN8AQM9LWM0D4loKWxJek").unwrap(),
+        "v1,g0hM9SsE+OTPJTGt/tmIKtSyZlE3uFJELVlNIOLJ1OE="
+    );
+}
+
+#[test]
+fn test_webhook_timestamped_scheme() {
+    let mut profile = WebhookProfile::new("acme", WebhookScheme::Timestamped, "whsec_test");
+    profile.header = "Acme-Signature".to_string();
+    let body = br#"{"id":"evt_1", "amount": 10}"#;
+    let header = sign_webhook(&profile, body, 1_700_000_000, "").unwrap();
+    assert!(header.starts_with("t=1700000000,v1="));
+
+    // The raw bytes are signed: reformatting the JSON breaks the signature
+    let lookup = |name: &str| (name == "Acme-Signature").then_some(header.as_str());
+    assert_eq!(verify_webhook(&profile, lookup, body, 1_700_000_100), Ok(()));
+    assert_eq!(verify_webhook(&profile, lookup, br#"{"id":"evt_1","amount":10}"#, 1_700_000_100), Err(WebhookError::Invalid));
+    assert_eq!(verify_webhook(&profile, lookup, body, 1_699_999_000), Err(WebhookError::OutsideTolerance));
+
+    // Any of several v1 signatures may match, e.g. during secret rotation
+    let rotated = format!("t=1700000000,v1={},{}", "00".repeat(32), header.split(',').nth(1).unwrap());
+    let lookup = |_: &str| Some(rotated.as_str());
+    assert_eq!(verify_webhook(&profile, lookup, body, 1_700_000_000), Ok(()));
+
+    let missing = verify_webhook(&profile, |_: &str| None, body, 0).unwrap_err();
+    assert_eq!(missing.code(), "webhook_header_missing");
+    let malformed = verify_webhook(&profile, |_: &str| Some("v1=abcd"), body, 0).unwrap_err();
+    assert_eq!(malformed.code(), "webhook_header_malformed");
+}
+
+#[test]
+fn test_signature_set_threshold() {
+    let keyring = Keyring::new(vec![
+        KeyEntry::new("ops", get_test_secret_key(), KeyState::Active),
+        KeyEntry::new("finance", b"finance-secret".to_vec(), KeyState::VerifyOnly),
+        KeyEntry::new("old", b"old-secret".to_vec(), KeyState::Retired),
+        KeyEntry::for_algorithm("cfo", KeyAlgorithm::EdDsa, [9u8; 32].to_vec(), KeyState::Active),
+    ])
+    .unwrap();
+    let keys: Vec<&KeyEntry> = ["ops", "finance", "cfo"].iter().map(|kid| keyring.get(kid).unwrap()).collect();
+    let data = json!({"transfer": 1_000_000, "to": "ACME"});
+    let claims = SignatureClaims::default();
+    let set = sign_set(&data, &claims, &keys).unwrap();
+    assert_eq!(set.len(), 3);
+    // Members sign behind a domain prefix, so none is a plain signature
+    assert_ne!(set[0].signature, sign_data(&data, &get_test_secret_key()).unwrap());
+    let plain = SetSignature { signature: sign_data(&data, &get_test_secret_key()).unwrap(), ..set[0].clone() };
+    let signers: Vec<String> = ["ops", "finance", "cfo"].iter().map(|kid| kid.to_string()).collect();
+    let all_signers = ThresholdPolicy::for_signers(&signers, None);
+    let report = verify_set(&data, &claims, &[plain], &ThresholdPolicy::for_signers(&signers, Some(1)), &keyring).unwrap();
+    assert!(!report.valid);
+
+    let all = verify_set(&data, &claims, &set, &all_signers, &keyring).unwrap();
+    assert!(all.valid);
+    assert_eq!(all.threshold, 3);
+    assert_eq!(all.passed, vec!["ops", "finance", "cfo"]);
+
+    // 2 of 3 survives one bad signature, 3 of 3 does not
+    let mut tampered = set.clone();
+    tampered[1].signature = set[0].signature.clone();
+    let two_of_three = ThresholdPolicy::for_signers(&signers, Some(2));
+    let report = verify_set(&data, &claims, &tampered, &two_of_three, &keyring).unwrap();
+    assert!(report.valid);
+    assert_eq!(report.failed, vec![FailedSignature { kid: "finance".to_string(), reason: "invalid signature".to_string() }]);
+    assert!(!verify_set(&data, &claims, &tampered, &all_signers, &keyring).unwrap().valid);
+
+    // Repeating one key does not reach the threshold
+    let repeated = vec![set[0].clone(), set[0].clone()];
+    let report = verify_set(&data, &claims, &repeated, &two_of_three, &keyring).unwrap();
+    assert!(!report.valid);
+    assert_eq!(report.failed[0].reason, "duplicate key");
+
+    // Only keys named by the policy count, and retired keys never do
+    let restricted = two_of_three.tightened_by(&ThresholdPolicy { threshold: None, kids: Some(vec!["ops".to_string(), "cfo".to_string()]) });
+    let report = verify_set(&data, &claims, &set, &restricted, &keyring).unwrap();
+    assert_eq!(report.passed, vec!["ops", "cfo"]);
+    let retired = sign_set(&data, &claims, &[keyring.get("old").unwrap()]).unwrap();
+    let report = verify_set(&data, &claims, &retired, &ThresholdPolicy::for_signers(&["old".to_string()], None), &keyring).unwrap();
+    assert_eq!(report.failed[0].reason, "retired key");
+
+    // A client policy can raise the threshold but not lower it or add keys
+    let loosened = two_of_three.tightened_by(&ThresholdPolicy { threshold: Some(1), kids: Some(vec!["ops".to_string(), "root".to_string()]) });
+    assert_eq!(loosened, ThresholdPolicy { threshold: Some(2), kids: Some(vec!["ops".to_string()]) });
+    assert_eq!(two_of_three.tightened_by(&ThresholdPolicy { threshold: Some(3), kids: None }).threshold, Some(3));
+
+    // The threshold never comes from the set itself
+    assert!(verify_set(&data, &claims, &set, &ThresholdPolicy::default(), &keyring).is_err());
+    assert!(verify_set(&data, &claims, &[], &all_signers, &keyring).is_err());
+}
+
+#[test]
+fn test_deterministic_cbor_vectors() {
+    // RFC 8949 Appendix A, restricted to preferred serializations
+    let vectors: Vec<(serde_json::Value, &str)> = vec![
+        (json!(0), "00"),
+        (json!(23), "17"),
+        (json!(24), "1818"),
+        (json!(1000), "1903e8"),
+        (json!(1000000), "1a000f4240"),
+        (json!(18446744073709551615u64), "1bffffffffffffffff"),
+        (json!(-1), "20"),
+        (json!(-1000), "3903e7"),
+        (json!(0.0), "f90000"),
+        (json!(-0.0), "f98000"),
+        (json!(1.5), "f93e00"),
+        (json!(65504.0), "f97bff"),
+        (json!(5.960464477539063e-8), "f90001"),
+        (json!(0.00006103515625), "f90400"),
+        (json!(-4.0), "f9c400"),
+        (json!(100000.0), "fa47c35000"),
+        (json!(3.4028234663852886e+38), "fa7f7fffff"),
+        (json!(1.1), "fb3ff199999999999a"),
+        (json!(-4.1), "fbc010666666666666"),
+        (json!(1.0e+300), "fb7e37e43c8800759c"),
+        (json!(false), "f4"),
+        (json!(null), "f6"),
+        (json!("IETF"), "6449455446"),
+        (json!("\u{00fc}"), "62c3bc"),
+        (json!([1, [2, 3], [4, 5]]), "8301820203820405"),
+        (json!({"a": 1, "b": [2, 3]}), "a26161016162820203"),
+    ];
+    for (value, expected) in vectors {
+        let encoded = canonicalize_cbor(&value);
+        assert_eq!(hex::encode(&encoded), expected, "{}", value);
+        assert_eq!(cbor_to_json(&decode_cbor(&encoded).unwrap()).unwrap(), value);
+    }
+
+    // Map keys sort by their encoded bytes, not by the JSON order
+    let keys = CborValue::Map(vec![
+        (CborValue::Array(vec![CborValue::Unsigned(100)]), CborValue::Null),
+        (CborValue::Text("aa".to_string()), CborValue::Null),
+        (CborValue::Unsigned(100), CborValue::Null),
+        (CborValue::Bool(false), CborValue::Null),
+        (CborValue::Text("z".to_string()), CborValue::Null),
+        (CborValue::integer(-1), CborValue::Null),
+        (CborValue::Unsigned(10), CborValue::Null),
+        (CborValue::Array(vec![CborValue::integer(-1)]), CborValue::Null),
+    ]);
+    assert_eq!(
+        hex::encode(encode_cbor(&keys)),
+        "a80af61864f620f6617af6626161f6811864f68120f6f4f6"
+    );
+    assert_eq!(canonicalize_cbor(&json!({"b": 1, "a": 2})), canonicalize_cbor(&json!({"a": 2, "b": 1})));
+
+    // Malformed input is refused
+    assert!(decode_cbor(&hex::decode("9f0102ff").unwrap()).is_err()); // indefinite length
+    assert!(decode_cbor(&hex::decode("a2616101616102").unwrap()).is_err()); // duplicate key
+    assert!(decode_cbor(&hex::decode("a20101180102").unwrap()).is_err()); // duplicate key, not preferred
+
+    // A large map is checked for duplicates in linear time
+    let entries = 200_000u64;
+    let mut large = vec![0xba];
+    large.extend_from_slice(&(entries as u32).to_be_bytes());
+    for i in 0..entries {
+        large.push(0x1a);
+        large.extend_from_slice(&(i as u32).to_be_bytes());
+        large.push(0xf6);
+    }
+    assert!(matches!(decode_cbor(&large).unwrap(), CborValue::Map(map) if map.len() == entries as usize));
+    large.extend_from_slice(&[0x00, 0xf6]);
+    large[1..5].copy_from_slice(&(entries as u32 + 1).to_be_bytes());
+    assert!(decode_cbor(&large).is_err());
+    assert!(decode_cbor(&hex::decode("1903").unwrap()).is_err()); // truncated
+    assert!(decode_cbor(&hex::decode("0000").unwrap()).is_err()); // trailing bytes
+    assert!(decode_cbor(&hex::decode("5bffffffffffffffff").unwrap()).is_err()); // bogus length
+}
+
+#[test]
+fn test_cose_round_trips() {
+    let keyring = Keyring::new(vec![
+        KeyEntry::new("hmac-1", get_test_secret_key(), KeyState::Active),
+        KeyEntry::for_algorithm("p256", KeyAlgorithm::Es256, [5u8; 32].to_vec(), KeyState::Active),
+        KeyEntry::for_algorithm("ed", KeyAlgorithm::EdDsa, [6u8; 32].to_vec(), KeyState::Active),
+    ])
+    .unwrap();
+    let data = json!({"device": "th-01", "temp": 21.5, "ok": true});
+
+    for (kid, tag) in [("hmac-1", 0xd1), ("p256", 0xd2), ("ed", 0xd2)] {
+        let key = keyring.get(kid).unwrap();
+        let message = sign_cose(&data, key).unwrap();
+        // COSE_Mac0 is tag 17, COSE_Sign1 tag 18, each a 4-element array
+        assert_eq!(message[..2], [tag, 0x84], "{}", kid);
+
+        let verified = verify_cose(&message, None, &keyring).unwrap().unwrap();
+        assert_eq!(verified.kid, kid);
+        assert_eq!(verified.payload, data);
+
+        // Flipping a payload byte breaks the signature
+        let decoded = decode_cbor(&message).unwrap();
+        let CborValue::Tag(t, inner) = decoded else { panic!("tagged") };
+        let CborValue::Array(mut items) = *inner else { panic!("array") };
+        if let CborValue::Bytes(payload) = &mut items[2] {
+            let last = payload.len() - 1;
+            payload[last] ^= 1;
+        }
+        let tampered = encode_cbor(&CborValue::Tag(t, Box::new(CborValue::Array(items.clone()))));
+        assert!(verify_cose(&tampered, None, &keyring).unwrap().is_none(), "{}", kid);
+
+        // A nil payload is taken from the detached data
+        items[2] = CborValue::Null;
+        let detached = encode_cbor(&CborValue::Tag(t, Box::new(CborValue::Array(items))));
+        assert!(verify_cose(&detached, Some(&data), &keyring).unwrap().is_some());
+        assert!(verify_cose(&detached, Some(&json!({"device": "th-02"})), &keyring).unwrap().is_none());
+        assert!(verify_cose(&detached, None, &keyring).is_err());
+    }
+
+    // A COSE_Sign1 tag around an HMAC message is refused
+    let mut message = sign_cose(&data, keyring.get("hmac-1").unwrap()).unwrap();
+    message[0] = 0xd2;
+    assert!(verify_cose(&message, None, &keyring).is_err());
+}
+
+#[test]
+fn test_tenant_key_derivation() {
+    let master = b"tenant-master-secret";
+    let acme = derive_tenant_key(master, "acme", TenantKeyUse::Signing);
+    assert_eq!(acme, derive_tenant_key(master, "acme", TenantKeyUse::Signing));
+    assert_ne!(acme, derive_tenant_key(master, "globex", TenantKeyUse::Signing));
+    assert_ne!(acme, derive_tenant_key(master, "acme", TenantKeyUse::Encryption));
+    assert_ne!(acme, derive_tenant_key(b"another-master", "acme", TenantKeyUse::Signing));
+    // Version 1 is HKDF with the use's label as salt and the master as input
+    let mut expected = [0u8; 32];
+    hkdf::Hkdf::<sha2::Sha256>::new(Some(b"riot/tenant-signing/v1"), master).expand(b"acme", &mut expected).unwrap();
+    assert_eq!(*acme, expected);
+
+    assert!(validate_tenant_id("acme-eu.1_a").is_ok());
+    for invalid in ["", "a/b", "acme corp", "é", &"a".repeat(MAX_TENANT_ID_LEN + 1)] {
+        assert!(validate_tenant_id(invalid).is_err(), "{:?}", invalid);
+    }
+
+    // Values encrypted for one tenant only decrypt with its key
+    let key = derive_tenant_key(master, "acme", TenantKeyUse::Encryption);
+    let other = derive_tenant_key(master, "globex", TenantKeyUse::Encryption);
+    let input = json!({"name": "John Doe", "age": 30, "tags": ["a", "b"]});
+    let encrypted = encrypt_data_with_key(&input, &key).unwrap();
+    assert_ne!(encrypted, encrypt_data_with_key(&input, &key).unwrap(), "nonces are random");
+    assert_eq!(decrypt_data_with_key(&encrypted, &[*other, *key]).unwrap(), input);
+    assert!(decrypt_data_with_key(&encrypted, &[*other]).is_err());
+
+    // A value moved to another property no longer decrypts
+    let swapped = json!({"name": encrypted["age"], "age": encrypted["name"]});
+    assert!(decrypt_data_with_key(&swapped, &[*key]).is_err());
+    assert!(decrypt_data_with_key(&json!({"name": "plain"}), &[*key]).is_err());
+}
+
+#[test]
+fn test_secret_bytes() {
+    let entry = KeyEntry::new("k1", b"super-secret-key".to_vec(), KeyState::Active);
+    let printed = format!("{:?}", entry);
+    assert!(printed.contains("k1") && printed.contains("[REDACTED; 16]"), "{}", printed);
+    assert!(!printed.contains("super-secret-key"));
+
+    // Clones share one buffer instead of copying the material
+    let copy = entry.clone();
+    assert_eq!(copy.secret.expose().as_ptr(), entry.secret.expose().as_ptr());
+    assert_eq!(copy.secret, SecretBytes::from("super-secret-key"));
+    assert_ne!(copy.secret, SecretBytes::from("super-secret-kez"));
+    assert_ne!(copy.secret, SecretBytes::from("super-secret"));
+
+    // Locked secrets live on their own pages and read back unchanged
+    lock_secret_memory();
+    let long = vec![7u8; 5000];
+    let locked = SecretBytes::new(&long);
+    assert_eq!(locked.expose(), &long[..]);
+    assert_eq!(locked.expose().as_ptr() as usize % 4096, 0);
+    assert_eq!(compute(b"data", &locked).unwrap(), compute(b"data", &long).unwrap());
+}
+
+#[test]
+fn test_secret_strength() {
+    assert!(check_secret_strength("k", b"90cc6becdb4eb49553c70f6fb2e25adbe5746a9eca53a6ae180c904076a45367").is_ok());
+    assert!(check_secret_strength("k", b"kMx3v0Q2+8bWc1Zr7yT9aLp4sN6eHf5gJd0uVq1oXiA=").is_ok());
+    assert!(check_secret_strength("k", &hex::decode("90cc6becdb4eb49553c70f6fb2e25adbe5746a9eca53a6ae180c904076a45367").unwrap()).is_ok());
+    // Hex of 16 bytes is too short once decoded
+    let short = hex::encode([0x5au8, 0x13, 0xc7, 0x88, 0x01, 0xfe, 0x42, 0x9d, 0x6b, 0x30, 0xe4, 0x17, 0xa9, 0x5c, 0x2f, 0x86]);
+    let error = check_secret_strength("k", short.as_bytes()).unwrap_err();
+    assert!(error.contains("too short: 16 bytes") && !error.contains(&short), "{}", error);
+    // Passphrases are refused however long, Base64-decodable or not
+    for passphrase in [
+        "test-secret-key-for-the-integration-suite",
+        "test-secret-key-for-the-integration-suite-and-more",
+        "correct horse battery staple and then some more words",
+        "CORRECT_HORSE_BATTERY_STAPLE_AND_THEN_SOME_MORE_WORDS",
+    ] {
+        let error = check_secret_strength("k", passphrase.as_bytes()).unwrap_err();
+        assert!(error.contains("is text") || error.contains("too predictable"), "{}: {}", passphrase, error);
+    }
+    assert!(check_secret_strength("k", b"test-secret-key-for-the-integration-suite-and-more").unwrap_err().contains("is text"));
+    assert!(check_secret_strength("k", &[7u8; 64]).unwrap_err().contains("too predictable"));
+    assert!(check_secret_strength("k", b"passwordpasswordpasswordpassword").is_err());
+
+    // Configured keys are checked, retired ones excepted
+    let vars = |spec: &'static str| move |name: &str| (name == "HMAC_KEYS").then(|| spec.to_string());
+    assert!(Keyring::from_vars(vars("a:90cc6becdb4eb49553c70f6fb2e25adbe5746a9eca53a6ae180c904076a45367")).is_ok());
+    assert!(Keyring::from_vars(vars("a:90cc6becdb4eb49553c70f6fb2e25adbe5746a9eca53a6ae180c904076a45367,b:00ff")).is_err());
+    let retired = |name: &str| match name {
+        "HMAC_KEYS" => Some("a:90cc6becdb4eb49553c70f6fb2e25adbe5746a9eca53a6ae180c904076a45367,b:00ff".to_string()),
+        "HMAC_RETIRED_KEY_IDS" => Some("b".to_string()),
+        _ => None,
+    };
+    assert!(Keyring::from_vars(retired).is_ok());
+}
+
+#[test]
+fn test_key_providers() {
+    use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+
+    let master = [0x42u8; 32];
+    let file = FileKeyProvider::new(vec![("master-1".to_string(), SecretBytes::from(master))]).unwrap();
+    assert!(file.has_key("master-1") && !file.has_key("master-2"));
+    assert!(FileKeyProvider::new(vec![("short".to_string(), SecretBytes::from("too short"))]).is_err());
+
+    // Wrapped keys only unwrap under the same master key id
+    let wrapped = file.wrap("master-1", b"data key material").unwrap();
+    assert_ne!(wrapped, file.wrap("master-1", b"data key material").unwrap(), "nonces are random");
+    assert_eq!(file.unwrap("master-1", &wrapped).unwrap().expose(), b"data key material");
+    let mut tampered = wrapped.clone();
+    tampered[20] ^= 1;
+    assert_eq!(file.unwrap("master-1", &tampered).unwrap_err().code(), "provider_failed");
+    assert_eq!(file.unwrap("master-2", &wrapped).unwrap_err().code(), "provider_key_not_found");
+
+    // Signing is HMAC-SHA256, so a provider derives the tenant keys of
+    // version 2, which differ from those of a master held by the service
+    assert_eq!(BASE64.encode(file.sign("master-1", b"data").unwrap()), compute(b"data", &master).unwrap());
+    for key_use in [TenantKeyUse::Signing, TenantKeyUse::Encryption] {
+        let derived = derive_provider_tenant_key(&file, "master-1", "acme", key_use).unwrap();
+        assert_eq!(derived, derive_tenant_key_v2(&master, "acme", key_use));
+        assert_ne!(derived, derive_tenant_key(&master, "acme", key_use));
+    }
+
+    // The soft token needs a login and never hands out its keys
+    let token = SoftToken::new("test-token", "1234");
+    assert_eq!(token.generate_key("master").unwrap_err(), ProviderError::NotLoggedIn);
+    assert_eq!(token.login("0000").unwrap_err(), ProviderError::IncorrectPin);
+    token.login("1234").unwrap();
+    let handle = token.generate_key("master").unwrap();
+    assert_eq!(token.find("master").unwrap(), handle);
+    assert!(token.generate_key("master").is_err());
+    let wrapped = token.wrap("master", b"data key material").unwrap();
+    assert_eq!(token.unwrap("master", &wrapped).unwrap().expose(), b"data key material");
+    assert_eq!(token.sign("master", b"data").unwrap().len(), 32);
+    token.import_key("imported", master).unwrap();
+    assert_eq!(token.sign("imported", b"data").unwrap(), file.sign("master-1", b"data").unwrap());
+    token.logout();
+    assert!(!token.has_key("master"));
+    assert_eq!(token.sign("master", b"data").unwrap_err().code(), "provider_not_logged_in");
+    token.login("1234").unwrap();
+    token.destroy("master").unwrap();
+    assert_eq!(token.unwrap("master", &wrapped).unwrap_err(), ProviderError::UnknownKey("master".to_string()));
+}
+
+#[test]
+fn test_key_check_values() {
+    use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+
+    // The HMAC of riot.kcv.v1 under the zero key, not its GHASH key (DC95C0)
+    assert_eq!(aes_check_value(&[0u8; 32]).unwrap(), "946D26");
+    assert!(aes_check_value(&[0u8; 16]).is_err());
+
+    // HMAC check values tell keys apart without revealing them
+    let hmac = |secret: &[u8]| key_check_value(&KeyEntry::new("k", secret.to_vec(), KeyState::Active)).unwrap();
+    let kcv = hmac(b"test-secret-key-for-the-integration-suite");
+    assert_eq!(kcv.len(), 6);
+    assert!(kcv.bytes().all(|b| b.is_ascii_digit() || (b'A'..=b'F').contains(&b)));
+    assert_eq!(kcv, hmac(b"test-secret-key-for-the-integration-suite"));
+    assert_ne!(kcv, hmac(b"test-secret-key-for-the-integration-suitf"));
+
+    // A provider computes the same value for its master key
+    let master = [0x42u8; 32];
+    let file = FileKeyProvider::new(vec![("master-1".to_string(), SecretBytes::from(master))]).unwrap();
+    assert_eq!(provider_check_value(&file, "master-1").unwrap(), hmac(&master));
+    assert!(provider_check_value(&file, "master-2").is_err());
+
+    // Asymmetric keys use the JWK thumbprint: RFC 8037, appendix A.3
+    let seed = URL_SAFE_NO_PAD.decode("nWGxne_9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A").unwrap();
+    let ed25519 = KeyEntry::for_algorithm("ed", KeyAlgorithm::EdDsa, seed, KeyState::Active);
+    assert_eq!(key_check_value(&ed25519).unwrap(), "90FACA");
+}
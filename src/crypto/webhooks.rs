@@ -0,0 +1,236 @@
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use std::fmt;
+use std::str::FromStr;
+
+use super::secret::SecretBytes;
+use super::signing::{create_signing_instance, verify_bytes};
+use hmac::Mac;
+
+/// How a provider signs its webhooks. All schemes use HMAC-SHA256 over the
+/// raw body bytes, never over re-serialized JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookScheme {
+    /// `t=<unix>,v1=<hex>` in one header, signing `{t}.{body}` (Stripe and
+    /// many others). Every `v1` entry is tried, to allow secret rotation.
+    Timestamped,
+    /// `v0=<hex>` plus a separate timestamp header, signing `v0:{ts}:{body}` (Slack).
+    Slack,
+    /// `sha256=<hex>` signing the body alone, with no timestamp (GitHub).
+    GitHub,
+    /// Standard Webhooks (Svix): `webhook-id`, `webhook-timestamp` and
+    /// space-separated `v1,<base64>` signatures over `{id}.{ts}.{body}`.
+    /// Secrets of the form `whsec_<base64>` are decoded.
+    StandardWebhooks,
+}
+
+impl WebhookScheme {
+    /// Header carrying the signature, unless the profile overrides it.
+    pub fn default_header(&self) -> &'static str {
+        match self {
+            WebhookScheme::Timestamped => "Stripe-Signature",
+            WebhookScheme::Slack => "X-Slack-Signature",
+            WebhookScheme::GitHub => "X-Hub-Signature-256",
+            WebhookScheme::StandardWebhooks => "webhook-signature",
+        }
+    }
+}
+
+impl FromStr for WebhookScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "timestamped" | "stripe" => Ok(WebhookScheme::Timestamped),
+            "slack" => Ok(WebhookScheme::Slack),
+            "github" => Ok(WebhookScheme::GitHub),
+            "standard-webhooks" | "svix" => Ok(WebhookScheme::StandardWebhooks),
+            other => Err(format!("Unknown webhook scheme: {}", other)),
+        }
+    }
+}
+
+/// Settings for verifying the webhooks of one provider.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookProfile {
+    /// Name used to select the profile, e.g. `stripe`.
+    pub name: String,
+    pub scheme: WebhookScheme,
+    /// Signing secret shared with the provider.
+    pub secret: SecretBytes,
+    /// Header carrying the signature.
+    pub header: String,
+    /// Largest accepted difference between the signed timestamp and now, in seconds.
+    pub tolerance_secs: u64,
+}
+
+impl WebhookProfile {
+    /// Profile using the scheme's default header and a 300 second tolerance.
+    pub fn new(name: impl Into<String>, scheme: WebhookScheme, secret: impl Into<SecretBytes>) -> Self {
+        WebhookProfile {
+            name: name.into(),
+            scheme,
+            secret: secret.into(),
+            header: scheme.default_header().to_string(),
+            tolerance_secs: 300,
+        }
+    }
+
+    fn key(&self) -> SecretBytes {
+        if self.scheme == WebhookScheme::StandardWebhooks {
+            if let Some(encoded) = self.secret.strip_prefix(b"whsec_") {
+                if let Ok(key) = BASE64.decode(encoded) {
+                    return SecretBytes::from(key);
+                }
+            }
+        }
+        self.secret.clone()
+    }
+}
+
+/// Why a webhook was not accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebhookError {
+    /// A header the scheme needs is absent.
+    MissingHeader(String),
+    /// A header could not be parsed.
+    Malformed(String),
+    /// The signed timestamp is outside the profile's tolerance.
+    OutsideTolerance,
+    /// No signature matches the body.
+    Invalid,
+}
+
+impl WebhookError {
+    /// Stable machine-readable code returned by the API.
+    pub fn code(&self) -> &'static str {
+        match self {
+            WebhookError::MissingHeader(_) => "webhook_header_missing",
+            WebhookError::Malformed(_) => "webhook_header_malformed",
+            WebhookError::OutsideTolerance => "webhook_timestamp_out_of_tolerance",
+            WebhookError::Invalid => "webhook_signature_invalid",
+        }
+    }
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WebhookError::MissingHeader(name) => write!(f, "Missing header {}", name),
+            WebhookError::Malformed(e) => write!(f, "Malformed webhook header: {}", e),
+            WebhookError::OutsideTolerance => write!(f, "Webhook timestamp outside the tolerance window"),
+            WebhookError::Invalid => write!(f, "Invalid webhook signature"),
+        }
+    }
+}
+
+/// The signed bytes and candidate signatures extracted from a webhook.
+struct SignedWebhook {
+    input: Vec<u8>,
+    timestamp: Option<u64>,
+    signatures: Vec<String>,
+}
+
+fn timestamp(value: &str) -> Result<u64, WebhookError> {
+    value.trim().parse().map_err(|_| WebhookError::Malformed(format!("invalid timestamp {}", value)))
+}
+
+fn prefixed_input(prefix: &str, body: &[u8]) -> Vec<u8> {
+    let mut input = prefix.as_bytes().to_vec();
+    input.extend_from_slice(body);
+    input
+}
+
+fn parse_webhook<'a>(
+    profile: &WebhookProfile,
+    header: impl Fn(&str) -> Option<&'a str>,
+    body: &[u8],
+) -> Result<SignedWebhook, WebhookError> {
+    let required = |name: &str| header(name).ok_or_else(|| WebhookError::MissingHeader(name.to_string()));
+    let value = required(&profile.header)?;
+    match profile.scheme {
+        WebhookScheme::Timestamped => {
+            let mut t = None;
+            let mut signatures = Vec::new();
+            for item in value.split(',') {
+                match item.trim().split_once('=') {
+                    Some(("t", ts)) => t = Some(timestamp(ts)?),
+                    Some(("v1", signature)) => signatures.push(signature.to_string()),
+                    Some(_) => {}
+                    None => return Err(WebhookError::Malformed(format!("unexpected item '{}'", item.trim()))),
+                }
+            }
+            let t = t.ok_or_else(|| WebhookError::Malformed("no t= timestamp".to_string()))?;
+            Ok(SignedWebhook { input: prefixed_input(&format!("{}.", t), body), timestamp: Some(t), signatures })
+        }
+        WebhookScheme::Slack => {
+            let raw_ts = required("X-Slack-Request-Timestamp")?;
+            let ts = timestamp(raw_ts)?;
+            let signatures = value.strip_prefix("v0=").map(str::to_string).into_iter().collect();
+            Ok(SignedWebhook { input: prefixed_input(&format!("v0:{}:", raw_ts.trim()), body), timestamp: Some(ts), signatures })
+        }
+        WebhookScheme::GitHub => {
+            let signatures = value.strip_prefix("sha256=").map(str::to_string).into_iter().collect();
+            Ok(SignedWebhook { input: body.to_vec(), timestamp: None, signatures })
+        }
+        WebhookScheme::StandardWebhooks => {
+            let id = required("webhook-id")?;
+            let raw_ts = required("webhook-timestamp")?;
+            let ts = timestamp(raw_ts)?;
+            let signatures = value
+                .split_whitespace()
+                .filter_map(|s| s.strip_prefix("v1,"))
+                .map(str::to_string)
+                .collect();
+            let prefix = format!("{}.{}.", id.trim(), raw_ts.trim());
+            Ok(SignedWebhook { input: prefixed_input(&prefix, body), timestamp: Some(ts), signatures })
+        }
+    }
+}
+
+/// Verifies a webhook against `profile`, looking headers up with `header`.
+///
+/// The HMAC is computed over the raw `body`, and checked in constant time
+/// against every signature the header offers. The timestamp, if the scheme
+/// has one, must be within `tolerance_secs` of `now` in either direction.
+pub fn verify_webhook<'a>(
+    profile: &WebhookProfile,
+    header: impl Fn(&str) -> Option<&'a str>,
+    body: &[u8],
+    now: u64,
+) -> Result<(), WebhookError> {
+    let signed = parse_webhook(profile, header, body)?;
+    if signed.signatures.is_empty() {
+        return Err(WebhookError::Malformed(format!("no supported signature in {}", profile.header)));
+    }
+    if signed.timestamp.is_some_and(|ts| ts.abs_diff(now) > profile.tolerance_secs) {
+        return Err(WebhookError::OutsideTolerance);
+    }
+    let key = profile.key();
+    for signature in &signed.signatures {
+        if verify_bytes(&signed.input, signature, &key).map_err(|_| WebhookError::Invalid)? {
+            return Ok(());
+        }
+    }
+    Err(WebhookError::Invalid)
+}
+
+/// Computes the signature header value a provider using `profile` would send
+/// for `body` at `timestamp`, e.g. to test receivers.
+///
+/// For the Standard Webhooks scheme, `id` is the `webhook-id`.
+pub fn sign_webhook(profile: &WebhookProfile, body: &[u8], timestamp: u64, id: &str) -> Result<String, String> {
+    let (input, prefix) = match profile.scheme {
+        WebhookScheme::Timestamped => (prefixed_input(&format!("{}.", timestamp), body), format!("t={},v1=", timestamp)),
+        WebhookScheme::Slack => (prefixed_input(&format!("v0:{}:", timestamp), body), "v0=".to_string()),
+        WebhookScheme::GitHub => (body.to_vec(), "sha256=".to_string()),
+        WebhookScheme::StandardWebhooks => (prefixed_input(&format!("{}.{}.", id, timestamp), body), "v1,".to_string()),
+    };
+    let mut instance = create_signing_instance(&profile.key())?;
+    instance.update(&input);
+    let mac = instance.finalize().into_bytes();
+    let signature = match profile.scheme {
+        WebhookScheme::StandardWebhooks => BASE64.encode(mac),
+        _ => hex::encode(mac),
+    };
+    Ok(format!("{}{}", prefix, signature))
+}
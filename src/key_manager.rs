@@ -0,0 +1,1247 @@
+//! Key management.
+//! Holds the service's typed keys (HMAC, AES, Ed25519, P-256), each with an
+//! id, purposes, a lifecycle state and a creation date. Keys come from a
+//! keyring file in TOML or JSON, optionally encrypted with a passphrase, or
+//! from the `HMAC_KEYS`/`HMAC_SECRET_KEY`/`JWS_KEYS` environment variables.
+//! With a `KeyProvider`, derivation keys can stay in the provider and the
+//! keyring file can be encrypted under one of its master keys.
+//! Handlers receive the `KeyManager` as shared application data and take a
+//! snapshot of the keys for each request. Keys loaded from a file can be
+//! generated, activated, demoted and retired at runtime; every change is
+//! written back to the file and seen by all workers at once. `reload`
+//! re-reads the keys from their source after an outside change.
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use zeroize::Zeroizing;
+
+use crate::crypto::{
+    aes_check_value, check_secret_strength, derive_provider_tenant_key, derive_tenant_key, key_check_value, provider_check_value,
+    unix_now, validate_tenant_id, FileKeyProvider, KeyAlgorithm, KeyEntry, KeyProvider, KeyState, Keyring, SecretBytes, TenantKeyUse,
+};
+
+/// Kind of key material.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyType {
+    /// HMAC-SHA256 shared secret, of any length.
+    Hmac,
+    /// 256-bit AES key.
+    Aes,
+    /// Ed25519 private seed (32 bytes).
+    Ed25519,
+    /// P-256 private scalar (32 bytes).
+    P256,
+}
+
+impl KeyType {
+    /// Signing algorithm of keys of this type, if they can sign.
+    pub fn algorithm(&self) -> Option<KeyAlgorithm> {
+        match self {
+            KeyType::Hmac => Some(KeyAlgorithm::Hs256),
+            KeyType::Aes => None,
+            KeyType::Ed25519 => Some(KeyAlgorithm::EdDsa),
+            KeyType::P256 => Some(KeyAlgorithm::Es256),
+        }
+    }
+
+    /// The key type of a signing algorithm.
+    pub fn for_algorithm(algorithm: KeyAlgorithm) -> Self {
+        match algorithm {
+            KeyAlgorithm::Hs256 => KeyType::Hmac,
+            KeyAlgorithm::Es256 => KeyType::P256,
+            KeyAlgorithm::EdDsa => KeyType::Ed25519,
+        }
+    }
+
+    /// Purposes a key of this type may serve.
+    fn allows(&self, purpose: KeyPurpose) -> bool {
+        match purpose {
+            KeyPurpose::Signing => self.algorithm().is_some(),
+            KeyPurpose::Encryption => *self == KeyType::Aes,
+            KeyPurpose::Derivation => matches!(self, KeyType::Hmac | KeyType::Aes),
+        }
+    }
+
+    /// Purposes of a key of this type that does not list any.
+    fn default_purpose(&self) -> KeyPurpose {
+        match self {
+            KeyType::Aes => KeyPurpose::Encryption,
+            _ => KeyPurpose::Signing,
+        }
+    }
+
+    /// Name used in keyring files and the API.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyType::Hmac => "hmac",
+            KeyType::Aes => "aes",
+            KeyType::Ed25519 => "ed25519",
+            KeyType::P256 => "p256",
+        }
+    }
+
+    /// Fresh random key material of the right size for the type: 32 bytes,
+    /// or a valid P-256 scalar.
+    pub fn generate_material(&self) -> SecretBytes {
+        match self {
+            KeyType::P256 => SecretBytes::from(p256::SecretKey::random(&mut rand::rngs::OsRng).to_bytes().to_vec()),
+            _ => {
+                let mut material = [0u8; 32];
+                rand::rngs::OsRng.fill_bytes(&mut material);
+                SecretBytes::from(material)
+            }
+        }
+    }
+
+    /// Required length of the key material, when fixed.
+    fn material_len(&self) -> Option<usize> {
+        match self {
+            KeyType::Hmac => None,
+            KeyType::Aes | KeyType::Ed25519 | KeyType::P256 => Some(32),
+        }
+    }
+}
+
+/// What a key may be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyPurpose {
+    /// Signing and verification; the key joins the `Keyring`.
+    Signing,
+    /// Encryption and decryption of data (AES keys).
+    Encryption,
+    /// Master secret other keys are derived from (HMAC and AES keys).
+    Derivation,
+}
+
+/// A key held by the `KeyManager`.
+#[derive(Debug, Clone)]
+pub struct ManagedKey {
+    pub kid: String,
+    pub key_type: KeyType,
+    pub purposes: Vec<KeyPurpose>,
+    /// `Active` keys are used for new signatures or ciphertexts, `VerifyOnly`
+    /// keys only to check or decrypt existing ones, `Retired` keys not at all.
+    pub state: KeyState,
+    /// Creation time, as a Unix timestamp in seconds, when known.
+    pub created_at: Option<u64>,
+    /// The raw key material; empty for keys held by a `KeyProvider`.
+    pub material: SecretBytes,
+    /// Id of the master key holding this key's material in the `KeyProvider`,
+    /// for derivation keys the service never sees.
+    pub provider_key: Option<String>,
+}
+
+impl ManagedKey {
+    /// A key with the default purpose of its type and no creation date.
+    pub fn new(kid: impl Into<String>, key_type: KeyType, material: impl Into<SecretBytes>, state: KeyState) -> Self {
+        ManagedKey {
+            kid: kid.into(),
+            key_type,
+            purposes: vec![key_type.default_purpose()],
+            state,
+            created_at: None,
+            material: material.into(),
+            provider_key: None,
+        }
+    }
+
+    /// A derivation key held by the `KeyProvider` as master key `provider_key`.
+    pub fn in_provider(kid: impl Into<String>, provider_key: impl Into<String>, state: KeyState) -> Self {
+        ManagedKey {
+            purposes: vec![KeyPurpose::Derivation],
+            provider_key: Some(provider_key.into()),
+            ..ManagedKey::new(kid, KeyType::Hmac, SecretBytes::new(&[]), state)
+        }
+    }
+
+    pub fn has_purpose(&self, purpose: KeyPurpose) -> bool {
+        self.purposes.contains(&purpose)
+    }
+
+    /// True when activating `other` should make this key verify-only.
+    fn replaced_by(&self, other: &ManagedKey) -> bool {
+        other.purposes.iter().any(|purpose| {
+            self.has_purpose(*purpose) && (*purpose != KeyPurpose::Signing || self.key_type == other.key_type)
+        })
+    }
+
+    /// Key check value of a key whose material is at hand, see
+    /// `KeySet::check_value` for keys held by a key provider.
+    pub fn check_value(&self) -> Result<String, String> {
+        match self.key_type.algorithm() {
+            _ if self.provider_key.is_some() => Err(format!("Key {} is held by a key provider", self.kid)),
+            Some(algorithm) => key_check_value(&KeyEntry::for_algorithm(self.kid.clone(), algorithm, self.material.clone(), self.state)),
+            None => aes_check_value(&self.material),
+        }
+    }
+
+    /// The `Keyring` entry of a signing key.
+    fn to_entry(&self) -> Option<KeyEntry> {
+        let algorithm = self.key_type.algorithm().filter(|_| self.has_purpose(KeyPurpose::Signing))?;
+        Some(KeyEntry::for_algorithm(self.kid.clone(), algorithm, self.material.clone(), self.state))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.purposes.is_empty() {
+            return Err(format!("Key {} has no purpose", self.kid));
+        }
+        if let Some(purpose) = self.purposes.iter().find(|p| !self.key_type.allows(**p)) {
+            return Err(format!("Key {}: {:?} keys cannot be used for {:?}", self.kid, self.key_type, purpose));
+        }
+        if self.provider_key.is_some() {
+            if self.key_type != KeyType::Hmac || self.purposes != [KeyPurpose::Derivation] {
+                return Err(format!("Key {}: only HMAC derivation keys can be held by a key provider", self.kid));
+            }
+            if !self.material.is_empty() {
+                return Err(format!("Key {} has both a secret and a provider key", self.kid));
+            }
+            return Ok(());
+        }
+        if self.material.is_empty() {
+            return Err(format!("Empty secret for key {}", self.kid));
+        }
+        match self.key_type.material_len() {
+            Some(len) if self.material.len() != len => {
+                Err(format!("Key {}: {:?} keys must be {} bytes", self.kid, self.key_type, len))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl From<&KeyEntry> for ManagedKey {
+    fn from(entry: &KeyEntry) -> Self {
+        ManagedKey::new(entry.kid.clone(), KeyType::for_algorithm(entry.algorithm), entry.secret.clone(), entry.state)
+    }
+}
+
+/// An immutable, validated set of keys, with the `Keyring` of its signing keys.
+pub struct KeySet {
+    keys: Vec<ManagedKey>,
+    keyring: Arc<Keyring>,
+    provider: Option<Arc<dyn KeyProvider>>,
+    /// Tenant keys derived by the key provider, which is too slow to ask on
+    /// every request, by master key id, tenant and use.
+    provider_derived: Mutex<HashMap<DerivedKeyId, Zeroizing<[u8; 32]>>>,
+}
+
+/// Master key id, tenant and use of a derived tenant key.
+type DerivedKeyId = (String, String, TenantKeyUse);
+
+/// Most tenant keys `KeySet` keeps from the key provider; the cache starts
+/// over when it is full.
+const MAX_PROVIDER_DERIVED_KEYS: usize = 10_000;
+
+impl KeySet {
+    /// Validates `keys`: ids must be unique, material must suit the key type,
+    /// the signing keys must form a valid `Keyring`, and at most one
+    /// encryption key and one derivation key may be active.
+    pub fn new(keys: Vec<ManagedKey>) -> Result<Self, String> {
+        KeySet::with_provider(keys, None)
+    }
+
+    /// Validates `keys` like `new`; keys held by a provider must be found in
+    /// `provider`, unless they are retired.
+    pub fn with_provider(keys: Vec<ManagedKey>, provider: Option<Arc<dyn KeyProvider>>) -> Result<Self, String> {
+        for (i, key) in keys.iter().enumerate() {
+            key.validate()?;
+            if keys[..i].iter().any(|other| other.kid == key.kid) {
+                return Err(format!("Duplicate key id: {}", key.kid));
+            }
+            if let Some(provider_key) = key.provider_key.as_deref().filter(|_| key.state != KeyState::Retired) {
+                match &provider {
+                    None => return Err(format!("Key {} is held by a key provider, but none is configured", key.kid)),
+                    Some(provider) if !provider.has_key(provider_key) => {
+                        return Err(format!("Key {}: the {} key provider has no key {}", key.kid, provider.name(), provider_key));
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        let active_encryption = keys
+            .iter()
+            .filter(|k| k.has_purpose(KeyPurpose::Encryption) && k.state == KeyState::Active)
+            .count();
+        if active_encryption > 1 {
+            return Err("Keyring has more than one active encryption key".to_string());
+        }
+        let active_derivation = keys
+            .iter()
+            .filter(|k| k.has_purpose(KeyPurpose::Derivation) && k.state == KeyState::Active)
+            .count();
+        if active_derivation > 1 {
+            return Err("Keyring has more than one active derivation key".to_string());
+        }
+        let keyring = Keyring::new(keys.iter().filter_map(ManagedKey::to_entry).collect())?;
+        Ok(KeySet { keys, keyring: Arc::new(keyring), provider, provider_derived: Mutex::default() })
+    }
+
+    /// All keys, in configuration order.
+    pub fn keys(&self) -> &[ManagedKey] {
+        &self.keys
+    }
+
+    /// Looks up a key by id, whatever its state.
+    pub fn get(&self, kid: &str) -> Option<&ManagedKey> {
+        self.keys.iter().find(|k| k.kid == kid)
+    }
+
+    /// The signing keys.
+    pub fn keyring(&self) -> Arc<Keyring> {
+        self.keyring.clone()
+    }
+
+    /// Describes how `other` differs from this set, without revealing material.
+    pub fn changes(&self, other: &KeySet) -> Vec<String> {
+        let mut changes = Vec::new();
+        for key in &other.keys {
+            match self.get(&key.kid) {
+                None => changes.push(format!("key {} added ({:?}, {:?})", key.kid, key.key_type, key.state)),
+                Some(old) => {
+                    if old.state != key.state {
+                        changes.push(format!("key {}: {:?} -> {:?}", key.kid, old.state, key.state));
+                    }
+                    if old.key_type != key.key_type || old.material != key.material || old.provider_key != key.provider_key {
+                        changes.push(format!("key {}: material replaced", key.kid));
+                    }
+                    if old.purposes != key.purposes {
+                        changes.push(format!("key {}: purposes {:?} -> {:?}", key.kid, old.purposes, key.purposes));
+                    }
+                }
+            }
+        }
+        for key in &self.keys {
+            if other.get(&key.kid).is_none() {
+                changes.push(format!("key {} removed", key.kid));
+            }
+        }
+        changes
+    }
+
+    /// The active key for `purpose` and `key_type`, if any.
+    pub fn active(&self, purpose: KeyPurpose, key_type: KeyType) -> Option<&ManagedKey> {
+        self.keys
+            .iter()
+            .find(|k| k.key_type == key_type && k.has_purpose(purpose) && k.state == KeyState::Active)
+    }
+
+    /// Derivation keys that are not retired, the active one first.
+    fn master_keys(&self) -> Vec<&ManagedKey> {
+        let mut masters: Vec<&ManagedKey> = self
+            .keys
+            .iter()
+            .filter(|k| k.has_purpose(KeyPurpose::Derivation) && k.state != KeyState::Retired)
+            .collect();
+        masters.sort_by_key(|k| k.state != KeyState::Active);
+        masters
+    }
+
+    /// True when there is a derivation key to scope requests to tenants.
+    pub fn has_tenants(&self) -> bool {
+        !self.master_keys().is_empty()
+    }
+
+    /// The signing keys of `tenant`: an HMAC key derived from each derivation
+    /// key that is not retired, with the id and state of its master key.
+    pub fn tenant_keyring(&self, tenant: &str) -> Result<Keyring, String> {
+        validate_tenant_id(tenant)?;
+        let entries = self
+            .master_keys()
+            .into_iter()
+            .map(|master| {
+                let secret = self.derive_tenant_key(master, tenant, TenantKeyUse::Signing)?;
+                Ok(KeyEntry::new(master.kid.clone(), secret.as_slice(), master.state))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if entries.is_empty() {
+            return Err("No derivation key is configured".to_string());
+        }
+        Keyring::new(entries).map_err(|_| "No active derivation key".to_string())
+    }
+
+    /// The encryption keys of `tenant`, derived like `tenant_keyring`: the
+    /// key of the active derivation key first, for new ciphertexts.
+    pub fn tenant_encryption_keys(&self, tenant: &str) -> Result<Zeroizing<Vec<[u8; 32]>>, String> {
+        validate_tenant_id(tenant)?;
+        let masters = self.master_keys();
+        match masters.first() {
+            None => Err("No derivation key is configured".to_string()),
+            Some(master) if master.state != KeyState::Active => Err("No active derivation key".to_string()),
+            Some(_) => masters
+                .into_iter()
+                .map(|master| self.derive_tenant_key(master, tenant, TenantKeyUse::Encryption).map(|key| *key))
+                .collect::<Result<Vec<_>, String>>()
+                .map(Zeroizing::new),
+        }
+    }
+
+    /// Key check value of `key`, computed by the key provider when it holds
+    /// the material.
+    pub fn check_value(&self, key: &ManagedKey) -> Result<String, String> {
+        match (&key.provider_key, &self.provider) {
+            (None, _) => key.check_value(),
+            (Some(provider_key), Some(provider)) => provider_check_value(provider.as_ref(), provider_key)
+                .map_err(|e| format!("Key {}: {}", key.kid, e)),
+            (Some(_), None) => Err(format!("Key {} is held by a key provider, but none is configured", key.kid)),
+        }
+    }
+
+    /// The key of `tenant` derived from `master`: with version 1 of the
+    /// derivation when the service holds the master, and version 2, by the
+    /// key provider, when the provider does. Keys from the provider are
+    /// cached for the life of this set.
+    fn derive_tenant_key(&self, master: &ManagedKey, tenant: &str, key_use: TenantKeyUse) -> Result<Zeroizing<[u8; 32]>, String> {
+        let (provider_key, provider) = match (&master.provider_key, &self.provider) {
+            (None, _) => return Ok(derive_tenant_key(&master.material, tenant, key_use)),
+            (Some(provider_key), Some(provider)) => (provider_key, provider),
+            (Some(_), None) => return Err(format!("Key {} is held by a key provider, but none is configured", master.kid)),
+        };
+        let cache_key = (master.kid.clone(), tenant.to_string(), key_use);
+        if let Some(key) = self.provider_derived.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get(&cache_key) {
+            return Ok(key.clone());
+        }
+        // Not under the lock: other tenants need not wait for the provider
+        let key = derive_provider_tenant_key(provider.as_ref(), provider_key, tenant, key_use)
+            .map_err(|e| format!("Key {}: {}", master.kid, e))?;
+        let mut cache = self.provider_derived.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if cache.len() >= MAX_PROVIDER_DERIVED_KEYS {
+            cache.clear();
+        }
+        cache.insert(cache_key, key.clone());
+        Ok(key)
+    }
+}
+
+/// The keys of the service, shared by all workers.
+///
+/// Create it once and register the same `web::Data` in every `App`.
+/// Handlers call `keyring()` or `current()` once per request, so that a
+/// request sees a consistent set of keys.
+pub struct KeyManager {
+    current: RwLock<Arc<KeySet>>,
+    /// The file the keys were loaded from, where changes are written. Its
+    /// lock serializes changes, so that none is lost.
+    store: Option<Mutex<Keystore>>,
+    provider: Option<Arc<dyn KeyProvider>>,
+    /// Algorithms signing keys may use.
+    algorithms: Vec<KeyAlgorithm>,
+}
+
+impl KeyManager {
+    /// Manages a validated set of keys, in memory only.
+    pub fn new(keys: Vec<ManagedKey>) -> Result<Self, String> {
+        KeyManager::with_provider(keys, None)
+    }
+
+    /// Manages `keys` in memory, some of which may be held by `provider`.
+    pub fn with_provider(keys: Vec<ManagedKey>, provider: Option<Arc<dyn KeyProvider>>) -> Result<Self, String> {
+        let keys = KeySet::with_provider(keys, provider.clone())?;
+        Ok(KeyManager { current: RwLock::new(Arc::new(keys)), store: None, provider, algorithms: KeyAlgorithm::ALL.to_vec() })
+    }
+
+    /// Restricts signing keys to `algorithms`, failing if a current key
+    /// uses another one. Reloads and key changes are held to it too.
+    pub fn with_algorithms(mut self, algorithms: Vec<KeyAlgorithm>) -> Result<Self, String> {
+        check_algorithms(&self.current(), &algorithms)?;
+        self.algorithms = algorithms;
+        Ok(self)
+    }
+
+    /// Loads the keys from the environment.
+    ///
+    /// - `KEYRING_FILE`: a keyring file, see `load`. When unset, the keys are
+    ///   read by `Keyring::from_env`.
+    /// - `KEYRING_PASSPHRASE`: passphrase of an encrypted keyring file.
+    /// - `KEY_PROVIDER`: the `KeyProvider` holding master keys, see
+    ///   `key_provider_from_vars`.
+    /// - `TENANT_MASTER_KEY`: without a keyring file, an optional master
+    ///   secret tenant keys are derived from, with id `TENANT_MASTER_KEY_ID`
+    ///   (default `tenants`).
+    /// - `TENANT_MASTER_PROVIDER_KEY`: instead of `TENANT_MASTER_KEY`, the
+    ///   id of a master key of the key provider to derive tenant keys from.
+    /// - `SIGNING_ALGORITHMS`: comma-separated algorithms signing keys may
+    ///   use, e.g. `EdDSA,ES256` (all by default).
+    pub fn from_env() -> Result<Self, String> {
+        KeyManager::from_vars(|name| env::var(name).ok())
+    }
+
+    /// Loads the keys like `from_env`, reading variables through `var`.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let provider = key_provider_from_vars(&var)?;
+        let algorithms = algorithms_from_vars(&var)?;
+        let manager = match var("KEYRING_FILE") {
+            Some(path) => KeyManager::load_with_provider(path, var("KEYRING_PASSPHRASE").as_deref(), provider)?,
+            None => {
+                let keys = env_keys(&var, provider.clone())?;
+                KeyManager { current: RwLock::new(Arc::new(keys)), store: None, provider, algorithms: KeyAlgorithm::ALL.to_vec() }
+            }
+        };
+        manager.with_algorithms(algorithms)
+    }
+
+    /// Loads a keyring file: a TOML or JSON document with a `keys` array,
+    /// or such a document encrypted by `encrypt_keyring`.
+    ///
+    /// Changes made through the manager are written back to the file, in
+    /// the same format and, if it was encrypted, under the same passphrase.
+    /// Secrets of keys that are not retired must pass `check_secret_strength`.
+    pub fn load(path: impl AsRef<Path>, passphrase: Option<&str>) -> Result<Self, String> {
+        KeyManager::load_with_provider(path, passphrase, None)
+    }
+
+    /// Loads a keyring file like `load`, with `provider` for the keys it
+    /// holds and for files encrypted by `encrypt_keyring_with_provider`.
+    pub fn load_with_provider(
+        path: impl AsRef<Path>,
+        passphrase: Option<&str>,
+        provider: Option<Arc<dyn KeyProvider>>,
+    ) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let (keys, format, encryption) = read_keyring(&text, passphrase, provider.as_deref())?;
+        let keys = KeySet::with_provider(keys, provider.clone())?;
+        let store = Keystore { path: path.to_path_buf(), format, encryption };
+        Ok(KeyManager {
+            current: RwLock::new(Arc::new(keys)),
+            store: Some(Mutex::new(store)),
+            provider,
+            algorithms: KeyAlgorithm::ALL.to_vec(),
+        })
+    }
+
+    /// The current set of keys.
+    pub fn current(&self) -> Arc<KeySet> {
+        self.current.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    /// The current signing keys.
+    pub fn keyring(&self) -> Arc<Keyring> {
+        self.current().keyring()
+    }
+
+    /// The key provider holding master keys, if one is configured.
+    pub fn provider(&self) -> Option<&dyn KeyProvider> {
+        self.provider.as_deref()
+    }
+
+    /// True when changes are written to a keyring file.
+    pub fn is_persistent(&self) -> bool {
+        self.store.is_some()
+    }
+
+    /// The keyring file, if the keys were loaded from one.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.store.as_ref().map(|store| store.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).path.clone())
+    }
+
+    /// Makes `keys` current and returns what changed.
+    fn replace(&self, keys: KeySet) -> Vec<String> {
+        let mut current = self.current.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let changes = current.changes(&keys);
+        *current = Arc::new(keys);
+        changes
+    }
+
+    /// Re-reads the keys from where they came from: the keyring file, with
+    /// the `KEYRING_PASSPHRASE` read through `var`, or the variables of
+    /// `from_vars`. The new keys replace the current ones only if
+    /// they are valid. Returns what changed.
+    pub fn reload(&self, var: impl Fn(&str) -> Option<String>) -> Result<Vec<String>, String> {
+        let Some(store) = &self.store else {
+            let keys = env_keys(var, self.provider.clone())?;
+            check_algorithms(&keys, &self.algorithms)?;
+            return Ok(self.replace(keys));
+        };
+        let mut store = store.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let text = fs::read_to_string(&store.path).map_err(|e| format!("Failed to read {}: {}", store.path.display(), e))?;
+        let (keys, format, encryption) = read_keyring(&text, var("KEYRING_PASSPHRASE").as_deref(), self.provider.as_deref())?;
+        let keys = KeySet::with_provider(keys, self.provider.clone())?;
+        check_algorithms(&keys, &self.algorithms)?;
+        store.format = format;
+        store.encryption = encryption;
+        Ok(self.replace(keys))
+    }
+
+    /// Applies `change` to a copy of the keys, validates the result, writes
+    /// it to the keyring file and only then makes it current.
+    fn update(&self, change: impl FnOnce(&mut Vec<ManagedKey>) -> Result<(), KeyAdminError>) -> Result<Arc<KeySet>, KeyAdminError> {
+        let store = self.store.as_ref().ok_or(KeyAdminError::NotPersistent)?;
+        let store = store.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut keys = self.current().keys().to_vec();
+        change(&mut keys)?;
+        let updated = KeySet::with_provider(keys, self.provider.clone()).map_err(KeyAdminError::Conflict)?;
+        check_algorithms(&updated, &self.algorithms).map_err(KeyAdminError::Conflict)?;
+        store.save(updated.keys()).map_err(KeyAdminError::Storage)?;
+        self.replace(updated);
+        Ok(self.current())
+    }
+
+    /// Generates a random key of `key_type`, added as verify-only so that it
+    /// is known everywhere before it is activated, or activated at once when
+    /// `activate` is set. Either way the keyring file is written once.
+    ///
+    /// The id defaults to `<type>-<date>-<random hex>`; purposes default to
+    /// the type's usual one.
+    pub fn generate(
+        &self,
+        kid: Option<String>,
+        key_type: KeyType,
+        purposes: Option<Vec<KeyPurpose>>,
+        activate: bool,
+    ) -> Result<ManagedKey, KeyAdminError> {
+        let now = unix_now();
+        let kid = kid.unwrap_or_else(|| {
+            let mut suffix = [0u8; 3];
+            rand::thread_rng().fill_bytes(&mut suffix);
+            format!("{}-{}-{}", key_type.as_str(), &format_timestamp(now)[..10], hex::encode(suffix))
+        });
+        let key = ManagedKey {
+            kid,
+            key_type,
+            purposes: purposes.unwrap_or_else(|| vec![key_type.default_purpose()]),
+            state: KeyState::VerifyOnly,
+            created_at: Some(now),
+            material: key_type.generate_material(),
+            provider_key: None,
+        };
+        self.update(|keys| {
+            if keys.iter().any(|k| k.kid == key.kid) {
+                return Err(KeyAdminError::Conflict(format!("Key {} already exists", key.kid)));
+            }
+            keys.push(key.clone());
+            if activate {
+                activate_key(keys, &key.kid)?;
+            }
+            Ok(())
+        })?;
+        Ok(ManagedKey { state: if activate { KeyState::Active } else { key.state }, ..key })
+    }
+
+    /// Makes `kid` the active key for its purposes. The key it replaces,
+    /// the active one of the same type and purpose (or, for encryption and
+    /// derivation, of the same purpose), becomes verify-only.
+    pub fn activate(&self, kid: &str) -> Result<Arc<KeySet>, KeyAdminError> {
+        self.update(|keys| activate_key(keys, kid))
+    }
+
+    /// Stops using the active key `kid` for new signatures or ciphertexts,
+    /// while still accepting existing ones.
+    pub fn demote(&self, kid: &str) -> Result<Arc<KeySet>, KeyAdminError> {
+        self.update(|keys| {
+            let key = find_key_mut(keys, kid)?;
+            if key.state != KeyState::Active {
+                return Err(KeyAdminError::Conflict(format!("Key {} is not active", kid)));
+            }
+            if key.key_type == KeyType::Hmac && key.has_purpose(KeyPurpose::Signing) {
+                return Err(KeyAdminError::Conflict(format!("Key {} is the active HMAC key; activate another key instead", kid)));
+            }
+            key.state = KeyState::VerifyOnly;
+            Ok(())
+        })
+    }
+
+    /// Retires `kid`: signatures made with it no longer verify.
+    pub fn retire(&self, kid: &str) -> Result<Arc<KeySet>, KeyAdminError> {
+        self.update(|keys| {
+            let key = find_key_mut(keys, kid)?;
+            if key.state == KeyState::Active {
+                return Err(KeyAdminError::Conflict(format!("Key {} is active; activate another key or demote it first", kid)));
+            }
+            key.state = KeyState::Retired;
+            Ok(())
+        })
+    }
+}
+
+/// Makes `kid` active among `keys`, demoting the key it replaces.
+fn activate_key(keys: &mut [ManagedKey], kid: &str) -> Result<(), KeyAdminError> {
+    let target = find_key(keys, kid)?.clone();
+    if target.state == KeyState::Retired {
+        return Err(KeyAdminError::Conflict(format!("Key {} is retired", kid)));
+    }
+    for key in keys.iter_mut() {
+        if key.kid == kid {
+            key.state = KeyState::Active;
+        } else if key.state == KeyState::Active && key.replaced_by(&target) {
+            key.state = KeyState::VerifyOnly;
+        }
+    }
+    Ok(())
+}
+
+/// Keys from the variables of `Keyring::from_vars`, plus the optional
+/// `TENANT_MASTER_KEY` or `TENANT_MASTER_PROVIDER_KEY`.
+fn env_keys(var: impl Fn(&str) -> Option<String>, provider: Option<Arc<dyn KeyProvider>>) -> Result<KeySet, String> {
+    let keyring = Keyring::from_vars(&var)?;
+    let kid = var("TENANT_MASTER_KEY_ID").unwrap_or_else(|| "tenants".to_string());
+    let master = match (var("TENANT_MASTER_KEY"), var("TENANT_MASTER_PROVIDER_KEY")) {
+        (None, None) => return Ok(KeySet { provider, ..KeySet::from(keyring) }),
+        (Some(_), Some(_)) => return Err("TENANT_MASTER_KEY and TENANT_MASTER_PROVIDER_KEY are both set".to_string()),
+        (Some(secret), None) => {
+            check_secret_strength(&kid, secret.as_bytes())?;
+            ManagedKey {
+                purposes: vec![KeyPurpose::Derivation],
+                ..ManagedKey::new(kid, KeyType::Hmac, secret.into_bytes(), KeyState::Active)
+            }
+        }
+        (None, Some(provider_key)) => ManagedKey::in_provider(kid, provider_key, KeyState::Active),
+    };
+    let mut keys: Vec<ManagedKey> = keyring.keys().iter().map(ManagedKey::from).collect();
+    keys.push(master);
+    KeySet::with_provider(keys, provider)
+}
+
+/// The `KeyProvider` named by `KEY_PROVIDER`, if set:
+/// - `file`: a `FileKeyProvider` reading the master keys from
+///   `KEY_PROVIDER_FILE`.
+pub fn key_provider_from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Option<Arc<dyn KeyProvider>>, String> {
+    let Some(name) = var("KEY_PROVIDER") else {
+        return Ok(None);
+    };
+    match name.as_str() {
+        "file" => {
+            let path = var("KEY_PROVIDER_FILE").ok_or_else(|| "KEY_PROVIDER=file requires KEY_PROVIDER_FILE".to_string())?;
+            Ok(Some(Arc::new(FileKeyProvider::load(path)?)))
+        }
+        _ => Err(format!("Unknown KEY_PROVIDER {}; supported: file", name)),
+    }
+}
+
+/// The algorithms listed in `SIGNING_ALGORITHMS`, or all of them.
+pub fn algorithms_from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Vec<KeyAlgorithm>, String> {
+    let Some(names) = var("SIGNING_ALGORITHMS") else {
+        return Ok(KeyAlgorithm::ALL.to_vec());
+    };
+    let algorithms = names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::parse)
+        .collect::<Result<Vec<KeyAlgorithm>, String>>()?;
+    if algorithms.is_empty() {
+        return Err("SIGNING_ALGORITHMS must list at least one algorithm".to_string());
+    }
+    Ok(algorithms)
+}
+
+/// Fails if a signing key of `keys` uses an algorithm not in `allowed`.
+fn check_algorithms(keys: &KeySet, allowed: &[KeyAlgorithm]) -> Result<(), String> {
+    for key in keys.keys().iter().filter(|key| key.has_purpose(KeyPurpose::Signing)) {
+        if let Some(algorithm) = key.key_type.algorithm().filter(|algorithm| !allowed.contains(algorithm)) {
+            return Err(format!("Key {} uses {}, which SIGNING_ALGORITHMS does not allow", key.kid, algorithm));
+        }
+    }
+    Ok(())
+}
+
+fn find_key<'a>(keys: &'a [ManagedKey], kid: &str) -> Result<&'a ManagedKey, KeyAdminError> {
+    keys.iter().find(|k| k.kid == kid).ok_or_else(|| KeyAdminError::UnknownKey(kid.to_string()))
+}
+
+fn find_key_mut<'a>(keys: &'a mut [ManagedKey], kid: &str) -> Result<&'a mut ManagedKey, KeyAdminError> {
+    keys.iter_mut().find(|k| k.kid == kid).ok_or_else(|| KeyAdminError::UnknownKey(kid.to_string()))
+}
+
+/// Why a key change was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyAdminError {
+    /// No key has this id.
+    UnknownKey(String),
+    /// The change would leave the keys in an invalid state.
+    Conflict(String),
+    /// The keys were not loaded from a keyring file, so changes would be lost.
+    NotPersistent,
+    /// The keyring file could not be written; nothing was changed.
+    Storage(String),
+}
+
+impl KeyAdminError {
+    /// Stable machine-readable code returned by the API.
+    pub fn code(&self) -> &'static str {
+        match self {
+            KeyAdminError::UnknownKey(_) => "key_not_found",
+            KeyAdminError::Conflict(_) => "key_state_conflict",
+            KeyAdminError::NotPersistent => "keyring_not_persistent",
+            KeyAdminError::Storage(_) => "keyring_storage_failed",
+        }
+    }
+}
+
+impl fmt::Display for KeyAdminError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyAdminError::UnknownKey(kid) => write!(f, "Unknown key id: {}", kid),
+            KeyAdminError::Conflict(e) => write!(f, "{}", e),
+            KeyAdminError::NotPersistent => write!(f, "Key changes require a KEYRING_FILE"),
+            KeyAdminError::Storage(e) => write!(f, "Failed to write keyring: {}", e),
+        }
+    }
+}
+
+impl From<Keyring> for KeySet {
+    fn from(keyring: Keyring) -> Self {
+        let keys = keyring.keys().iter().map(ManagedKey::from).collect();
+        KeySet { keys, keyring: Arc::new(keyring), provider: None, provider_derived: Mutex::default() }
+    }
+}
+
+impl From<Keyring> for KeyManager {
+    fn from(keyring: Keyring) -> Self {
+        KeyManager { current: RwLock::new(Arc::new(KeySet::from(keyring))), store: None, provider: None, algorithms: KeyAlgorithm::ALL.to_vec() }
+    }
+}
+
+/// One key as written in a keyring file.
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyRecord {
+    kid: String,
+    #[serde(rename = "type")]
+    key_type: KeyType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    purposes: Option<Vec<KeyPurpose>>,
+    state: KeyState,
+    /// RFC 3339 date or date-time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created: Option<String>,
+    /// Standard Base64 key material.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    secret: Option<Zeroizing<String>>,
+    /// Master key of the key provider holding the material, instead of `secret`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    provider_key: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyringDocument {
+    keys: Vec<KeyRecord>,
+}
+
+/// Parses a keyring document, TOML or JSON, decrypting it first if needed.
+///
+/// ```toml
+/// [[keys]]
+/// kid = "2025-01"
+/// type = "hmac"
+/// state = "active"
+/// created = 2025-01-15T00:00:00Z
+/// secret = "YW4taG1hYy1rZXktb2YtYXQtbGVhc3QtMzItYnl0ZXMhIQ=="
+/// ```
+///
+/// A key held by a key provider has a `provider_key` instead of a `secret`.
+pub fn parse_keyring(text: &str, passphrase: Option<&str>) -> Result<Vec<ManagedKey>, String> {
+    read_keyring(text, passphrase, None).map(|(keys, _, _)| keys)
+}
+
+/// Parses a keyring document, also returning how to write it back.
+fn read_keyring(
+    text: &str,
+    passphrase: Option<&str>,
+    provider: Option<&dyn KeyProvider>,
+) -> Result<(Vec<ManagedKey>, DocumentFormat, Option<FileEncryption>), String> {
+    let mut format = DocumentFormat::detect(text);
+    let mut document = parse_document(text)?;
+    let mut encryption = None;
+    if document.get("ciphertext").is_some() {
+        let (plaintext, file_encryption) = decrypt_keyring(document, passphrase, provider)?;
+        format = DocumentFormat::detect(&plaintext);
+        document = parse_document(&plaintext)?;
+        encryption = Some(file_encryption);
+    }
+    let document: KeyringDocument =
+        serde_json::from_value(document).map_err(|e| format!("Invalid keyring: {}", e))?;
+    document
+        .keys
+        .into_iter()
+        .map(|record| {
+            let material = match (&record.secret, &record.provider_key) {
+                (Some(secret), None) => BASE64
+                    .decode(secret.trim())
+                    .map_err(|_| format!("Key {}: secret must be standard Base64", record.kid))?,
+                (None, Some(_)) => Vec::new(),
+                _ => return Err(format!("Key {} needs either a secret or a provider_key", record.kid)),
+            };
+            let material = SecretBytes::from(material);
+            if record.state != KeyState::Retired && record.provider_key.is_none() {
+                check_secret_strength(&record.kid, &material)?;
+            }
+            let created_at = record.created.as_deref().map(parse_timestamp).transpose()
+                .map_err(|e| format!("Key {}: {}", record.kid, e))?;
+            Ok(ManagedKey {
+                purposes: record.purposes.unwrap_or_else(|| vec![record.key_type.default_purpose()]),
+                kid: record.kid,
+                key_type: record.key_type,
+                state: record.state,
+                created_at,
+                material,
+                provider_key: record.provider_key,
+            })
+        })
+        .collect::<Result<_, String>>()
+        .map(|keys| (keys, format, encryption))
+}
+
+/// Syntax of a keyring document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DocumentFormat {
+    Toml,
+    Json,
+}
+
+impl DocumentFormat {
+    /// JSON documents start with `{`; anything else is read as TOML.
+    fn detect(text: &str) -> Self {
+        if text.trim_start().starts_with('{') { DocumentFormat::Json } else { DocumentFormat::Toml }
+    }
+}
+
+/// Key and parameters an encrypted keyring file was written with, kept so
+/// that changes can be written back without holding the passphrase or
+/// calling the key provider.
+struct FileEncryption {
+    key: Zeroizing<[u8; 32]>,
+    protection: FileKeyProtection,
+}
+
+/// How the key of an encrypted keyring file is protected.
+enum FileKeyProtection {
+    /// Derived from a passphrase with PBKDF2-HMAC-SHA256.
+    Passphrase { salt: Vec<u8>, iterations: u32 },
+    /// Random, and wrapped by master key `kid` of the key provider.
+    Provider { kid: String, wrapped_key: Vec<u8> },
+}
+
+/// Keyring file of a `KeyManager`.
+struct Keystore {
+    path: PathBuf,
+    format: DocumentFormat,
+    encryption: Option<FileEncryption>,
+}
+
+impl Keystore {
+    /// Replaces the file with `keys`, atomically, keeping its format,
+    /// encryption and permissions.
+    fn save(&self, keys: &[ManagedKey]) -> Result<(), String> {
+        let document = KeyringDocument {
+            keys: keys
+                .iter()
+                .map(|key| KeyRecord {
+                    kid: key.kid.clone(),
+                    key_type: key.key_type,
+                    purposes: Some(key.purposes.clone()),
+                    state: key.state,
+                    created: key.created_at.map(format_timestamp),
+                    secret: key.provider_key.is_none().then(|| Zeroizing::new(BASE64.encode(key.material.expose()))),
+                    provider_key: key.provider_key.clone(),
+                })
+                .collect(),
+        };
+        let mut text = Zeroizing::new(match self.format {
+            DocumentFormat::Toml => toml::to_string(&document).map_err(|e| e.to_string())?,
+            DocumentFormat::Json => serde_json::to_string_pretty(&document).map_err(|e| e.to_string())?,
+        });
+        if let Some(encryption) = &self.encryption {
+            text = Zeroizing::new(seal_keyring(&text, encryption)?);
+        }
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let write = || -> io::Result<()> {
+            // Left over by a crash; the store lock rules out a concurrent save
+            if let Err(e) = fs::remove_file(&temporary) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e);
+                }
+            }
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            // Readable by the owner only from the start, not after a chmod
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let mut file = options.open(&temporary)?;
+            file.write_all(text.as_bytes())?;
+            if let Ok(metadata) = fs::metadata(&self.path) {
+                file.set_permissions(metadata.permissions())?;
+            }
+            file.sync_all()?;
+            fs::rename(&temporary, &self.path)?;
+            // Make the rename itself durable
+            #[cfg(unix)]
+            {
+                let directory = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+                File::open(directory)?.sync_all()?;
+            }
+            Ok(())
+        };
+        write().map_err(|e| {
+            let _ = fs::remove_file(&temporary);
+            format!("Failed to write {}: {}", self.path.display(), e)
+        })
+    }
+}
+
+fn parse_document(text: &str) -> Result<Value, String> {
+    if DocumentFormat::detect(text) == DocumentFormat::Json {
+        serde_json::from_str(text).map_err(|e| format!("Invalid keyring JSON: {}", e))
+    } else {
+        let table: toml::Table = toml::from_str(text).map_err(|e| format!("Invalid keyring TOML: {}", e))?;
+        Ok(toml_to_json(toml::Value::Table(table)))
+    }
+}
+
+/// Converts TOML to JSON, with date-times as RFC 3339 strings.
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Value::from(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(items) => Value::Array(items.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(table.into_iter().map(|(k, v)| (k, toml_to_json(v))).collect()),
+    }
+}
+
+/// PBKDF2 iterations used by `encrypt_keyring` by default.
+pub const DEFAULT_KDF_ITERATIONS: u32 = 600_000;
+
+/// Fewest PBKDF2 iterations accepted, when writing or reading a keyring file.
+pub const MIN_KDF_ITERATIONS: u32 = 100_000;
+
+/// Most PBKDF2 iterations accepted, so that an edited file cannot stall
+/// startup or a reload for minutes.
+pub const MAX_KDF_ITERATIONS: u32 = 10_000_000;
+
+/// Checks that `iterations` lies within the accepted bounds.
+fn check_kdf_iterations(iterations: u32) -> Result<(), String> {
+    if !(MIN_KDF_ITERATIONS..=MAX_KDF_ITERATIONS).contains(&iterations) {
+        return Err(format!(
+            "PBKDF2 iterations must be between {} and {}, not {}",
+            MIN_KDF_ITERATIONS, MAX_KDF_ITERATIONS, iterations
+        ));
+    }
+    Ok(())
+}
+
+/// An encrypted keyring file.
+#[derive(Debug, Serialize, Deserialize)]
+struct EncryptedKeyring {
+    /// Always `aes-256-gcm`.
+    encryption: String,
+    /// `pbkdf2-sha256`, with `iterations` and `salt`, or `key-provider`,
+    /// with `key_id` and `wrapped_key`.
+    kdf: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    iterations: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    salt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    wrapped_key: Option<String>,
+    nonce: String,
+    ciphertext: String,
+}
+
+fn derive_file_key(passphrase: &str, salt: &[u8], iterations: u32) -> Zeroizing<[u8; 32]> {
+    Zeroizing::new(pbkdf2::pbkdf2_hmac_array::<Sha256, 32>(passphrase.as_bytes(), salt, iterations))
+}
+
+/// Encrypts a keyring document with AES-256-GCM under a key derived from
+/// `passphrase` with PBKDF2-HMAC-SHA256, returning the JSON file contents.
+///
+/// The document is parsed first, so that only valid keyrings are encrypted.
+pub fn encrypt_keyring(plaintext: &str, passphrase: &str, iterations: u32) -> Result<String, String> {
+    if passphrase.is_empty() {
+        return Err("Passphrase must not be empty".to_string());
+    }
+    check_kdf_iterations(iterations)?;
+    validate_plaintext_keyring(plaintext)?;
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let key = derive_file_key(passphrase, &salt, iterations);
+    seal_keyring(plaintext, &FileEncryption { key, protection: FileKeyProtection::Passphrase { salt: salt.to_vec(), iterations } })
+}
+
+/// Encrypts a keyring document with AES-256-GCM under a random file key,
+/// wrapped by master key `kid` of `provider`, returning the JSON file
+/// contents. Loading the file then needs the provider, not a passphrase.
+///
+/// The document is parsed first, so that only valid keyrings are encrypted.
+pub fn encrypt_keyring_with_provider(plaintext: &str, provider: &dyn KeyProvider, kid: &str) -> Result<String, String> {
+    validate_plaintext_keyring(plaintext)?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    rand::rngs::OsRng.fill_bytes(key.as_mut_slice());
+    let wrapped_key = provider.wrap(kid, key.as_slice()).map_err(|e| e.to_string())?;
+    let protection = FileKeyProtection::Provider { kid: kid.to_string(), wrapped_key };
+    seal_keyring(plaintext, &FileEncryption { key, protection })
+}
+
+/// Checks a keyring document before encrypting it; keys held by a key
+/// provider are checked when the file is loaded with the provider.
+fn validate_plaintext_keyring(plaintext: &str) -> Result<(), String> {
+    let keys = parse_keyring(plaintext, None)?;
+    KeySet::new(keys.into_iter().filter(|key| key.provider_key.is_none()).collect()).map(|_| ())
+}
+
+/// Encrypts a keyring document under an already derived or unwrapped file key.
+fn seal_keyring(plaintext: &str, encryption: &FileEncryption) -> Result<String, String> {
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let cipher = Aes256Gcm::new_from_slice(encryption.key.as_slice()).map_err(|e| e.to_string())?;
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+        .map_err(|_| "Keyring encryption failed".to_string())?;
+    let mut envelope = EncryptedKeyring {
+        encryption: "aes-256-gcm".to_string(),
+        kdf: String::new(),
+        iterations: None,
+        salt: None,
+        key_id: None,
+        wrapped_key: None,
+        nonce: BASE64.encode(nonce),
+        ciphertext: BASE64.encode(ciphertext),
+    };
+    match &encryption.protection {
+        FileKeyProtection::Passphrase { salt, iterations } => {
+            envelope.kdf = "pbkdf2-sha256".to_string();
+            envelope.iterations = Some(*iterations);
+            envelope.salt = Some(BASE64.encode(salt));
+        }
+        FileKeyProtection::Provider { kid, wrapped_key } => {
+            envelope.kdf = "key-provider".to_string();
+            envelope.key_id = Some(kid.clone());
+            envelope.wrapped_key = Some(BASE64.encode(wrapped_key));
+        }
+    }
+    serde_json::to_string_pretty(&envelope).map_err(|e| e.to_string())
+}
+
+fn decrypt_keyring(
+    document: Value,
+    passphrase: Option<&str>,
+    provider: Option<&dyn KeyProvider>,
+) -> Result<(Zeroizing<String>, FileEncryption), String> {
+    let envelope: EncryptedKeyring =
+        serde_json::from_value(document).map_err(|e| format!("Invalid encrypted keyring: {}", e))?;
+    let decode = |field: Option<&String>| {
+        let field = field.ok_or_else(|| format!("Encrypted keyring is missing a field for {}", envelope.kdf))?;
+        BASE64.decode(field).map_err(|_| "Invalid encrypted keyring encoding".to_string())
+    };
+    let (key, protection) = match (envelope.encryption.as_str(), envelope.kdf.as_str()) {
+        ("aes-256-gcm", "pbkdf2-sha256") => {
+            let passphrase = passphrase.ok_or_else(|| "Keyring is encrypted but no passphrase is set".to_string())?;
+            let salt = decode(envelope.salt.as_ref())?;
+            let iterations = envelope.iterations.ok_or_else(|| "Encrypted keyring is missing its iterations".to_string())?;
+            check_kdf_iterations(iterations)?;
+            (derive_file_key(passphrase, &salt, iterations), FileKeyProtection::Passphrase { salt, iterations })
+        }
+        ("aes-256-gcm", "key-provider") => {
+            let provider = provider.ok_or_else(|| "Keyring is encrypted by a key provider but none is configured".to_string())?;
+            let kid = envelope.key_id.clone().ok_or_else(|| "Encrypted keyring is missing its key_id".to_string())?;
+            let wrapped_key = decode(envelope.wrapped_key.as_ref())?;
+            let unwrapped = provider.unwrap(&kid, &wrapped_key).map_err(|e| format!("Failed to decrypt keyring: {}", e))?;
+            let key: [u8; 32] = unwrapped.expose().try_into().map_err(|_| "Invalid keyring file key length".to_string())?;
+            (Zeroizing::new(key), FileKeyProtection::Provider { kid, wrapped_key })
+        }
+        (encryption, kdf) => return Err(format!("Unsupported keyring encryption {} with {}", encryption, kdf)),
+    };
+    let (nonce, ciphertext) = (decode(Some(&envelope.nonce))?, decode(Some(&envelope.ciphertext))?);
+    if nonce.len() != 12 {
+        return Err("Invalid encrypted keyring nonce".to_string());
+    }
+    let cipher = Aes256Gcm::new_from_slice(key.as_slice()).map_err(|e| e.to_string())?;
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| "Failed to decrypt keyring: wrong passphrase or corrupted file".to_string())?;
+    let plaintext = String::from_utf8(plaintext).map_err(|e| {
+        let _wiped = Zeroizing::new(e.into_bytes());
+        "Decrypted keyring is not UTF-8".to_string()
+    })?;
+    Ok((Zeroizing::new(plaintext), FileEncryption { key, protection }))
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The date of a day count since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    (if month <= 2 { year_of_era + era * 400 + 1 } else { year_of_era + era * 400 }, month, day)
+}
+
+/// Parses an RFC 3339 date (`2025-01-15`) or date-time
+/// (`2025-01-15T08:30:00Z`, `2025-01-15T09:30:00.5+01:00`) into Unix seconds.
+pub fn parse_timestamp(text: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid date {}", text);
+    let number = |s: &str| s.parse::<i64>().map_err(|_| invalid());
+    let (date, time) = match text.split_once(['T', 't', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+    let mut fields = date.splitn(3, '-');
+    let (Some(year), Some(month), Some(day)) = (fields.next(), fields.next(), fields.next()) else {
+        return Err(invalid());
+    };
+    let (year, month, day) = (number(year)?, number(month)?, number(day)?);
+    let days = days_from_civil(year, month, day);
+    if civil_from_days(days) != (year, month, day) {
+        return Err(invalid());
+    }
+    let mut seconds = days * 86400;
+    if let Some(time) = time {
+        let (clock, offset) = match time.find(['Z', 'z', '+', '-']) {
+            Some(at) => time.split_at(at),
+            None => return Err(invalid()),
+        };
+        let clock = clock.split('.').next().unwrap_or_default();
+        let parts: Vec<i64> = clock.split(':').map(number).collect::<Result<_, _>>()?;
+        let [hour, minute, second] = parts[..] else { return Err(invalid()) };
+        if hour > 23 || minute > 59 || second > 60 {
+            return Err(invalid());
+        }
+        seconds += hour * 3600 + minute * 60 + second;
+        if let Some(sign) = offset.chars().next().filter(|c| *c == '+' || *c == '-') {
+            let (offset_hours, offset_minutes) = offset[1..].split_once(':').ok_or_else(invalid)?;
+            let offset = number(offset_hours)? * 3600 + number(offset_minutes)? * 60;
+            seconds -= if sign == '+' { offset } else { -offset };
+        } else if offset.len() != 1 {
+            return Err(invalid());
+        }
+    }
+    u64::try_from(seconds).map_err(|_| invalid())
+}
+
+/// Formats Unix seconds as an RFC 3339 UTC date-time.
+pub fn format_timestamp(seconds: u64) -> String {
+    let days = (seconds / 86400) as i64;
+    let (year, month, day) = civil_from_days(days);
+    let rest = seconds % 86400;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, rest / 3600, rest / 60 % 60, rest % 60)
+}
@@ -3,14 +3,39 @@
 
 use actix_web::{web, App, HttpResponse, HttpServer, Responder};
 use env_logger::Env;
-use log::info;
+use log::{error, info};
+use std::collections::HashSet;
 use std::env;
+use std::sync::Arc;
+use std::time::Duration;
 use dotenvy::dotenv;
 
 pub mod routes;
 pub mod crypto;
 pub mod models;
 pub mod middleware;
+pub mod config;
+pub mod config_file;
+pub mod nonces;
+pub mod audit;
+pub mod key_manager;
+pub mod reload;
+pub mod tenants;
+pub mod secrets;
+
+/// Registers the endpoints that can be scoped to a tenant, at the root and
+/// again under `/tenants/{tenant}`.
+pub fn data_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/encrypt", web::post().to(routes::encrypt))
+        .route("/decrypt", web::post().to(routes::decrypt))
+        .route("/sign", web::post().to(routes::sign))
+        .route("/verify", web::post().to(routes::verify))
+        .route("/sign/raw", web::post().to(routes::sign_raw))
+        .route("/verify/raw", web::post().to(routes::verify_raw))
+        .route("/merkle/prove", web::post().to(routes::merkle_prove))
+        .route("/merkle/verify", web::post().to(routes::merkle_verify))
+        .route("/http-signatures/sign", web::post().to(routes::sign_http_request));
+}
 
 /// Simple health check endpoint.
 /// Returns a 200 OK response with a JSON body `{"status": "a-ok"}`.
@@ -22,41 +47,191 @@ pub async fn health_check() -> impl Responder {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    // Load .env file
-    dotenv().ok();
+    /// Logs why the server cannot start and exits with status 1.
+    fn fail(context: &str, error: impl std::fmt::Display) -> ! {
+        error!("{}: {}", context, error);
+        std::process::exit(1)
+    }
+
+    // Load .env file, remembering what was set before it for reloads
+    let base_env: HashSet<String> = env::vars().map(|(name, _)| name).collect();
+    let env_file = dotenv().ok();
+
+    // The configuration file supplies what the environment does not set,
+    // including the log level, so it is read before the logger starts
+    let config_file = config_file::ConfigFile::from_vars(|name| env::var(name).ok());
+    let file_var = |name: &str| config_file.as_ref().ok().and_then(Option::as_ref).and_then(|file| file.get(name));
 
     // Initialize logger with more detailed configuration
     env_logger::Builder::from_env(Env::default()
-        .default_filter_or("info")
-        .default_write_style_or("always"))
+        .default_filter_or(file_var("RUST_LOG").unwrap_or_else(|| "info".to_string()))
+        .default_write_style_or(file_var("RUST_LOG_STYLE").unwrap_or_else(|| "always".to_string())))
         .format_timestamp_millis()
         .format_module_path(false)
         .init();
+    let config_file = config_file.unwrap_or_else(|e| fail("Invalid configuration file", e));
+
+    // Secrets may come from files and stdin as well as the environment
+    let secrets = secrets::Secrets::read(|name| env::var(name).ok())
+        .unwrap_or_else(|e| fail("Cannot read secrets", e));
+    let var = |name: &str| {
+        env::var(name)
+            .ok()
+            .or_else(|| secrets.get(name))
+            .or_else(|| config_file.as_ref().and_then(|file| file.get(name)))
+    };
+    let config = config_file::Config::from_vars(var)
+        .and_then(|config| config.validate().map(|()| config))
+        .unwrap_or_else(|e| fail("Invalid configuration", e));
+
+    // `riot-api --print-config` prints the configuration in effect, without
+    // secrets, once the keys and settings it describes are known to load
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--print-config") {
+        key_manager::KeyManager::from_vars(var).unwrap_or_else(|e| fail("Invalid key configuration", e));
+        config::Settings::from_vars(var).unwrap_or_else(|e| fail("Invalid configuration", e));
+        println!("{}", config.to_toml().unwrap_or_else(|e| fail("Cannot print configuration", e)));
+        return Ok(());
+    }
+
+    // `riot-api encrypt-keyring <file> [--provider-key <kid>]` prints an
+    // encrypted copy of a keyring file
+    if args.get(1).map(String::as_str) == Some("encrypt-keyring") {
+        let usage = "riot-api encrypt-keyring <keyring-file> [--provider-key <kid>]";
+        let Some(path) = args.get(2) else {
+            fail("Usage", usage);
+        };
+        let plaintext = std::fs::read_to_string(path)?;
+        let encrypted = match args.get(3).map(String::as_str) {
+            Some("--provider-key") => {
+                let kid = args.get(4).unwrap_or_else(|| fail("Usage", usage));
+                let provider = key_manager::key_provider_from_vars(var)
+                    .unwrap_or_else(|e| fail("Invalid key provider", e))
+                    .unwrap_or_else(|| fail("Cannot encrypt keyring", "KEY_PROVIDER must be set"));
+                key_manager::encrypt_keyring_with_provider(&plaintext, provider.as_ref(), kid)
+            }
+            Some(_) => fail("Usage", usage),
+            None => {
+                let passphrase = var("KEYRING_PASSPHRASE")
+                    .unwrap_or_else(|| fail("Cannot encrypt keyring", "KEYRING_PASSPHRASE must be set"));
+                key_manager::encrypt_keyring(&plaintext, &passphrase, key_manager::DEFAULT_KDF_ITERATIONS)
+            }
+        };
+        match encrypted {
+            Ok(encrypted) => println!("{}", encrypted),
+            Err(e) => fail("Failed to encrypt keyring", e),
+        }
+        return Ok(());
+    }
+
+    // `riot-api generate-key <type> [--store [--kid <kid>]]` prints a new random
+    // key, or adds it to the KEYRING_FILE as verify-only
+    if args.get(1).map(String::as_str) == Some("generate-key") {
+        let usage = "riot-api generate-key <hmac|aes|ed25519|p256> [--store [--kid <kid>]]";
+        let key_type: key_manager::KeyType = args
+            .get(2)
+            .and_then(|name| serde_json::from_value(serde_json::Value::from(name.as_str())).ok())
+            .unwrap_or_else(|| fail("Usage", usage));
+        let output = match args[3..].iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            [] => serde_json::to_value(models::GeneratedKey::random(key_type).unwrap_or_else(|e| fail("Failed to generate key", e))),
+            ["--store"] | ["--store", "--kid", _] => {
+                let keys = key_manager::KeyManager::from_vars(var)
+                    .unwrap_or_else(|e| fail("Invalid key configuration", e));
+                let key = keys
+                    .generate(args.get(5).cloned(), key_type, None, false)
+                    .unwrap_or_else(|e| fail("Failed to store key", e));
+                let kcv = keys.current().check_value(&key).ok();
+                serde_json::to_value(models::KeyInfo { kcv, ..models::KeyInfo::from(&key) })
+            }
+            _ => fail("Usage", usage),
+        };
+        println!("{}", serde_json::to_string_pretty(&output.unwrap_or_else(|e| fail("Failed to print key", e)))?);
+        return Ok(());
+    }
 
     info!("Starting Riot API server...");
+    match &config_file {
+        Some(file) => info!("Configuration file {} with the {} profile", file.path().display(), file.profile()),
+        None => info!("Configuration from the environment, {} profile", config.profile),
+    }
+
+    // Keep key material out of swap and core dumps; must precede key loading
+    if config.keys.lock_memory.unwrap_or(false) {
+        crypto::lock_secret_memory();
+        info!("Key material is locked into memory");
+    }
+
+    // Shared by all workers, which take a snapshot of the keys per request
+    let key_manager = web::Data::new(
+        key_manager::KeyManager::from_vars(var)
+            .unwrap_or_else(|e| fail("Invalid key configuration", e))
+    );
+    if let Some(provider) = key_manager.provider() {
+        info!("Master keys held by the {} key provider", provider.name());
+    }
+    let keys = key_manager.current();
+    info!("Signing with key {} ({} keys loaded)", keys.keyring().active().kid, keys.keys().len());
+    if keys.has_tenants() {
+        info!("Tenant scoping enabled");
+    }
+
+    // Shared by all workers, which read the current settings per request
+    let settings = web::Data::new(config::LiveSettings::new(
+        config::Settings::from_vars(var)
+            .unwrap_or_else(|e| fail("Invalid configuration", e))
+    ));
+    let current = settings.current();
+    info!("{} webhook profile(s) configured", current.webhooks.profiles.len());
+    if current.admin.token.is_some() && !key_manager.is_persistent() {
+        info!("Admin API enabled, but key changes need a KEYRING_FILE");
+    }
+
+    // Reload keys and settings on SIGHUP, and when a watched file changes
+    let poll_secs = config.server.reload_poll_secs.unwrap_or(5);
+    let reloader = reload::Reloader::new(key_manager.clone(), settings.clone(), base_env, env_file, &secrets)
+        .unwrap_or_else(|e| fail("Invalid reload configuration", e));
+    actix_web::rt::spawn(reload::watch(
+        Arc::new(reloader),
+        (poll_secs > 0).then(|| Duration::from_secs(poll_secs)),
+    ));
 
-    // Read config from environment variables
-    let port: u16 = env::var("PORT")
-        .unwrap_or_else(|_| "8080".to_string())
-        .parse()
-        .expect("PORT must be a valid number");
+    // Shared by all workers so a nonce seen by one is rejected by the others
+    let nonce_store = web::Data::new(
+        nonces::NonceStore::from_vars(var, crypto::unix_now())
+            .unwrap_or_else(|e| fail("Invalid nonce store configuration", e))
+    );
 
-    let hmac_secret_key = env::var("HMAC_SECRET_KEY")
-        .expect("HMAC_SECRET_KEY must be set");
+    // Shared by all workers so the chain has a single head
+    let audit_log = web::Data::new(
+        audit::AuditLog::from_vars(var)
+            .unwrap_or_else(|e| fail("Invalid audit log", e))
+    );
 
-    let hmac_secret_key_bytes = hmac_secret_key.into_bytes(); // Convert to bytes here
+    // Wipe the secrets read from files, now held by the keys and settings
+    drop(secrets);
 
-    HttpServer::new(move || {
+    let mut server = HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(hmac_secret_key_bytes.clone())) // Store key bytes in app data
+            .app_data(key_manager.clone()) // Store the keys in app data
+            .app_data(settings.clone())
+            .app_data(nonce_store.clone())
+            .app_data(audit_log.clone())
+            .wrap(middleware::HttpSignatures)
             .wrap(middleware::Logger)
             .route("/health", web::get().to(health_check))
-            .route("/encrypt", web::post().to(routes::encrypt))
-            .route("/decrypt", web::post().to(routes::decrypt))
-            .route("/sign", web::post().to(routes::sign))
-            .route("/verify", web::post().to(routes::verify))
-    })
-    .bind(("0.0.0.0", port))?
-    .run()
-    .await
+            .configure(data_routes)
+            .service(web::scope("/tenants/{tenant}").configure(data_routes))
+            .route("/webhooks/verify", web::post().to(routes::verify_webhook_request))
+            .route("/audit", web::get().to(routes::audit_entries))
+            .route("/.well-known/jwks.json", web::get().to(routes::jwks))
+            .route("/keys/generate", web::post().to(routes::generate_key_material))
+            .route("/admin/keys", web::get().to(routes::list_keys))
+            .route("/admin/keys", web::post().to(routes::generate_key))
+            .route("/admin/keys/{kid}/kcv", web::get().to(routes::key_check_value))
+            .route("/admin/keys/{kid}/{action}", web::post().to(routes::change_key_state))
+    });
+    if let Some(workers) = config.server.workers {
+        server = server.workers(workers);
+    }
+    server.bind(config.bind_address())?.run().await
 }
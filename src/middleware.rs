@@ -1,17 +1,27 @@
 //! Defines Actix web middleware for the application.
-//! Currently includes a logging middleware.
+//! Includes a logging middleware and verification of HTTP Message
+//! Signatures (RFC 9421).
 
 use actix_web::{
-    dev::{Service, ServiceRequest, ServiceResponse, Transform},
-    Error,
+    body::EitherBody,
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    web, Error, HttpMessage, HttpResponse,
 };
+use crate::config::{HttpSignatureMode, LiveSettings, Settings};
+use crate::crypto::{check_content_digest, unix_now, verify_message, HttpRequestParts, HttpSignatureError};
+use crate::key_manager::KeyManager;
+use crate::nonces::{NonceError, NonceStore};
+use crate::tenants::Tenant;
 use futures::future::{ok, Ready};
+use futures::StreamExt;
 use log::{info, warn};
 use std::time::Instant;
 use std::future::Future;
 use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
 use std::task::{Context, Poll};
-use actix_web::http::header::{USER_AGENT, CONTENT_TYPE};
+use actix_web::http::header::{USER_AGENT, CONTENT_TYPE, HOST};
 
 /// Actix middleware factory for logging requests and responses.
 ///
@@ -96,4 +106,200 @@ where
             Ok(res)
         })
     }
-} 
\ No newline at end of file
+}
+
+/// Actix middleware factory verifying HTTP Message Signatures (RFC 9421).
+///
+/// Its settings are the `http_signatures` of the `LiveSettings` in
+/// application data, read per request, or the defaults. Requests carrying `Signature` and `Signature-Input` headers are checked
+/// against the signing keys of the `KeyManager` in application data, those of
+/// the tenant for a request scoped to one; in `Required` mode, requests
+/// without them are refused too. Every signature must cover `@method`, the
+/// target (`@target-uri`, `@path` or `@request-target`) and, when there is a
+/// body, `content-digest`, which is checked against the body, read up to
+/// `SigningConfig::max_raw_body_bytes`. A `created` parameter is required,
+/// and a `nonce` is recorded in the `NonceStore`.
+///
+/// Verified signatures are stored in the request extensions as a
+/// `Vec<VerifiedHttpSignature>`.
+pub struct HttpSignatures;
+
+impl<S, B> Transform<S, ServiceRequest> for HttpSignatures
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = HttpSignaturesMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(HttpSignaturesMiddleware { service: Rc::new(service) })
+    }
+}
+
+/// The actual HTTP signature verification service.
+pub struct HttpSignaturesMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for HttpSignaturesMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let settings = req
+            .app_data::<web::Data<LiveSettings>>()
+            .map_or_else(|| Arc::new(Settings::default()), |s| s.current());
+
+        Box::pin(async move {
+            let config = &settings.http_signatures;
+            let signed = req.headers().contains_key("signature") || req.headers().contains_key("signature-input");
+            let exempt = config.exempt_paths.iter().any(|path| path == req.path());
+            let outcome = match config.mode {
+                HttpSignatureMode::Off => Ok(()),
+                HttpSignatureMode::Optional if !signed => Ok(()),
+                HttpSignatureMode::Required if !signed && exempt => Ok(()),
+                HttpSignatureMode::Required if !signed => Err(HttpSignatureError::Missing),
+                _ => verify_request(&mut req, &settings).await,
+            };
+            match outcome {
+                Ok(()) => service.call(req).await.map(ServiceResponse::map_into_left_body),
+                Err(e) => {
+                    warn!("{} {} - {}", req.method(), req.path(), e);
+                    let response = match e {
+                        HttpSignatureError::Invalid(_) | HttpSignatureError::Missing => HttpResponse::Unauthorized(),
+                        HttpSignatureError::BodyTooLarge(_) => HttpResponse::PayloadTooLarge(),
+                        _ => HttpResponse::BadRequest(),
+                    }
+                    .json(serde_json::json!({
+                        "error": e.to_string(),
+                        "code": e.code()
+                    }));
+                    Ok(req.into_response(response).map_into_right_body())
+                }
+            }
+        })
+    }
+}
+
+/// Verifies the signatures of `req`, putting its body back for the handler.
+async fn verify_request(req: &mut ServiceRequest, settings: &Settings) -> Result<(), HttpSignatureError> {
+    let (Some(signature_input), Some(signature)) = (header_value(req, "signature-input")?, header_value(req, "signature")?) else {
+        return Err(HttpSignatureError::Malformed("Signature and Signature-Input must be sent together".to_string()));
+    };
+    let tenant = Tenant::of_service_request(req).map_err(HttpSignatureError::Malformed)?;
+    let Some(keys) = req.app_data::<web::Data<KeyManager>>() else {
+        return Err(HttpSignatureError::Invalid("no keyring configured".to_string()));
+    };
+    let keyring = tenant.signing_keys(keys).map_err(HttpSignatureError::Invalid)?;
+    let skew = settings.signing.clock_skew_secs;
+
+    let body = read_body(req.take_payload(), settings.signing.max_raw_body_bytes).await?;
+    req.set_payload(Payload::from(body.clone()));
+
+    let parts = request_parts(req);
+    let verified = verify_message(&parts, &signature_input, &signature, &keyring)?;
+    let now = unix_now();
+    for signature in &verified {
+        let params = &signature.params;
+        if !params.covers("@method") {
+            return Err(HttpSignatureError::Uncovered("@method".to_string()));
+        }
+        if !["@target-uri", "@path", "@request-target"].iter().any(|c| params.covers(c)) {
+            return Err(HttpSignatureError::Uncovered("the request target".to_string()));
+        }
+        if !body.is_empty() && !params.covers("content-digest") {
+            return Err(HttpSignatureError::Uncovered("content-digest".to_string()));
+        }
+        params.check_time(now, skew, settings.http_signatures.max_age_secs)?;
+    }
+    if let Some(digest) = parts.header("content-digest") {
+        check_content_digest(&digest, &body)?;
+    }
+    for signature in &verified {
+        let Some(nonce) = signature.params.nonce() else { continue };
+        let Some(store) = req.app_data::<web::Data<NonceStore>>() else {
+            return Err(HttpSignatureError::Invalid("replay protection is not configured".to_string()));
+        };
+        let not_after = signature.params.created().map(|created| created + settings.http_signatures.max_age_secs + skew);
+        match store.check_and_record(nonce, now, not_after) {
+            Ok(()) => {}
+            Err(NonceError::Replayed) => return Err(HttpSignatureError::Invalid("nonce already used".to_string())),
+            Err(e) => return Err(HttpSignatureError::Malformed(e.to_string())),
+        }
+    }
+    info!(
+        "Verified HTTP message signature from key(s) {}",
+        verified.iter().map(|s| s.kid.as_str()).collect::<Vec<_>>().join(", ")
+    );
+    req.extensions_mut().insert(verified);
+    Ok(())
+}
+
+/// Reads the whole body, refusing more than `limit` bytes: the largest body
+/// any endpoint accepts, `SigningConfig::max_raw_body_bytes`.
+async fn read_body(mut payload: Payload, limit: usize) -> Result<web::Bytes, HttpSignatureError> {
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| HttpSignatureError::Malformed(format!("unreadable body: {}", e)))?;
+        if body.len() + chunk.len() > limit {
+            return Err(HttpSignatureError::BodyTooLarge(limit));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.freeze())
+}
+
+fn header_value(req: &ServiceRequest, name: &str) -> Result<Option<String>, HttpSignatureError> {
+    let values = req
+        .headers()
+        .get_all(name)
+        .map(|v| v.to_str().map(str::to_string))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| HttpSignatureError::Malformed(format!("{} is not valid ASCII", name)))?;
+    Ok((!values.is_empty()).then(|| values.join(", ")))
+}
+
+/// Collects the parts of `req` that signatures can cover.
+///
+/// The scheme and authority are those of the connection and the request
+/// itself. `Forwarded` and `X-Forwarded-*` headers are ignored, as any client
+/// can set them.
+fn request_parts(req: &ServiceRequest) -> HttpRequestParts {
+    let scheme = if req.app_config().secure() { "https" } else { "http" };
+    let authority = req
+        .uri()
+        .authority()
+        .map(|authority| authority.to_string())
+        .or_else(|| req.headers().get(HOST).and_then(|host| host.to_str().ok()).map(str::to_string))
+        .unwrap_or_else(|| req.app_config().host().to_string());
+    // Fields that are not valid ASCII cannot be covered, so they are left out
+    let headers = req
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str().to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    HttpRequestParts {
+        method: req.method().to_string(),
+        scheme: scheme.to_string(),
+        authority,
+        path: req.path().to_string(),
+        query: req.uri().query().map(str::to_string),
+        headers,
+    }
+}
@@ -2,15 +2,403 @@
 
 //! Defines data structures used for API request and response bodies.
 
-use serde::{Deserialize, Serialize};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
+use std::collections::BTreeMap;
+use crate::audit::{AuditEntry, AuditHead};
+use crate::crypto::{public_jwk, InclusionProof, KeyAlgorithm, KeyEntry, KeyState, SetSignature, SignatureClaims, ThresholdPolicy};
+use crate::key_manager::{format_timestamp, KeyPurpose, KeyType, ManagedKey};
 
 /// Only the `/verify` uses this, as others simply use `serde_json::Value`.
 /// Represents the expected JSON structure for the `/verify` endpoint request body.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct VerifyRequest {
-    /// The original data that was signed.
-    pub data: Value,
-    /// The HMAC signature to be verified.
+    /// The original data that was signed. Required unless a compact `jws` or
+    /// an attached `cose` message carries it. A present `null` is data.
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    /// The HMAC signature to be verified. Required unless `jws`, `cose` or
+    /// `signatures` is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// A JWS returned by `/sign?format=jws` or `format=jws-detached`, verified
+    /// instead of `signature`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jws: Option<String>,
+    /// Id of the key that produced the signature, as returned by `/sign`.
+    /// When omitted, every key that is not retired is tried.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    /// A Base64 COSE message returned by `/sign?format=cose`, verified
+    /// instead of `signature`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cose: Option<String>,
+    /// A signature set returned by `/sign?format=set`, verified instead of `signature`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signatures: Option<Vec<SetSignature>>,
+    /// How many members of `signatures` must be valid; all of them by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<ThresholdPolicy>,
+    /// Claims returned by `/sign`, which are part of the signed bytes.
+    #[serde(flatten)]
+    pub claims: SignatureClaims,
+}
+
+/// Deserializes a field that is present as `Some`, even when it is `null`;
+/// only a missing field, through `#[serde(default)]`, is `None`.
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+/// Response body of `/sign`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignResponse {
+    /// The Base64 HMAC signature.
+    pub signature: String,
+    /// Id of the key that produced the signature.
+    pub kid: String,
+    /// Claims bound into the signature; must be sent back to `/verify`.
+    #[serde(flatten)]
+    pub claims: SignatureClaims,
+}
+
+/// Response body of `/sign?format=set`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignatureSetResponse {
+    /// One signature per configured signer.
+    pub signatures: Vec<SetSignature>,
+    /// Claims bound into every signature; must be sent back to `/verify`.
+    #[serde(flatten)]
+    pub claims: SignatureClaims,
+}
+
+/// Response body of `/sign` when a JWS is requested.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JwsResponse {
+    /// The JWS in compact serialization; the payload segment is empty when detached.
+    pub jws: String,
+    /// Id of the key that produced the signature.
+    pub kid: String,
+    /// JOSE algorithm of the signature.
+    pub alg: KeyAlgorithm,
+}
+
+/// Response body of `/sign?format=cose`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CoseResponse {
+    /// The tagged COSE message, standard Base64 encoded.
+    pub cose: String,
+    /// Id of the key that produced the message.
+    pub kid: String,
+    /// Algorithm of the key.
+    pub alg: KeyAlgorithm,
+}
+
+/// Output format of `/sign`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SignFormat {
+    /// Riot's Base64 HMAC signature.
+    #[default]
+    Signature,
+    /// JWS compact serialization with the payload embedded (RFC 7515).
+    Jws,
+    /// JWS with a detached, unencoded payload (RFC 7797).
+    JwsDetached,
+    /// The signed object itself, with the signature under a reserved property.
+    Embedded,
+    /// A signed Merkle root, for later selective disclosure via `/merkle/prove`.
+    Merkle,
+    /// One signature per configured signer, for threshold verification.
+    Set,
+    /// A COSE_Mac0 (HS256) or COSE_Sign1 (ES256, EdDSA) message over
+    /// deterministic CBOR (RFC 9052).
+    Cose,
+}
+
+/// Shape of the `/verify` request body.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VerifyFormat {
+    /// A `VerifyRequest` with `data` and `signature`.
+    #[default]
+    Request,
+    /// A document returned by `/sign?format=embedded`.
+    Embedded,
+}
+
+/// Query parameters accepted by `/verify`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct VerifyOptions {
+    /// Shape of the request body.
+    #[serde(default)]
+    pub format: VerifyFormat,
+}
+
+/// Query parameters accepted by `/sign`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SignOptions {
+    /// Output format; a plain signature by default.
+    #[serde(default)]
+    pub format: SignFormat,
+    /// Key algorithm for JWS and COSE output; `HS256` by default.
+    pub alg: Option<KeyAlgorithm>,
+    /// Include the issue time, even without `exp` or `nbf`.
+    #[serde(default)]
+    pub iat: bool,
+    /// Absolute expiry, as a Unix timestamp in seconds.
+    pub exp: Option<u64>,
+    /// Expiry relative to now, in seconds. Cannot be combined with `exp`.
+    pub expires_in: Option<u64>,
+    /// Start of validity, as a Unix timestamp in seconds.
+    pub nbf: Option<u64>,
+    /// Embed a random nonce so the signature can only be verified once.
+    #[serde(default)]
+    pub nonce: bool,
+    /// JSON Pointers of the only fields to sign, one `cover` parameter each.
+    /// Filled in by `/sign` itself, as a struct field only takes one value.
+    #[serde(skip)]
+    pub cover: Vec<String>,
+    /// Comma-separated key ids to sign a set with; all configured signers by default.
+    pub signers: Option<String>,
+}
+
+/// A signed Merkle root, returned by `/sign?format=merkle`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MerkleSignature {
+    /// Hex-encoded Merkle root of the document.
+    pub root: String,
+    /// Number of leaves in the tree.
+    pub leaf_count: usize,
+    /// HMAC signature over the root and leaf count.
     pub signature: String,
+    /// Id of the key that produced the signature.
+    pub kid: String,
+}
+
+/// Request body of `/merkle/prove`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MerkleProveRequest {
+    /// The full document that was signed.
+    pub data: Value,
+    /// JSON Pointers to disclose; each covers every leaf below it.
+    pub pointers: Vec<String>,
+    /// Key to sign the root with. The root is signed anew, so this must be
+    /// the active key, which is also the default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+}
+
+/// A signed root plus inclusion proofs: returned by `/merkle/prove` and
+/// accepted by `/merkle/verify`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MerkleDisclosure {
+    #[serde(flatten)]
+    pub signature: MerkleSignature,
+    /// Proofs for the disclosed leaves.
+    pub proofs: Vec<InclusionProof>,
+}
+
+/// Request body of `/http-signatures/sign`: the request a client is about to send.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HttpSignRequest {
+    /// Request method, e.g. `POST`.
+    pub method: String,
+    /// Absolute URL the request is sent to.
+    pub url: String,
+    /// Header fields the request will carry.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Request body as text; a `Content-Digest` is added when present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Components to cover; `@method`, `@target-uri` and, with a body,
+    /// `content-digest` by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub components: Option<Vec<String>>,
+    /// Key algorithm; `HS256` by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alg: Option<KeyAlgorithm>,
+    /// Lifetime of the signature in seconds, bound as `expires`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_in: Option<u64>,
+    /// Bind a random nonce so the signed request is accepted only once.
+    #[serde(default)]
+    pub nonce: bool,
+    /// Signature label; `sig1` by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// Response body of `/http-signatures/sign`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HttpSignResponse {
+    /// Header fields to attach to the request: `Signature-Input`, `Signature`
+    /// and, when a body was given, `Content-Digest`.
+    pub headers: BTreeMap<String, String>,
+    /// The exact text that was signed, to help debug verification failures.
+    pub signature_base: String,
+    /// Id of the key that produced the signature.
+    pub kid: String,
+}
+
+/// Query parameters accepted by `/webhooks/verify`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WebhookOptions {
+    /// Name of the provider profile; may be omitted when only one is configured.
+    pub profile: Option<String>,
+}
+
+/// Where `/sign/raw` returns the signature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RawOutput {
+    /// A `SignResponse` body, plus the signature headers.
+    #[default]
+    Json,
+    /// Only the `X-Signature` and `X-Signature-Kid` headers, with no body.
+    Header,
+}
+
+/// Query parameters accepted by `/sign/raw`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RawSignOptions {
+    #[serde(default)]
+    pub output: RawOutput,
+}
+
+/// Query parameters accepted by `/verify/raw`; the `X-Signature` and
+/// `X-Signature-Kid` headers take precedence.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RawVerifyOptions {
+    /// The signature returned by `/sign/raw`.
+    pub signature: Option<String>,
+    /// Id of the key that produced the signature.
+    pub kid: Option<String>,
+}
+
+/// Query parameters accepted by `/audit`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AuditQuery {
+    /// `seq` of the first entry to return; 0 by default.
+    #[serde(default)]
+    pub from: u64,
+    /// Largest number of entries to return; 100 by default, at most 1000.
+    pub limit: Option<usize>,
+}
+
+/// Response body of `/audit`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditPage {
+    /// The requested entries, in chain order.
+    pub entries: Vec<AuditEntry>,
+    /// Entry count and last hash of the whole chain when the page was read.
+    pub head: AuditHead,
+}
+
+/// A key as listed by the admin API; never includes the key material.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyInfo {
+    pub kid: String,
+    #[serde(rename = "type")]
+    pub key_type: KeyType,
+    pub purposes: Vec<KeyPurpose>,
+    pub state: KeyState,
+    /// RFC 3339 creation time, when known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    /// Master key of the key provider holding the material, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider_key: Option<String>,
+    /// Key check value, a non-secret fingerprint of the material.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kcv: Option<String>,
+}
+
+impl From<&ManagedKey> for KeyInfo {
+    fn from(key: &ManagedKey) -> Self {
+        KeyInfo {
+            kid: key.kid.clone(),
+            key_type: key.key_type,
+            purposes: key.purposes.clone(),
+            state: key.state,
+            created: key.created_at.map(format_timestamp),
+            provider_key: key.provider_key.clone(),
+            kcv: None,
+        }
+    }
+}
+
+/// Response body of the `/admin/keys` endpoints.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyList {
+    pub keys: Vec<KeyInfo>,
+}
+
+/// Request body of `POST /admin/keys`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GenerateKeyRequest {
+    #[serde(rename = "type")]
+    pub key_type: KeyType,
+    /// Id of the new key; generated from the type and date when omitted.
+    pub kid: Option<String>,
+    /// Defaults to signing, or encryption for AES keys.
+    pub purposes: Option<Vec<KeyPurpose>>,
+    /// Activate the key immediately instead of adding it as verify-only.
+    #[serde(default)]
+    pub activate: bool,
+}
+
+/// Response body of `GET /admin/keys/{kid}/kcv`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyCheckValue {
+    pub kid: String,
+    #[serde(rename = "type")]
+    pub key_type: KeyType,
+    pub kcv: String,
+}
+
+/// Request body of `POST /keys/generate`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyGenerationRequest {
+    #[serde(rename = "type")]
+    pub key_type: KeyType,
+}
+
+/// Response body of `POST /keys/generate`: a new key, returned once and
+/// stored nowhere.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GeneratedKey {
+    #[serde(rename = "type")]
+    pub key_type: KeyType,
+    /// Standard Base64 key material, as a keyring file takes it.
+    pub secret: String,
+    /// Key check value, to compare with `GET /admin/keys/{kid}/kcv` once
+    /// the key is deployed.
+    pub kcv: String,
+    /// Public JWK of Ed25519 and P-256 keys, without a `kid`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_jwk: Option<Value>,
+}
+
+impl GeneratedKey {
+    /// A random key of `key_type`, of the right size for the type.
+    pub fn random(key_type: KeyType) -> Result<Self, String> {
+        let key = ManagedKey::new("generated", key_type, key_type.generate_material(), KeyState::VerifyOnly);
+        let kcv = key.check_value()?;
+        let public_jwk = match key_type.algorithm() {
+            Some(algorithm) => {
+                let entry = KeyEntry::for_algorithm(key.kid.clone(), algorithm, key.material.clone(), key.state);
+                public_jwk(&entry)?.map(|mut jwk| {
+                    if let Some(members) = jwk.as_object_mut() {
+                        members.remove("kid");
+                    }
+                    jwk
+                })
+            }
+            None => None,
+        };
+        Ok(GeneratedKey { key_type, secret: BASE64.encode(key.material.expose()), kcv, public_jwk })
+    }
 }
@@ -0,0 +1,164 @@
+//! Replay protection for `/verify`.
+//! Remembers the nonces of signatures that have already been verified, for a
+//! limited time, optionally persisting them to an append-only file so that a
+//! restart does not reopen the replay window.
+
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use log::warn;
+use rand::RngCore;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Longest nonce accepted from clients.
+const MAX_NONCE_LEN: usize = 128;
+
+/// Generates a random 128-bit nonce, URL-safe Base64 encoded.
+pub fn generate_nonce() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Why a nonce was not accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NonceError {
+    /// The nonce was already seen within the replay window.
+    Replayed,
+    /// The nonce is empty, too long or uses characters outside `[A-Za-z0-9_-]`.
+    Malformed,
+    /// The signature carrying the nonce has no `exp`, so the nonce would be
+    /// forgotten while the signature is still valid.
+    NoExpiry,
+    /// The nonce could not be persisted.
+    Storage(String),
+}
+
+impl NonceError {
+    /// Stable machine-readable code returned by the API.
+    pub fn code(&self) -> &'static str {
+        match self {
+            NonceError::Replayed => "nonce_replayed",
+            NonceError::Malformed => "nonce_malformed",
+            NonceError::NoExpiry => "nonce_without_expiry",
+            NonceError::Storage(_) => "nonce_storage_failed",
+        }
+    }
+}
+
+impl fmt::Display for NonceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NonceError::Replayed => write!(f, "Nonce already used"),
+            NonceError::Malformed => write!(f, "Malformed nonce"),
+            NonceError::NoExpiry => write!(f, "A signature with a nonce must have an exp"),
+            NonceError::Storage(e) => write!(f, "Failed to record nonce: {}", e),
+        }
+    }
+}
+
+/// Nonces seen by `/verify`, each remembered until its expiry time.
+///
+/// One store must be shared by all workers, so create it once and register the
+/// same `web::Data` in every `App`.
+pub struct NonceStore {
+    seen: Mutex<HashMap<String, u64>>,
+    ttl_secs: u64,
+    path: Option<PathBuf>,
+}
+
+impl NonceStore {
+    /// In-memory store remembering nonces for `ttl_secs`.
+    pub fn new(ttl_secs: u64) -> Self {
+        NonceStore { seen: Mutex::new(HashMap::new()), ttl_secs, path: None }
+    }
+
+    /// Store backed by an append-only file of `<expiry> <nonce>` lines.
+    ///
+    /// Entries still live at `now` are loaded and the file is compacted.
+    pub fn open(path: impl AsRef<Path>, ttl_secs: u64, now: u64) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let mut seen = HashMap::new();
+        if path.exists() {
+            let file = File::open(&path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+            for line in BufReader::new(file).lines() {
+                let line = line.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                let Some((expiry, nonce)) = line.split_once(' ') else { continue };
+                match expiry.parse::<u64>() {
+                    Ok(expiry) if expiry > now => {
+                        seen.insert(nonce.to_string(), expiry);
+                    }
+                    Ok(_) => {}
+                    Err(_) => warn!("Skipping malformed line in nonce store {}", path.display()),
+                }
+            }
+        }
+        let compacted: String = seen.iter().map(|(nonce, expiry)| format!("{} {}\n", expiry, nonce)).collect();
+        fs::write(&path, compacted).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        Ok(NonceStore { seen: Mutex::new(seen), ttl_secs, path: Some(path) })
+    }
+
+    /// Builds the store from environment variables.
+    ///
+    /// - `NONCE_TTL_SECS`: how long a nonce is remembered (default 300).
+    /// - `NONCE_STORE_PATH`: optional file to persist nonces to.
+    pub fn from_env(now: u64) -> Result<Self, String> {
+        NonceStore::from_vars(|name| env::var(name).ok(), now)
+    }
+
+    /// Builds the store like `from_env`, reading variables through `var`.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>, now: u64) -> Result<Self, String> {
+        let ttl_secs = match var("NONCE_TTL_SECS") {
+            Some(ttl) => ttl.parse().map_err(|_| "NONCE_TTL_SECS must be a number of seconds".to_string())?,
+            None => 300,
+        };
+        match var("NONCE_STORE_PATH") {
+            Some(path) => NonceStore::open(path, ttl_secs, now),
+            None => Ok(NonceStore::new(ttl_secs)),
+        }
+    }
+
+    /// Records `nonce` as used, failing if it was already used and has not expired.
+    ///
+    /// The nonce is remembered for the configured TTL, or until `not_after`
+    /// if that is later (typically the signature's `exp` plus clock skew).
+    pub fn check_and_record(&self, nonce: &str, now: u64, not_after: Option<u64>) -> Result<(), NonceError> {
+        let well_formed = !nonce.is_empty()
+            && nonce.len() <= MAX_NONCE_LEN
+            && nonce.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if !well_formed {
+            return Err(NonceError::Malformed);
+        }
+
+        let mut seen = self.seen.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        seen.retain(|_, expiry| *expiry > now);
+        if seen.contains_key(nonce) {
+            return Err(NonceError::Replayed);
+        }
+        let expiry = now.saturating_add(self.ttl_secs).max(not_after.unwrap_or(0));
+        if let Some(path) = &self.path {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{} {}", expiry, nonce))
+                .map_err(|e| NonceError::Storage(e.to_string()))?;
+        }
+        seen.insert(nonce.to_string(), expiry);
+        Ok(())
+    }
+
+    /// Number of nonces currently remembered, expired or not.
+    pub fn len(&self) -> usize {
+        self.seen.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).len()
+    }
+
+    /// True when no nonce is remembered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
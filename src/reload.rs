@@ -0,0 +1,205 @@
+//! Hot reload of keys and settings.
+//! On SIGHUP, or when the `.env` file, the configuration file, the keyring
+//! file or a secret file changes, the variables are read again and new keys and `Settings` are
+//! swapped in for all workers at once. Requests in flight finish with the snapshot they
+//! took; an invalid change is logged and the running configuration kept.
+
+use actix_web::web;
+use log::{error, info, warn};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::config::{LiveSettings, Settings};
+use crate::config_file::{Config, ConfigFile};
+use crate::key_manager::KeyManager;
+use crate::secrets::Secrets;
+
+/// Variables only read at startup; changing them requires a restart.
+const STARTUP_VARIABLES: &[&str] = &[
+    "PORT",
+    "KEYRING_FILE",
+    "NONCE_TTL_SECS",
+    "NONCE_STORE_PATH",
+    "AUDIT_LOG_PATH",
+    "RELOAD_POLL_SECS",
+    "LOCK_KEY_MEMORY",
+    "KEY_PROVIDER",
+    "KEY_PROVIDER_FILE",
+    "SIGNING_ALGORITHMS",
+    "BIND_ADDRESS",
+    "WORKERS",
+    "RUST_LOG",
+    "RUST_LOG_STYLE",
+    "CONFIG_FILE",
+    "CONFIG_PROFILE",
+];
+
+/// Reloads the keys and settings shared by all workers.
+pub struct Reloader {
+    keys: web::Data<KeyManager>,
+    settings: web::Data<LiveSettings>,
+    /// Names set in the process environment before the `.env` file was
+    /// loaded, which take precedence over the file as they do at startup.
+    /// Only the names are kept; the values are read again on reload, so that
+    /// no copy of a secret outlives it.
+    base: HashSet<String>,
+    /// The `.env` file, read again on every reload.
+    env_file: Option<PathBuf>,
+    /// Values of `STARTUP_VARIABLES` when the server started.
+    startup: Vec<(&'static str, Option<String>)>,
+    /// The secret read from standard input at startup, which reloads keep.
+    stdin: Secrets,
+    /// Files secrets were read from at the last reload.
+    secret_files: Mutex<Vec<PathBuf>>,
+    /// The configuration file read at the last reload.
+    config_file: Mutex<Option<PathBuf>>,
+    /// Serializes reloads triggered by the signal and by the file watcher.
+    lock: Mutex<()>,
+}
+
+impl Reloader {
+    /// `base` names the variables set before `env_file` was loaded, and
+    /// `secrets` are those the server started with.
+    pub fn new(
+        keys: web::Data<KeyManager>,
+        settings: web::Data<LiveSettings>,
+        base: HashSet<String>,
+        env_file: Option<PathBuf>,
+        secrets: &Secrets,
+    ) -> Result<Self, String> {
+        let mut reloader = Reloader {
+            keys,
+            settings,
+            base,
+            env_file,
+            startup: Vec::new(),
+            stdin: secrets.kept_for_reload(),
+            secret_files: Mutex::new(Vec::new()),
+            config_file: Mutex::new(None),
+            lock: Mutex::new(()),
+        };
+        let vars = reloader.vars()?;
+        let file = ConfigFile::from_vars(|name| vars.get(name).cloned())?;
+        let var = |name: &str| {
+            vars.get(name).cloned().or_else(|| secrets.get(name)).or_else(|| file.as_ref().and_then(|file| file.get(name)))
+        };
+        reloader.startup = STARTUP_VARIABLES.iter().map(|name| (*name, var(name))).collect();
+        reloader.secret_files = Mutex::new(secrets.files().to_vec());
+        reloader.config_file = Mutex::new(file.map(|file| file.path().to_path_buf()));
+        Ok(reloader)
+    }
+
+    /// The variables as the server would see them if it started now.
+    fn vars(&self) -> Result<HashMap<String, String>, String> {
+        let mut vars: HashMap<String, String> = env::vars().filter(|(name, _)| self.base.contains(name)).collect();
+        if let Some(path) = &self.env_file {
+            let entries = dotenvy::from_path_iter(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            for entry in entries {
+                let (name, value) = entry.map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+                vars.entry(name).or_insert(value);
+            }
+        }
+        Ok(vars)
+    }
+
+    /// Reads the variables again and swaps in the new keys and settings,
+    /// both or neither. Returns what changed.
+    pub fn reload(&self) -> Result<Vec<String>, String> {
+        let _reloading = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let vars = self.vars()?;
+        let secrets = Secrets::reread(|name| vars.get(name).cloned(), &self.stdin)?;
+        *self.secret_files.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = secrets.files().to_vec();
+        let file = ConfigFile::from_vars(|name| vars.get(name).cloned())?;
+        *self.config_file.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = file.as_ref().map(|file| file.path().to_path_buf());
+        let var = |name: &str| {
+            vars.get(name).cloned().or_else(|| secrets.get(name)).or_else(|| file.as_ref().and_then(|file| file.get(name)))
+        };
+        // Validate the settings before the keys are swapped, so that an
+        // invalid change leaves both untouched
+        Config::from_vars(var)?.validate()?;
+        let settings = Settings::from_vars(var)?;
+        let mut changes = self.keys.reload(var)?;
+        changes.extend(self.settings.replace(settings));
+        for (name, value) in &self.startup {
+            if var(name) != *value {
+                changes.push(format!("{} changed, which takes effect after a restart", name));
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Reloads and logs the outcome.
+    pub fn reload_and_log(&self, reason: &str) {
+        match self.reload() {
+            Ok(changes) if changes.is_empty() => info!("Reloaded after {}: no changes", reason),
+            Ok(changes) => {
+                for change in changes {
+                    info!("Reloaded after {}: {}", reason, change);
+                }
+            }
+            Err(e) => error!("Reload after {} failed, keeping the current keys and settings: {}", reason, e),
+        }
+    }
+
+    /// Files whose changes trigger a reload.
+    fn watched_files(&self) -> Vec<PathBuf> {
+        let secret_files = self.secret_files.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        let config_file = self.config_file.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        self.env_file.iter().cloned().chain(config_file).chain(self.keys.path()).chain(secret_files).collect()
+    }
+
+    /// Digests of the watched files, `None` for unreadable ones.
+    fn fingerprint(&self) -> Vec<Option<Vec<u8>>> {
+        self.watched_files()
+            .iter()
+            .map(|path| fs::read(path).ok().map(|contents| Sha256::digest(contents).to_vec()))
+            .collect()
+    }
+}
+
+/// Reloads on SIGHUP and, when `poll` is given, whenever a watched file's
+/// contents change. Runs until the server stops.
+///
+/// Reloading may derive a keyring file key, which is slow by design, so it
+/// runs on the blocking thread pool.
+pub async fn watch(reloader: Arc<Reloader>, poll: Option<Duration>) {
+    let run = |reloader: Arc<Reloader>, reason: &'static str| async move {
+        if let Err(e) = actix_web::rt::task::spawn_blocking(move || reloader.reload_and_log(reason)).await {
+            error!("Reload after {} panicked: {}", reason, e);
+        }
+    };
+
+    if let Some(poll) = poll {
+        let reloader = reloader.clone();
+        actix_web::rt::spawn(async move {
+            let mut fingerprint = reloader.fingerprint();
+            let mut interval = actix_web::rt::time::interval(poll);
+            loop {
+                interval.tick().await;
+                let current = reloader.fingerprint();
+                if current != fingerprint {
+                    fingerprint = current;
+                    run(reloader.clone(), "a file change").await;
+                }
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    {
+        use actix_web::rt::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::hangup()) {
+            Ok(mut hangup) => {
+                while hangup.recv().await.is_some() {
+                    run(reloader.clone(), "SIGHUP").await;
+                }
+            }
+            Err(e) => warn!("Cannot listen for SIGHUP: {}", e),
+        }
+    }
+}
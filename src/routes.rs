@@ -3,23 +3,63 @@
 //! processing, calls the appropriate cryptographic functions, and
 //! constructs the HTTP response.
 
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{http::Uri, web, HttpMessage, HttpRequest, HttpResponse, Responder};
 use serde_json::Value;
-use crate::crypto::{encrypt_data, decrypt_data, sign_data, verify_signature};
-use crate::models::VerifyRequest;
+use crate::audit::{AuditLog, AuditRecord};
+use crate::config::{AdminConfig, LiveSettings, Settings, SigningConfig};
+use crate::key_manager::{KeyAdminError, KeyManager, KeySet, ManagedKey};
+use crate::crypto::{
+    canonicalize_json, compute, content_digest, decode_cbor, encrypt_data, decrypt_data, decrypt_data_with_key, encrypt_data_with_key, http_signature_alg, merkle_signing_input,
+    parse_json, project_coverage, public_jwk, sign_cose, sign_jws, sign_message, sign_raw_bytes, sign_set, sign_with_claims, signature_base,
+    unix_now, verify_bytes, verify_cose, verify_raw_bytes, verify_inclusion, verify_jws, verify_set, verify_webhook, verify_with_claims,
+    CborValue, HttpRequestParts, KeyAlgorithm, KeyEntry, KeyState, Keyring, MerkleTree, ParamValue, SetSignature,
+    SignatureClaims, SignatureParams, ThresholdPolicy, WebhookError,
+};
+use crate::models::{
+    AuditPage, AuditQuery, CoseResponse, GenerateKeyRequest, GeneratedKey, HttpSignRequest, HttpSignResponse, JwsResponse, MerkleDisclosure, MerkleProveRequest, MerkleSignature,
+    KeyCheckValue, KeyGenerationRequest, KeyInfo, KeyList, RawOutput, RawSignOptions, RawVerifyOptions, SignFormat, SignOptions, SignResponse, SignatureSetResponse,
+    VerifyFormat, VerifyOptions, VerifyRequest, WebhookOptions,
+};
+use base64::{Engine as _, engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD}};
+use serde::de::DeserializeOwned;
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use crate::nonces::{generate_nonce, NonceError, NonceStore};
+use crate::tenants::Tenant;
 use log::{info, warn, error};
 
+/// Header carrying the signature of a raw body.
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+/// Header carrying the id of the key that signed a raw body.
+pub const SIGNATURE_KID_HEADER: &str = "X-Signature-Kid";
 /// Handles POST requests to `/encrypt`.
 ///
 /// Takes a JSON object in the request body, encrypts its top-level values
-/// using Base64 encoding, and returns the modified JSON object.
+/// using Base64 encoding, and returns the modified JSON object. When the
+/// request is scoped to a tenant, each value is instead encrypted with
+/// AES-256-GCM under the tenant's derived key.
 ///
 /// # Errors
-/// Returns a 400 Bad Request if the input is not a valid JSON object or if
-/// encryption fails internally.
-pub async fn encrypt(data: web::Json<Value>) -> impl Responder {
+/// Returns a 400 Bad Request if the input is not a valid JSON object, the
+/// tenant is invalid or has no keys, or if encryption fails internally.
+pub async fn encrypt(
+    data: web::Json<Value>,
+    tenant: Tenant,
+    key_manager: Option<web::Data<KeyManager>>,
+) -> impl Responder {
     info!("Received encryption request");
-    match encrypt_data(&data.into_inner()) {
+    let keys = match tenant.encryption_keys(key_manager.as_ref().map(|k| k.get_ref())) {
+        Ok(keys) => keys,
+        Err(response) => return response,
+    };
+    let data = data.into_inner();
+    let result = match &keys {
+        Some(keys) => encrypt_data_with_key(&data, &keys[0]),
+        None => encrypt_data(&data),
+    };
+    match result {
         Ok(encrypted) => {
             info!("Successfully encrypted data");
             HttpResponse::Ok().json(encrypted)
@@ -38,13 +78,29 @@ pub async fn encrypt(data: web::Json<Value>) -> impl Responder {
 /// Takes a JSON object in the request body, attempts to decrypt any Base64-encoded
 /// string values at the top level, and returns the modified JSON object.
 /// Non-string values or strings that are not valid Base64 are preserved.
+/// When the request is scoped to a tenant, every value must have been
+/// encrypted for that tenant.
 ///
 /// # Errors
-/// Returns a 400 Bad Request if the input is not a valid JSON object or if
-/// decryption fails internally (e.g., decoding error).
-pub async fn decrypt(data: web::Json<Value>) -> impl Responder {
+/// Returns a 400 Bad Request if the input is not a valid JSON object, the
+/// tenant is invalid or has no keys, or if decryption fails (e.g., decoding
+/// error, or a value encrypted for another tenant).
+pub async fn decrypt(
+    data: web::Json<Value>,
+    tenant: Tenant,
+    key_manager: Option<web::Data<KeyManager>>,
+) -> impl Responder {
     info!("Received decryption request");
-    match decrypt_data(&data.into_inner()) {
+    let keys = match tenant.encryption_keys(key_manager.as_ref().map(|k| k.get_ref())) {
+        Ok(keys) => keys,
+        Err(response) => return response,
+    };
+    let data = data.into_inner();
+    let result = match &keys {
+        Some(keys) => decrypt_data_with_key(&data, keys),
+        None => decrypt_data(&data),
+    };
+    match result {
         Ok(decrypted) => {
             info!("Successfully decrypted data");
             HttpResponse::Ok().json(decrypted)
@@ -58,58 +114,826 @@ pub async fn decrypt(data: web::Json<Value>) -> impl Responder {
     }
 }
 
+/// Returns the current settings, or the defaults when none are registered.
+fn current_settings(settings: Option<web::Data<LiveSettings>>) -> Arc<Settings> {
+    settings.map_or_else(|| Arc::new(Settings::default()), |s| s.current())
+}
+
+/// Parses a signing request body, applying the configured duplicate key policy.
+///
+/// Returns a ready-made 400 response when the body is not acceptable JSON.
+fn parse_signed_body(body: &[u8], config: &SigningConfig) -> Result<Value, HttpResponse> {
+    parse_json(body, config.duplicate_keys).map_err(|e| {
+        warn!("Rejected request body: {}", e);
+        HttpResponse::BadRequest().json(serde_json::json!({
+            "error": e
+        }))
+    })
+}
+
+/// Rejects a body not declared as JSON, as `web::Json` does: the content
+/// type must be `application/json` or end in `+json`.
+fn require_json(req: &HttpRequest) -> Result<(), HttpResponse> {
+    match req.mime_type() {
+        Ok(Some(mime)) if mime.subtype() == "json" || mime.suffix().is_some_and(|suffix| suffix == "json") => Ok(()),
+        _ => {
+            warn!("Rejected request body without a JSON content type");
+            Err(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Content-Type must be application/json"
+            })))
+        }
+    }
+}
+
+/// Parses a request body into `T`, applying the configured duplicate key policy.
+fn parse_signed_request<T: DeserializeOwned>(body: &[u8], config: &SigningConfig) -> Result<T, HttpResponse> {
+    let value = parse_signed_body(body, config)?;
+    serde_json::from_value(value).map_err(|e| {
+        warn!("Malformed request: {}", e);
+        HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Invalid request: {}", e)
+        }))
+    })
+}
+
+/// Turns `/sign` query parameters into the claims to bind into the signature.
+///
+/// `iat` is set whenever any time claim is requested. A fresh nonce is
+/// generated when `nonce` is requested, and `cover` becomes the coverage list.
+fn claims_from_options(options: &SignOptions, now: u64) -> Result<SignatureClaims, String> {
+    let exp = match (options.exp, options.expires_in) {
+        (Some(_), Some(_)) => return Err("Use either exp or expires_in, not both".to_string()),
+        (Some(exp), None) => Some(exp),
+        (None, Some(seconds)) => Some(now.saturating_add(seconds)),
+        (None, None) => None,
+    };
+    if options.nonce && exp.is_none() {
+        // The nonce store only has to remember a nonce while its signature is valid
+        return Err("nonce=true requires exp or expires_in".to_string());
+    }
+    if let Some(exp) = exp {
+        if exp <= now {
+            return Err("exp must be in the future".to_string());
+        }
+        if options.nbf.is_some_and(|nbf| nbf >= exp) {
+            return Err("nbf must be before exp".to_string());
+        }
+    }
+    let timed = options.iat || exp.is_some() || options.nbf.is_some();
+    Ok(SignatureClaims {
+        iat: timed.then_some(now),
+        exp,
+        nbf: options.nbf,
+        nonce: options.nonce.then(generate_nonce),
+        coverage: (!options.cover.is_empty()).then(|| options.cover.clone()),
+    })
+}
+
+/// Collects every value of the query parameter `name`, which may be repeated.
+fn repeated_param(query: &str, name: &str) -> Result<Vec<String>, String> {
+    let pairs = web::Query::<Vec<(String, String)>>::from_query(query).map_err(|e| e.to_string())?;
+    Ok(pairs.into_inner().into_iter().filter(|(key, _)| key == name).map(|(_, value)| value).collect())
+}
+
+/// Records the nonce of a verified signature, if it has one, until the
+/// signature expires. A nonce without `exp` is refused: the store would
+/// forget it after `NONCE_TTL_SECS` and the signature could then be replayed.
+///
+/// Returns the error response to send when the nonce is refused.
+fn record_nonce(claims: &SignatureClaims, nonces: Option<&NonceStore>, config: &SigningConfig) -> Option<HttpResponse> {
+    let nonce = claims.nonce.as_deref()?;
+    let Some(store) = nonces else {
+        error!("Received a nonce but no nonce store is configured");
+        return Some(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Replay protection is not configured"
+        })));
+    };
+    let recorded = match claims.exp {
+        Some(exp) => store.check_and_record(nonce, unix_now(), Some(exp.saturating_add(config.clock_skew_secs))),
+        None => Err(NonceError::NoExpiry),
+    };
+    match recorded {
+        Ok(()) => None,
+        Err(NonceError::Storage(e)) => {
+            error!("Failed to record nonce: {}", e);
+            Some(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Verification failed"
+            })))
+        },
+        Err(e) => {
+            warn!("Signature verification failed: {}", e);
+            Some(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e.to_string(),
+                "code": e.code()
+            })))
+        }
+    }
+}
+
+/// Hex SHA-256 of the canonical JSON of a signed payload, as recorded in the audit log.
+fn payload_digest(data: &Value) -> String {
+    hex::encode(Sha256::digest(canonicalize_json(data).to_string().as_bytes()))
+}
+
+/// Records the signatures carried by `response` in the audit log, if one is
+/// registered, before the response is sent.
+///
+/// A signature that cannot be logged is not handed out: the response then
+/// becomes a 500 Internal Server Error.
+fn audited(response: HttpResponse, records: Vec<AuditRecord>, audit: Option<&AuditLog>) -> HttpResponse {
+    let Some(audit) = audit else {
+        return response;
+    };
+    let now = unix_now();
+    for record in records {
+        if let Err(e) = audit.append(record, now) {
+            error!("Failed to write audit log: {}", e);
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Signing failed",
+                "code": e.code()
+            }));
+        }
+    }
+    response
+}
+
+/// Returns the first candidate key for which `check` accepts the signature.
+fn first_matching_key(
+    keys: Vec<&KeyEntry>,
+    check: impl Fn(&[u8]) -> Result<bool, String>,
+) -> Result<Option<&KeyEntry>, String> {
+    for key in keys {
+        if check(&key.secret)? {
+            return Ok(Some(key));
+        }
+    }
+    Ok(None)
+}
+
 /// Handles POST requests to `/sign`.
 ///
 /// Takes a JSON object in the request body, generates an HMAC-SHA256 signature
-/// based on its canonical representation, and returns the signature in a JSON object.
-/// The HMAC secret key is retrieved from application data.
+/// based on its canonical representation, and returns the signature in a JSON object
+/// together with the `kid` of the key that produced it.
+/// The active key is taken from the `KeyManager` in application data.
+///
+/// Importantly it ensures key ordering can be arbitrary. Duplicate keys are
+/// handled according to `SigningConfig::duplicate_keys`.
+///
+/// With `format=jws` or `format=jws-detached` a JWS is returned instead,
+/// signed with the active key for `alg` (`HS256`, `ES256` or `EdDSA`). With
+/// `format=embedded` the payload object itself is returned, with the usual
+/// response stored under `SigningConfig::signature_property`. With
+/// `format=merkle` the root of the document's Merkle tree is signed instead,
+/// so that single fields can later be disclosed with `/merkle/prove`. With
+/// `format=cose` a Base64 COSE_Mac0 or COSE_Sign1 message over the
+/// deterministic CBOR of the payload is returned. With
+/// `format=set` the payload is signed once by every key listed in
+/// `SigningConfig::signers` (or the `signers` subset), for threshold
+/// verification by `/verify`.
 ///
-/// Importantly it ensures key ordering can be arbitrary.
+/// The optional query parameters `exp`, `expires_in`, `nbf` and `iat` (see
+/// `SignOptions`) bind time claims into the signature, and `nonce=true` binds
+/// a random nonce, which requires `exp` or `expires_in`; they are returned next to the signature and must be sent
+/// back to `/verify`. Each repeated `cover` parameter adds a JSON Pointer to
+/// the fields the signature covers.
+///
+/// Every signature issued is appended to the `AuditLog`, when one is registered.
 /// 
 /// # Errors
-/// Returns a 400 Bad Request if signing fails internally.
-/// Returns a 500 Internal Server Error if the secret key is missing in app data.
-pub async fn sign(data: web::Json<Value>, hmac_key: web::Data<Vec<u8>>) -> impl Responder {
+/// Returns a 400 Bad Request if the body is not declared as or is not valid JSON,
+/// contains a rejected duplicate key, the time options are inconsistent, or if
+/// signing fails internally. Returns a 413 Payload Too Large if the body exceeds
+/// `SigningConfig::max_json_body_bytes`.
+/// Returns a 500 Internal Server Error if the `KeyManager` is missing in app data, or
+/// the signature cannot be written to the audit log.
+pub async fn sign(
+    req: HttpRequest,
+    payload: web::Payload,
+    options: web::Query<SignOptions>,
+    key_manager: web::Data<KeyManager>,
+    tenant: Tenant,
+    settings: Option<web::Data<LiveSettings>>,
+    audit: Option<web::Data<AuditLog>>,
+) -> impl Responder {
     info!("Received signing request");
-    let key = hmac_key.get_ref();
-    match sign_data(&data.into_inner(), key) {
+    if let Err(response) = require_json(&req) {
+        return response;
+    }
+    let keyring = match tenant.keyring(&key_manager) {
+        Ok(keyring) => keyring,
+        Err(response) => return response,
+    };
+    let settings = current_settings(settings);
+    let config = &settings.signing;
+    let body = match read_raw_body(payload, config.max_json_body_bytes).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let data = match parse_signed_body(&body, config) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let mut options = options.into_inner();
+    options.cover = match repeated_param(req.query_string(), "cover") {
+        Ok(cover) => cover,
+        Err(e) => {
+            warn!("Rejected signing options: {}", e);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e
+            }));
+        }
+    };
+    let claims = match claims_from_options(&options, unix_now()) {
+        Ok(claims) => claims,
+        Err(e) => {
+            warn!("Rejected signing options: {}", e);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e
+            }));
+        }
+    };
+    let (response, records) = match options.format {
+        SignFormat::Signature => sign_as_signature(&data, claims, &keyring, &tenant),
+        SignFormat::Jws | SignFormat::JwsDetached => sign_as_jws(&data, &options, &claims, &keyring, &tenant),
+        SignFormat::Embedded => sign_as_embedded(data, claims, &keyring, config, &tenant),
+        SignFormat::Merkle => sign_as_merkle(&data, &claims, &keyring, &tenant),
+        SignFormat::Set => sign_as_set(&data, &options, claims, &keyring, config, &tenant),
+        SignFormat::Cose => sign_as_cose(&data, &options, &claims, &keyring, &tenant),
+    };
+    audited(response, records, audit.as_ref().map(|a| a.get_ref()))
+}
+
+/// Checks that every pointer of `coverage` matches the payload.
+fn check_coverage(data: &Value, claims: &SignatureClaims) -> Result<(), HttpResponse> {
+    let Some(pointers) = &claims.coverage else {
+        return Ok(());
+    };
+    project_coverage(data, pointers).map(|_| ()).map_err(|e| {
+        warn!("Rejected coverage: {}", e);
+        HttpResponse::BadRequest().json(serde_json::json!({
+            "error": e
+        }))
+    })
+}
+
+/// Signs `data` with the active HMAC key and its claims, recording the
+/// signature under `format`.
+fn sign_with_active_key(
+    data: &Value,
+    claims: SignatureClaims,
+    keyring: &Keyring,
+    tenant: &Tenant,
+    format: &str,
+) -> Result<(SignResponse, AuditRecord), HttpResponse> {
+    check_coverage(data, &claims)?;
+    let key = keyring.active();
+    match sign_with_claims(data, &claims, &key.secret) {
         Ok(signature) => {
-            info!("Successfully generated signature");
-            HttpResponse::Ok().json(serde_json::json!({
-                "signature": signature
-            }))
+            info!("Successfully generated signature with key {}", key.kid);
+            let record = AuditRecord {
+                kid: key.kid.clone(),
+                tenant: tenant.id().map(str::to_string),
+                format: format.to_string(),
+                payload_digest: payload_digest(data),
+                signature: signature.clone(),
+            };
+            Ok((SignResponse { signature, kid: key.kid.clone(), claims }, record))
         },
         Err(e) => {
             error!("Signing failed internally: {}", e);
+            Err(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Signing failed"
+            })))
+        }
+    }
+}
+
+/// Signs `data` with the active HMAC key, for `/sign` without a format.
+fn sign_as_signature(
+    data: &Value,
+    claims: SignatureClaims,
+    keyring: &Keyring,
+    tenant: &Tenant,
+) -> (HttpResponse, Vec<AuditRecord>) {
+    match sign_with_active_key(data, claims, keyring, tenant, "signature") {
+        Ok((response, record)) => (HttpResponse::Ok().json(response), vec![record]),
+        Err(response) => (response, Vec::new()),
+    }
+}
+
+/// Signs the object `data` and returns it with the signature under the
+/// configured property, for `/sign?format=embedded`.
+fn sign_as_embedded(
+    data: Value,
+    claims: SignatureClaims,
+    keyring: &Keyring,
+    config: &SigningConfig,
+    tenant: &Tenant,
+) -> (HttpResponse, Vec<AuditRecord>) {
+    match data.as_object() {
+        Some(object) if object.contains_key(&config.signature_property) => {
+            return (HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Payload already has a '{}' property", config.signature_property)
+            })), Vec::new());
+        },
+        Some(_) => {},
+        None => {
+            return (HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Embedded signatures require a JSON object"
+            })), Vec::new());
+        }
+    }
+    let (response, record) = match sign_with_active_key(&data, claims, keyring, tenant, "embedded") {
+        Ok(signed) => signed,
+        Err(response) => return (response, Vec::new()),
+    };
+    let mut document = data;
+    if let (Some(object), Ok(signature)) = (document.as_object_mut(), serde_json::to_value(response)) {
+        object.insert(config.signature_property.clone(), signature);
+    }
+    (HttpResponse::Ok().json(document), vec![record])
+}
+
+/// Signs the root of a Merkle tree over `data`, for `/sign?format=merkle`.
+fn sign_as_merkle(
+    data: &Value,
+    claims: &SignatureClaims,
+    keyring: &Keyring,
+    tenant: &Tenant,
+) -> (HttpResponse, Vec<AuditRecord>) {
+    if !claims.is_empty() {
+        return (HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Time claims, nonces and coverage are not supported with Merkle signatures"
+        })), Vec::new());
+    }
+    match sign_merkle_root(data, keyring.active()) {
+        Ok((_, signature)) => {
+            info!("Successfully signed Merkle root with key {}", signature.kid);
+            let record = AuditRecord {
+                kid: signature.kid.clone(),
+                tenant: tenant.id().map(str::to_string),
+                format: "merkle".to_string(),
+                payload_digest: payload_digest(data),
+                signature: signature.signature.clone(),
+            };
+            (HttpResponse::Ok().json(signature), vec![record])
+        },
+        Err(e) => {
+            error!("Merkle signing failed internally: {}", e);
+            (HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Signing failed"
+            })), Vec::new())
+        }
+    }
+}
+
+/// Signs `data` as a JWS, for `/sign?format=jws` and `format=jws-detached`.
+fn sign_as_jws(
+    data: &Value,
+    options: &SignOptions,
+    claims: &SignatureClaims,
+    keyring: &Keyring,
+    tenant: &Tenant,
+) -> (HttpResponse, Vec<AuditRecord>) {
+    if !claims.is_empty() {
+        return (HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Time claims, nonces and coverage are not supported with JWS output"
+        })), Vec::new());
+    }
+    let algorithm = options.alg.unwrap_or(KeyAlgorithm::Hs256);
+    let Some(key) = keyring.active_for(algorithm) else {
+        warn!("No active {} key configured", algorithm);
+        return (HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("No active {} key configured", algorithm)
+        })), Vec::new());
+    };
+    let detached = options.format == SignFormat::JwsDetached;
+    match sign_jws(data, key, detached) {
+        Ok(jws) => {
+            info!("Successfully generated {} JWS with key {}", algorithm, key.kid);
+            let record = AuditRecord {
+                kid: key.kid.clone(),
+                tenant: tenant.id().map(str::to_string),
+                format: if detached { "jws-detached" } else { "jws" }.to_string(),
+                payload_digest: payload_digest(data),
+                signature: jws.rsplit('.').next().unwrap_or_default().to_string(),
+            };
+            let response = HttpResponse::Ok().json(JwsResponse {
+                jws,
+                kid: key.kid.clone(),
+                alg: algorithm,
+            });
+            (response, vec![record])
+        },
+        Err(e) => {
+            error!("JWS signing failed internally: {}", e);
+            (HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Signing failed"
+            })), Vec::new())
+        }
+    }
+}
+
+/// Signs `data` as a COSE message, for `/sign?format=cose`.
+fn sign_as_cose(
+    data: &Value,
+    options: &SignOptions,
+    claims: &SignatureClaims,
+    keyring: &Keyring,
+    tenant: &Tenant,
+) -> (HttpResponse, Vec<AuditRecord>) {
+    if !claims.is_empty() {
+        return (HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Time claims, nonces and coverage are not supported with COSE output"
+        })), Vec::new());
+    }
+    let algorithm = options.alg.unwrap_or(KeyAlgorithm::Hs256);
+    let Some(key) = keyring.active_for(algorithm) else {
+        warn!("No active {} key configured", algorithm);
+        return (HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("No active {} key configured", algorithm)
+        })), Vec::new());
+    };
+    match sign_cose(data, key) {
+        Ok(message) => {
+            info!("Successfully generated {} COSE message with key {}", algorithm, key.kid);
+            let record = AuditRecord {
+                kid: key.kid.clone(),
+                tenant: tenant.id().map(str::to_string),
+                format: "cose".to_string(),
+                payload_digest: payload_digest(data),
+                signature: BASE64.encode(cose_signature(&message)),
+            };
+            let response = HttpResponse::Ok().json(CoseResponse {
+                cose: BASE64.encode(message),
+                kid: key.kid.clone(),
+                alg: algorithm,
+            });
+            (response, vec![record])
+        },
+        Err(e) => {
+            error!("COSE signing failed internally: {}", e);
+            (HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Signing failed"
+            })), Vec::new())
+        }
+    }
+}
+
+/// The signature or tag, the last element of a COSE message produced by `sign_cose`.
+fn cose_signature(message: &[u8]) -> Vec<u8> {
+    let Ok(CborValue::Tag(_, structure)) = decode_cbor(message) else {
+        return Vec::new();
+    };
+    match *structure {
+        CborValue::Array(mut items) => match items.pop() {
+            Some(CborValue::Bytes(signature)) => signature,
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// Verifies the `cose` message of a `/verify` request.
+fn verify_as_cose(cose: &str, data: Option<&Value>, keyring: &Keyring) -> HttpResponse {
+    let Ok(message) = BASE64.decode(cose.trim()).or_else(|_| URL_SAFE_NO_PAD.decode(cose.trim().trim_end_matches('='))) else {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "COSE message must be Base64 encoded"
+        }));
+    };
+    match verify_cose(&message, data, keyring) {
+        Ok(Some(verified)) => {
+            if data.is_some_and(|data| canonicalize_json(data) != canonicalize_json(&verified.payload)) {
+                warn!("COSE verification failed: data does not match the payload");
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "Data does not match the COSE payload"
+                }));
+            }
+            info!("COSE verification successful with key {}", verified.kid);
+            HttpResponse::NoContent().finish()
+        },
+        Ok(None) => {
+            warn!("COSE verification failed: Invalid signature");
+            HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid signature"
+            }))
+        },
+        Err(e) => {
+            warn!("COSE verification failed: {}", e);
             HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e
+            }))
+        }
+    }
+}
+
+/// Signs `data` with every requested signer, for `/sign?format=set`.
+fn sign_as_set(
+    data: &Value,
+    options: &SignOptions,
+    claims: SignatureClaims,
+    keyring: &Keyring,
+    config: &SigningConfig,
+    tenant: &Tenant,
+) -> (HttpResponse, Vec<AuditRecord>) {
+    if let Err(response) = check_coverage(data, &claims) {
+        return (response, Vec::new());
+    }
+    let requested: Vec<String> = match &options.signers {
+        Some(signers) => signers.split(',').map(|kid| kid.trim().to_string()).collect(),
+        None => config.signers.clone(),
+    };
+    if requested.is_empty() {
+        return (HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "No signers configured for signature sets"
+        })), Vec::new());
+    }
+    let mut keys = Vec::with_capacity(requested.len());
+    for kid in &requested {
+        if !config.signers.contains(kid) || keys.iter().any(|k: &&KeyEntry| &k.kid == kid) {
+            warn!("Rejected signer {}", kid);
+            return (HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Key {} is not a configured signer", kid)
+            })), Vec::new());
+        }
+        match keyring.get(kid) {
+            Some(key) if key.state != KeyState::Retired => keys.push(key),
+            _ => {
+                error!("Signer {} is missing from the keyring or retired", kid);
+                return (HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("Signer {} is not available", kid)
+                })), Vec::new());
+            }
+        }
+    }
+    match sign_set(data, &claims, &keys) {
+        Ok(signatures) => {
+            info!("Generated signature set with {} signers", signatures.len());
+            let digest = payload_digest(data);
+            let records = signatures
+                .iter()
+                .map(|member| AuditRecord {
+                    kid: member.kid.clone(),
+                    tenant: tenant.id().map(str::to_string),
+                    format: "set".to_string(),
+                    payload_digest: digest.clone(),
+                    signature: member.signature.clone(),
+                })
+                .collect();
+            (HttpResponse::Ok().json(SignatureSetResponse { signatures, claims }), records)
+        },
+        Err(e) => {
+            error!("Signature set failed internally: {}", e);
+            (HttpResponse::BadRequest().json(serde_json::json!({
                 "error": "Signing failed"
+            })), Vec::new())
+        }
+    }
+}
+
+/// Verifies the `signatures` set of a `/verify` request.
+///
+/// The policy is the server's: only `SigningConfig::signers` count, and
+/// `SigningConfig::signature_set_threshold` of them (all by default) must
+/// be valid. A `policy` in the request can only tighten it.
+fn verify_as_set(
+    verify_request: &VerifyRequest,
+    data: &Value,
+    signatures: &[SetSignature],
+    keyring: &Keyring,
+    config: &SigningConfig,
+    nonces: Option<&NonceStore>,
+) -> HttpResponse {
+    if config.signers.is_empty() {
+        warn!("Rejected signature set: no signers configured");
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "No signers configured for signature sets"
+        }));
+    }
+    let mut policy = ThresholdPolicy::for_signers(&config.signers, config.signature_set_threshold);
+    if let Some(requested) = &verify_request.policy {
+        policy = policy.tightened_by(requested);
+    }
+    let report = match verify_set(data, &verify_request.claims, signatures, &policy, keyring) {
+        Ok(report) => report,
+        Err(e) => {
+            warn!("Signature set verification failed: {}", e);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e
+            }));
+        }
+    };
+    if !report.valid {
+        warn!("Signature set verification failed: {} of {} required signatures valid", report.passed.len(), report.threshold);
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Signature threshold not met",
+            "code": "threshold_not_met",
+            "report": report
+        }));
+    }
+    if let Err(e) = verify_request.claims.check_time(unix_now(), config.clock_skew_secs) {
+        warn!("Signature set verification failed: {}", e);
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": e.to_string(),
+            "code": e.code()
+        }));
+    }
+    if let Some(response) = record_nonce(&verify_request.claims, nonces, config) {
+        return response;
+    }
+    info!("Signature set verified by {}", report.passed.join(", "));
+    HttpResponse::Ok().json(report)
+}
+
+/// Verifies the `jws` of a `/verify` request.
+fn verify_as_jws(jws: &str, data: Option<&Value>, keyring: &Keyring, config: &SigningConfig) -> HttpResponse {
+    match verify_jws(jws, data, keyring, config.duplicate_keys) {
+        Ok(Some(verified)) => {
+            if data.is_some_and(|data| canonicalize_json(data) != canonicalize_json(&verified.payload)) {
+                warn!("JWS verification failed: data does not match the payload");
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "Data does not match the JWS payload"
+                }));
+            }
+            info!("JWS verification successful with key {}", verified.kid);
+            HttpResponse::NoContent().finish()
+        },
+        Ok(None) => {
+            warn!("JWS verification failed: Invalid signature");
+            HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid signature"
+            }))
+        },
+        Err(e) => {
+            warn!("JWS verification failed: {}", e);
+            HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e
             }))
         }
     }
 }
 
+/// Splits a document returned by `/sign?format=embedded` into a `VerifyRequest`.
+fn embedded_to_request(document: Value, property: &str) -> Result<VerifyRequest, String> {
+    let Value::Object(mut object) = document else {
+        return Err("Embedded documents must be JSON objects".to_string());
+    };
+    let signature = object
+        .remove(property)
+        .ok_or_else(|| format!("Missing '{}' property", property))?;
+    let signature: SignResponse = serde_json::from_value(signature)
+        .map_err(|e| format!("Malformed '{}' property: {}", property, e))?;
+    Ok(VerifyRequest {
+        data: Some(Value::Object(object)),
+        signature: Some(signature.signature),
+        kid: Some(signature.kid),
+        claims: signature.claims,
+        ..Default::default()
+    })
+}
+
+/// Rejects a `/verify` request that lacks a field its kind of signature needs.
+fn missing_field(field: &str) -> HttpResponse {
+    warn!("Malformed verification request: missing field `{}`", field);
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": format!("Invalid request: missing field `{}`", field)
+    }))
+}
+
 /// Handles POST requests to `/verify`.
 ///
 /// Takes a JSON object containing `data` and `signature` fields. It verifies
 /// if the provided signature matches the expected HMAC-SHA256 signature for the `data`.
-/// The HMAC secret key is retrieved from application data.
+/// If the optional `kid` is given only that key is used, otherwise every key of
+/// the `Keyring` that is not retired is tried.
+///
+/// Importantly it expects arbitrary key ordering. Duplicate keys anywhere in
+/// the body are handled according to `SigningConfig::duplicate_keys`.
+///
+/// Alternatively the body may carry a `jws` (compact, or detached with `data`),
+/// which is verified against the key named by its `kid` header, or a Base64
+/// `cose` message, whose payload is compared with `data` when given. With
+/// `?format=embedded` the body is a document returned by `/sign?format=embedded`:
+/// the signature property is removed and the remainder is verified.
+///
+/// When the signature has a `coverage` list, only those fields are checked and
+/// every one of them must be present.
+///
+/// A `signatures` set from `/sign?format=set` is checked member by member;
+/// it is valid when the optional `policy` threshold (all members by default)
+/// is met, and the answer is then `200 OK` with a report of which key ids
+/// passed and which failed.
 ///
-/// Importantly it expects arbitrary key ordering.
+/// Time claims (`iat`, `exp`, `nbf`) returned by `/sign` must be sent along;
+/// once the signature checks out they are compared with the current time,
+/// allowing `SigningConfig::clock_skew_secs` of drift. A `nonce` is recorded
+/// in the shared `NonceStore` and a second verification of it is refused.
 /// 
 /// # Responses
 /// - `204 No Content`: If the signature is valid.
-/// - `400 Bad Request`: If the signature is invalid or if verification fails internally.
-/// - `500 Internal Server Error`: If the secret key is missing in app data.
-pub async fn verify(data: web::Json<VerifyRequest>, hmac_key: web::Data<Vec<u8>>) -> impl Responder {
+/// - `200 OK`: If a signature set meets its threshold, with the report.
+/// - `400 Bad Request`: If the body is not declared as JSON or is malformed, the `kid` is unknown or retired,
+///   the signature is invalid or verification fails internally. Expired and
+///   not-yet-valid signatures carry the `code` `signature_expired` or
+///   `signature_not_yet_valid`; replayed nonces the `code` `nonce_replayed`;
+///   a covered field that is missing the `code` `coverage_unresolved`; an
+///   unmet signature set threshold the `code` `threshold_not_met` and a `report`.
+/// - `500 Internal Server Error`: If the `KeyManager` is missing in app data, or a
+///   nonce is present but no `NonceStore` is registered or it cannot be written.
+/// - `413 Payload Too Large`: If the body exceeds `SigningConfig::max_json_body_bytes`.
+pub async fn verify(
+    req: HttpRequest,
+    payload: web::Payload,
+    options: web::Query<VerifyOptions>,
+    key_manager: web::Data<KeyManager>,
+    tenant: Tenant,
+    settings: Option<web::Data<LiveSettings>>,
+    nonces: Option<web::Data<NonceStore>>,
+) -> impl Responder {
     info!("Received verification request");
-    let verify_request = data.into_inner();
-    let key = hmac_key.get_ref();
-    match verify_signature(&verify_request.data, &verify_request.signature, key) {
-        Ok(true) => {
-            info!("Signature verification successful");
+    if let Err(response) = require_json(&req) {
+        return response;
+    }
+    let keyring = match tenant.keyring(&key_manager) {
+        Ok(keyring) => keyring,
+        Err(response) => return response,
+    };
+    let settings = current_settings(settings);
+    let config = &settings.signing;
+    let body = match read_raw_body(payload, config.max_json_body_bytes).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let value = match parse_signed_body(&body, config) {
+        Ok(value) => value,
+        Err(response) => return response,
+    };
+    let parsed = match options.format {
+        VerifyFormat::Request => serde_json::from_value(value).map_err(|e| e.to_string()),
+        VerifyFormat::Embedded => embedded_to_request(value, &config.signature_property),
+    };
+    let verify_request: VerifyRequest = match parsed {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("Malformed verification request: {}", e);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Invalid request: {}", e)
+            }));
+        }
+    };
+    if let Some(jws) = &verify_request.jws {
+        return verify_as_jws(jws, verify_request.data.as_ref(), &keyring, config);
+    }
+    if let Some(cose) = &verify_request.cose {
+        return verify_as_cose(cose, verify_request.data.as_ref(), &keyring);
+    }
+    let Some(data) = &verify_request.data else {
+        return missing_field("data");
+    };
+    if let Some(pointers) = &verify_request.claims.coverage {
+        if let Err(e) = project_coverage(data, pointers) {
+            warn!("Signature verification failed: {}", e);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e,
+                "code": "coverage_unresolved"
+            }));
+        }
+    }
+    if let Some(signatures) = &verify_request.signatures {
+        return verify_as_set(&verify_request, data, signatures, &keyring, config, nonces.as_ref().map(|n| n.get_ref()));
+    }
+    let Some(signature) = &verify_request.signature else {
+        return missing_field("signature");
+    };
+    let keys = match keyring.verification_keys(verify_request.kid.as_deref()) {
+        Ok(keys) => keys,
+        Err(e) => {
+            warn!("Signature verification failed: {}", e);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e
+            }));
+        }
+    };
+    let outcome = first_matching_key(keys, |secret| {
+        verify_with_claims(data, &verify_request.claims, signature, secret)
+    });
+    match outcome {
+        Ok(Some(key)) => {
+            if let Err(e) = verify_request.claims.check_time(unix_now(), config.clock_skew_secs) {
+                warn!("Signature verification failed: {} (key {})", e, key.kid);
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": e.to_string(),
+                    "code": e.code()
+                }));
+            }
+            if let Some(response) = record_nonce(&verify_request.claims, nonces.as_ref().map(|n| n.get_ref()), config) {
+                return response;
+            }
+            info!("Signature verification successful with key {}", key.kid);
             HttpResponse::NoContent().finish()
         },
-        Ok(false) => {
+        Ok(None) => {
             warn!("Signature verification failed: Invalid signature");
             HttpResponse::BadRequest().json(serde_json::json!({
                 "error": "Invalid signature"
@@ -123,3 +947,827 @@ pub async fn verify(data: web::Json<VerifyRequest>, hmac_key: web::Data<Vec<u8>>
         }
     }
 }
+
+/// Builds the Merkle tree of `data` and signs its root with `key`.
+fn sign_merkle_root(data: &Value, key: &KeyEntry) -> Result<(MerkleTree, MerkleSignature), String> {
+    let tree = MerkleTree::build(data, &key.secret)?;
+    let root = tree.root();
+    let signature = compute(&merkle_signing_input(&root, tree.leaf_count()), &key.secret)?;
+    let signature = MerkleSignature {
+        root,
+        leaf_count: tree.leaf_count(),
+        signature,
+        kid: key.kid.clone(),
+    };
+    Ok((tree, signature))
+}
+
+/// Handles POST requests to `/merkle/prove`.
+///
+/// Takes the full signed document and a list of JSON Pointers, and returns
+/// the signed Merkle root with an inclusion proof for every leaf at or below
+/// each pointer. The response can be handed to a third party, who checks it
+/// with `/merkle/verify` without seeing the rest of the document. The root
+/// signature is appended to the `AuditLog`, like those of `/sign`.
+///
+/// # Errors
+/// Returns a 400 Bad Request if the body is malformed, the `kid` is not the
+/// active key, or a pointer matches nothing in the document, and a 500 Internal
+/// Server Error if the audit log cannot be written.
+pub async fn merkle_prove(
+    body: web::Bytes,
+    key_manager: web::Data<KeyManager>,
+    tenant: Tenant,
+    settings: Option<web::Data<LiveSettings>>,
+    audit: Option<web::Data<AuditLog>>,
+) -> impl Responder {
+    info!("Received Merkle proof request");
+    let keyring = match tenant.keyring(&key_manager) {
+        Ok(keyring) => keyring,
+        Err(response) => return response,
+    };
+    let settings = current_settings(settings);
+    let config = &settings.signing;
+    let request: MerkleProveRequest = match parse_signed_request(&body, config) {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+    // Proofs come with a freshly signed root, so only the active key may sign it
+    let key = keyring.active();
+    if let Some(kid) = request.kid.as_ref().filter(|kid| **kid != key.kid) {
+        warn!("Merkle proof refused: key {} is not the active key", kid);
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Key {} is not the active signing key", kid)
+        }));
+    }
+    let (tree, signature) = match sign_merkle_root(&request.data, key) {
+        Ok(signed) => signed,
+        Err(e) => {
+            error!("Merkle signing failed internally: {}", e);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Proof generation failed"
+            }));
+        }
+    };
+    let mut proofs = Vec::new();
+    for pointer in &request.pointers {
+        match tree.prove(pointer) {
+            Ok(found) => proofs.extend(found),
+            Err(e) => {
+                warn!("Merkle proof refused: {}", e);
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": e
+                }));
+            }
+        }
+    }
+    info!("Generated {} Merkle inclusion proofs", proofs.len());
+    let record = AuditRecord {
+        kid: signature.kid.clone(),
+        tenant: tenant.id().map(str::to_string),
+        format: "merkle".to_string(),
+        payload_digest: payload_digest(&request.data),
+        signature: signature.signature.clone(),
+    };
+    audited(
+        HttpResponse::Ok().json(MerkleDisclosure { signature, proofs }),
+        vec![record],
+        audit.as_ref().map(|a| a.get_ref()),
+    )
+}
+
+/// Handles POST requests to `/merkle/verify`.
+///
+/// Takes a disclosure produced by `/merkle/prove`: checks the signature over
+/// the root with the keyring, then every inclusion proof against the root.
+///
+/// # Responses
+/// - `204 No Content`: If the root signature and all proofs are valid.
+/// - `400 Bad Request`: If the body is malformed or has no proofs, the `kid`
+///   is unknown or retired, the root signature is invalid, or any proof does
+///   not match or has a path that does not fit its `index` and `leaf_count`.
+pub async fn merkle_verify(
+    body: web::Bytes,
+    key_manager: web::Data<KeyManager>,
+    tenant: Tenant,
+    settings: Option<web::Data<LiveSettings>>,
+) -> impl Responder {
+    info!("Received Merkle verification request");
+    let keyring = match tenant.keyring(&key_manager) {
+        Ok(keyring) => keyring,
+        Err(response) => return response,
+    };
+    let settings = current_settings(settings);
+    let config = &settings.signing;
+    let disclosure: MerkleDisclosure = match parse_signed_request(&body, config) {
+        Ok(disclosure) => disclosure,
+        Err(response) => return response,
+    };
+    if disclosure.proofs.is_empty() {
+        warn!("Merkle verification failed: no proofs");
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "A disclosure must contain at least one proof"
+        }));
+    }
+    let signed = &disclosure.signature;
+    let keys = match keyring.verification_keys(Some(&signed.kid)) {
+        Ok(keys) => keys,
+        Err(e) => {
+            warn!("Merkle verification failed: {}", e);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e
+            }));
+        }
+    };
+    let input = merkle_signing_input(&signed.root, signed.leaf_count);
+    match first_matching_key(keys, |secret| verify_bytes(&input, &signed.signature, secret)) {
+        Ok(Some(_)) => {},
+        Ok(None) => {
+            warn!("Merkle verification failed: Invalid signature");
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid signature"
+            }));
+        },
+        Err(e) => {
+            error!("Merkle verification failed internally: {}", e);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Verification failed"
+            }));
+        }
+    }
+    for proof in &disclosure.proofs {
+        if !verify_inclusion(proof, &signed.root, signed.leaf_count) {
+            warn!("Merkle verification failed: invalid proof for {}", proof.pointer);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Invalid inclusion proof for {}", proof.pointer)
+            }));
+        }
+    }
+    info!("Merkle verification successful for {} proofs", disclosure.proofs.len());
+    HttpResponse::NoContent().finish()
+}
+
+/// Handles POST requests to `/http-signatures/sign`.
+///
+/// Takes the method, URL, headers and body of a request a client is about to
+/// send and returns the HTTP Message Signature (RFC 9421) headers to attach:
+/// `Signature-Input`, `Signature` and, when a body is given, `Content-Digest`.
+/// The signature covers `@method`, `@target-uri` and `content-digest` unless
+/// `components` says otherwise, and carries `created`, `keyid` and `alg`
+/// parameters. Requests signed this way pass the `HttpSignatures` middleware.
+/// The signature is appended to the `AuditLog`, with the SHA-256 of the
+/// signature base as payload digest.
+///
+/// # Errors
+/// Returns a 400 Bad Request if the body is malformed, the URL is not
+/// absolute, no key is active for `alg`, or a covered header is missing,
+/// and a 500 Internal Server Error if the audit log cannot be written.
+pub async fn sign_http_request(
+    body: web::Bytes,
+    key_manager: web::Data<KeyManager>,
+    tenant: Tenant,
+    settings: Option<web::Data<LiveSettings>>,
+    audit: Option<web::Data<AuditLog>>,
+) -> impl Responder {
+    info!("Received HTTP message signing request");
+    let keyring = match tenant.keyring(&key_manager) {
+        Ok(keyring) => keyring,
+        Err(response) => return response,
+    };
+    let settings = current_settings(settings);
+    let config = &settings.signing;
+    let request: HttpSignRequest = match parse_signed_request(&body, config) {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+    let uri = match request.url.parse::<Uri>() {
+        Ok(uri) if uri.scheme().is_some() && uri.authority().is_some() => uri,
+        _ => {
+            warn!("Rejected URL: {}", request.url);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "url must be an absolute URL"
+            }));
+        }
+    };
+    let algorithm = request.alg.unwrap_or(KeyAlgorithm::Hs256);
+    let Some(key) = keyring.active_for(algorithm) else {
+        warn!("No active {} key configured", algorithm);
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("No active {} key configured", algorithm)
+        }));
+    };
+
+    let mut headers: Vec<(String, String)> = request.headers
+        .into_iter()
+        .filter(|(name, _)| !name.eq_ignore_ascii_case("content-digest"))
+        .collect();
+    let digest = request.body.as_ref().map(|body| content_digest(body.as_bytes()));
+    if let Some(digest) = &digest {
+        headers.push(("content-digest".to_string(), digest.clone()));
+    }
+    let parts = HttpRequestParts {
+        method: request.method.to_ascii_uppercase(),
+        scheme: uri.scheme_str().unwrap_or_default().to_string(),
+        authority: uri.authority().map(|a| a.to_string()).unwrap_or_default(),
+        path: uri.path().to_string(),
+        query: uri.query().map(str::to_string),
+        headers,
+    };
+    let components = request.components.unwrap_or_else(|| {
+        let mut components = vec!["@method".to_string(), "@target-uri".to_string()];
+        if digest.is_some() {
+            components.push("content-digest".to_string());
+        }
+        components
+    });
+    let now = unix_now();
+    let mut params = SignatureParams::new(components)
+        .with("created", ParamValue::Integer(now as i64));
+    if let Some(seconds) = request.expires_in {
+        params = params.with("expires", ParamValue::Integer(now.saturating_add(seconds) as i64));
+    }
+    if request.nonce {
+        params = params.with("nonce", ParamValue::String(generate_nonce()));
+    }
+    let params = params
+        .with("keyid", ParamValue::String(key.kid.clone()))
+        .with("alg", ParamValue::String(http_signature_alg(algorithm).to_string()));
+
+    let label = request.label.as_deref().unwrap_or("sig1");
+    let signed = signature_base(&parts, &params)
+        .and_then(|base| Ok((base, sign_message(&parts, &params, key, label)?)));
+    match signed {
+        Ok((signature_base, (signature_input, signature))) => {
+            info!("Signed HTTP request with key {}", key.kid);
+            let record = AuditRecord {
+                kid: key.kid.clone(),
+                tenant: tenant.id().map(str::to_string),
+                format: "http-signature".to_string(),
+                payload_digest: hex::encode(Sha256::digest(signature_base.as_bytes())),
+                signature: signature.clone(),
+            };
+            let mut headers = BTreeMap::new();
+            headers.insert("Signature-Input".to_string(), signature_input);
+            headers.insert("Signature".to_string(), signature);
+            if let Some(digest) = digest {
+                headers.insert("Content-Digest".to_string(), digest);
+            }
+            let response = HttpResponse::Ok().json(HttpSignResponse {
+                headers,
+                signature_base,
+                kid: key.kid.clone(),
+            });
+            audited(response, vec![record], audit.as_ref().map(|a| a.get_ref()))
+        },
+        Err(e) => {
+            warn!("HTTP message signing refused: {}", e);
+            HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e.to_string(),
+                "code": e.code()
+            }))
+        }
+    }
+}
+
+/// Handles POST requests to `/webhooks/verify`.
+///
+/// Takes an inbound webhook exactly as the provider sent it: the raw body and
+/// the provider's signature headers. The body is verified byte for byte with
+/// the profile named by `?profile=`, so it must not be re-serialized on the way.
+///
+/// # Responses
+/// - `204 No Content`: If the signature is valid and within the tolerance window.
+/// - `400 Bad Request`: If the profile is unknown or a header is missing or
+///   malformed (`webhook_header_missing`, `webhook_header_malformed`).
+/// - `401 Unauthorized`: If the signature does not match
+///   (`webhook_signature_invalid`) or the timestamp is too far from now
+///   (`webhook_timestamp_out_of_tolerance`).
+pub async fn verify_webhook_request(
+    req: HttpRequest,
+    body: web::Bytes,
+    options: web::Query<WebhookOptions>,
+    settings: Option<web::Data<LiveSettings>>,
+) -> impl Responder {
+    info!("Received webhook verification request");
+    let settings = current_settings(settings);
+    let profiles = settings.webhooks.profiles.as_slice();
+    let profile = match (&options.profile, profiles) {
+        (Some(name), _) => profiles.iter().find(|p| &p.name == name),
+        (None, [only]) => Some(only),
+        (None, _) => None,
+    };
+    let Some(profile) = profile else {
+        warn!("Rejected webhook: unknown or missing profile {:?}", options.profile);
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Unknown webhook profile"
+        }));
+    };
+    let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok());
+    match verify_webhook(profile, header, &body, unix_now()) {
+        Ok(()) => {
+            info!("Webhook verified with profile {}", profile.name);
+            HttpResponse::NoContent().finish()
+        },
+        Err(e) => {
+            warn!("Webhook verification failed for profile {}: {}", profile.name, e);
+            let mut response = match e {
+                WebhookError::MissingHeader(_) | WebhookError::Malformed(_) => HttpResponse::BadRequest(),
+                WebhookError::OutsideTolerance | WebhookError::Invalid => HttpResponse::Unauthorized(),
+            };
+            response.json(serde_json::json!({
+                "error": e.to_string(),
+                "code": e.code()
+            }))
+        }
+    }
+}
+
+/// Reads a request body of any content type, refusing more than `limit` bytes.
+async fn read_raw_body(mut payload: web::Payload, limit: usize) -> Result<web::Bytes, HttpResponse> {
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| {
+            warn!("Failed to read request body: {}", e);
+            HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Failed to read request body"
+            }))
+        })?;
+        if body.len() + chunk.len() > limit {
+            warn!("Rejected request body larger than {} bytes", limit);
+            return Err(HttpResponse::PayloadTooLarge().json(serde_json::json!({
+                "error": format!("Body exceeds {} bytes", limit)
+            })));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.freeze())
+}
+
+/// Handles POST requests to `/sign/raw`.
+///
+/// Signs the request body exactly as received, whatever its content type,
+/// with the active key: no JSON parsing or canonicalization takes place, so
+/// files, form bodies and other messages can be signed. The signature is the
+/// Base64 HMAC-SHA256 of the bytes behind a `riot.raw.v1.` prefix, so it
+/// cannot stand in for a signature of any other endpoint.
+///
+/// The signature and key id are returned in the `X-Signature` and
+/// `X-Signature-Kid` headers, and also as a JSON body unless
+/// `?output=header` is given, in which case the response is `204 No Content`.
+///
+/// Like `/sign`, every signature is appended to the `AuditLog`, with the
+/// SHA-256 of the raw bytes as payload digest.
+///
+/// # Errors
+/// Returns a 413 Payload Too Large if the body exceeds
+/// `SigningConfig::max_raw_body_bytes`, a 400 Bad Request if signing fails,
+/// and a 500 Internal Server Error if the audit log cannot be written.
+pub async fn sign_raw(
+    payload: web::Payload,
+    options: web::Query<RawSignOptions>,
+    key_manager: web::Data<KeyManager>,
+    tenant: Tenant,
+    settings: Option<web::Data<LiveSettings>>,
+    audit: Option<web::Data<AuditLog>>,
+) -> impl Responder {
+    info!("Received raw signing request");
+    let keyring = match tenant.keyring(&key_manager) {
+        Ok(keyring) => keyring,
+        Err(response) => return response,
+    };
+    let settings = current_settings(settings);
+    let config = &settings.signing;
+    let body = match read_raw_body(payload, config.max_raw_body_bytes).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let key = keyring.active();
+    match sign_raw_bytes(&body, &key.secret) {
+        Ok(signature) => {
+            info!("Signed {} raw bytes with key {}", body.len(), key.kid);
+            let record = AuditRecord {
+                kid: key.kid.clone(),
+                tenant: tenant.id().map(str::to_string),
+                format: "raw".to_string(),
+                payload_digest: hex::encode(Sha256::digest(&body)),
+                signature: signature.clone(),
+            };
+            let mut response = match options.output {
+                RawOutput::Json => HttpResponse::Ok(),
+                RawOutput::Header => HttpResponse::NoContent(),
+            };
+            response
+                .insert_header((SIGNATURE_HEADER, signature.clone()))
+                .insert_header((SIGNATURE_KID_HEADER, key.kid.clone()));
+            let response = match options.output {
+                RawOutput::Json => response.json(SignResponse {
+                    signature,
+                    kid: key.kid.clone(),
+                    claims: SignatureClaims::default(),
+                }),
+                RawOutput::Header => response.finish(),
+            };
+            audited(response, vec![record], audit.as_ref().map(|a| a.get_ref()))
+        },
+        Err(e) => {
+            error!("Raw signing failed internally: {}", e);
+            HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Signing failed"
+            }))
+        }
+    }
+}
+
+/// Handles POST requests to `/verify/raw`.
+///
+/// Verifies a signature from `/sign/raw` against the request body exactly as
+/// received. The signature is read from the `X-Signature` header or the
+/// `signature` query parameter, and the optional key id from
+/// `X-Signature-Kid` or `kid`; without a key id every key that is not
+/// retired is tried.
+///
+/// # Responses
+/// - `204 No Content`: If the signature is valid.
+/// - `400 Bad Request`: If no signature is given, the `kid` is unknown or
+///   retired, or the signature is invalid.
+/// - `413 Payload Too Large`: If the body exceeds `SigningConfig::max_raw_body_bytes`.
+pub async fn verify_raw(
+    req: HttpRequest,
+    payload: web::Payload,
+    options: web::Query<RawVerifyOptions>,
+    key_manager: web::Data<KeyManager>,
+    tenant: Tenant,
+    settings: Option<web::Data<LiveSettings>>,
+) -> impl Responder {
+    info!("Received raw verification request");
+    let keyring = match tenant.keyring(&key_manager) {
+        Ok(keyring) => keyring,
+        Err(response) => return response,
+    };
+    let settings = current_settings(settings);
+    let config = &settings.signing;
+    let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let Some(signature) = header(SIGNATURE_HEADER).or_else(|| options.signature.clone()) else {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Missing {} header", SIGNATURE_HEADER)
+        }));
+    };
+    let kid = header(SIGNATURE_KID_HEADER).or_else(|| options.kid.clone());
+    let body = match read_raw_body(payload, config.max_raw_body_bytes).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let keys = match keyring.verification_keys(kid.as_deref()) {
+        Ok(keys) => keys,
+        Err(e) => {
+            warn!("Raw verification failed: {}", e);
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e
+            }));
+        }
+    };
+    match first_matching_key(keys, |secret| verify_raw_bytes(&body, &signature, secret)) {
+        Ok(Some(key)) => {
+            info!("Raw verification successful with key {}", key.kid);
+            HttpResponse::NoContent().finish()
+        },
+        Ok(None) => {
+            warn!("Raw verification failed: Invalid signature");
+            HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid signature"
+            }))
+        },
+        Err(e) => {
+            error!("Raw verification failed internally: {}", e);
+            HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Verification failed"
+            }))
+        }
+    }
+}
+
+/// Handles GET requests to `/.well-known/jwks.json`.
+///
+/// Publishes the public halves of every active and verify-only asymmetric
+/// signing key as a JWK Set (RFC 7517), so that JWS and COSE signatures can
+/// be verified offline. HMAC keys are never published. Responses may be
+/// cached for `SigningConfig::jwks_max_age_secs` and carry an `ETag`; a
+/// matching `If-None-Match` gets a 304 Not Modified.
+///
+/// # Errors
+/// Returns a 500 Internal Server Error if a key cannot be encoded.
+pub async fn jwks(
+    req: HttpRequest,
+    key_manager: web::Data<KeyManager>,
+    settings: Option<web::Data<LiveSettings>>,
+) -> impl Responder {
+    let keyring = key_manager.keyring();
+    let settings = current_settings(settings);
+    let mut keys = Vec::new();
+    for key in keyring.keys().iter().filter(|k| k.state != KeyState::Retired) {
+        match public_jwk(key) {
+            Ok(Some(jwk)) => keys.push(jwk),
+            Ok(None) => {}
+            Err(e) => {
+                error!("Failed to publish key {}: {}", key.kid, e);
+                return HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": "Failed to build key set"
+                }));
+            }
+        }
+    }
+    let body = serde_json::json!({ "keys": keys }).to_string();
+    let etag = format!("\"{}\"", hex::encode(&Sha256::digest(body.as_bytes())[..16]));
+    let cache_control = format!("public, max-age={}", settings.signing.jwks_max_age_secs);
+    let not_modified = req
+        .headers()
+        .get("If-None-Match")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').map(|tag| tag.trim().trim_start_matches("W/")).any(|tag| tag == etag || tag == "*"));
+    let mut response = if not_modified { HttpResponse::NotModified() } else { HttpResponse::Ok() };
+    response
+        .insert_header(("Cache-Control", cache_control))
+        .insert_header(("ETag", etag));
+    if not_modified {
+        return response.finish();
+    }
+    response.content_type("application/jwk-set+json").body(body)
+}
+
+/// Largest page of entries returned by `/audit`.
+const MAX_AUDIT_PAGE: usize = 1000;
+
+/// Handles GET requests to `/audit`.
+///
+/// Returns up to `limit` entries of the audit log starting at `seq` `from`,
+/// together with the current head of the chain. Fetching every page from 0
+/// and checking it with `audit::verify_chain` against a head recorded
+/// earlier detects modified, removed or reordered entries. Like the key
+/// administration endpoints, it requires the admin token.
+///
+/// # Errors
+/// Returns a 401 Unauthorized or 403 Forbidden as described in
+/// `authorize_admin`, and a 500 Internal Server Error if no `AuditLog` is
+/// registered or it cannot be read.
+pub async fn audit_entries(
+    req: HttpRequest,
+    query: web::Query<AuditQuery>,
+    audit: Option<web::Data<AuditLog>>,
+    settings: Option<web::Data<LiveSettings>>,
+) -> impl Responder {
+    if let Err(response) = authorize_admin(&req, &current_settings(settings).admin) {
+        return response;
+    }
+    let Some(audit) = audit else {
+        error!("Audit log requested but none is configured");
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Audit log is not configured"
+        }));
+    };
+    let limit = query.limit.unwrap_or(100).min(MAX_AUDIT_PAGE);
+    match audit.entries(query.from, limit) {
+        Ok((entries, head)) => HttpResponse::Ok().json(AuditPage { entries, head }),
+        Err(e) => {
+            error!("Failed to read audit log: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to read audit log",
+                "code": e.code()
+            }))
+        }
+    }
+}
+
+/// Checks the `Authorization: Bearer <ADMIN_TOKEN>` header of an admin request.
+///
+/// Returns the response to send instead: 403 Forbidden when no token is
+/// configured, 401 Unauthorized when the header is missing or wrong.
+fn authorize_admin(req: &HttpRequest, config: &AdminConfig) -> Result<(), HttpResponse> {
+    if config.token.is_none() {
+        return Err(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "Admin API is disabled",
+            "code": "admin_disabled"
+        })));
+    }
+    let presented = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if presented.is_some_and(|token| config.authorizes(token)) {
+        return Ok(());
+    }
+    warn!("Rejected admin request to {}", req.path());
+    Err(HttpResponse::Unauthorized()
+        .insert_header(("WWW-Authenticate", "Bearer"))
+        .json(serde_json::json!({
+            "error": "Invalid or missing admin token",
+            "code": "admin_unauthorized"
+        })))
+}
+
+/// Lists `key` with its check value, when it can be computed.
+fn key_info(keys: &KeySet, key: &ManagedKey) -> KeyInfo {
+    let kcv = keys
+        .check_value(key)
+        .map_err(|e| warn!("No check value for key {}: {}", key.kid, e))
+        .ok();
+    KeyInfo { kcv, ..KeyInfo::from(key) }
+}
+
+fn key_list(keys: &KeySet) -> KeyList {
+    KeyList { keys: keys.keys().iter().map(|key| key_info(keys, key)).collect() }
+}
+
+fn key_admin_error(e: KeyAdminError) -> HttpResponse {
+    let body = serde_json::json!({
+        "error": e.to_string(),
+        "code": e.code()
+    });
+    match e {
+        KeyAdminError::UnknownKey(_) => HttpResponse::NotFound().json(body),
+        KeyAdminError::Conflict(_) | KeyAdminError::NotPersistent => HttpResponse::Conflict().json(body),
+        KeyAdminError::Storage(_) => {
+            error!("Key change not applied: {}", e);
+            HttpResponse::InternalServerError().json(body)
+        }
+    }
+}
+
+/// Handles GET requests to `/admin/keys`.
+///
+/// Lists every key with its type, purposes, state, creation date and key
+/// check value, but never its material.
+///
+/// # Errors
+/// Returns a 401 Unauthorized or 403 Forbidden as described in `authorize_admin`.
+pub async fn list_keys(
+    req: HttpRequest,
+    key_manager: web::Data<KeyManager>,
+    settings: Option<web::Data<LiveSettings>>,
+) -> impl Responder {
+    if let Err(response) = authorize_admin(&req, &current_settings(settings).admin) {
+        return response;
+    }
+    HttpResponse::Ok().json(key_list(&key_manager.current()))
+}
+
+/// Handles POST requests to `/admin/keys`.
+///
+/// Generates a random key of the requested type. It is added as
+/// verify-only, so that every verifier knows it before it signs anything,
+/// unless `activate` is set. The change is written to the keyring file and
+/// applies to all workers immediately.
+///
+/// # Errors
+/// Returns a 401 Unauthorized or 403 Forbidden as described in
+/// `authorize_admin`, a 409 Conflict if the key id is taken or the keys
+/// were not loaded from a keyring file, and a 500 Internal Server Error if
+/// the file cannot be written.
+pub async fn generate_key(
+    req: HttpRequest,
+    body: web::Json<GenerateKeyRequest>,
+    key_manager: web::Data<KeyManager>,
+    settings: Option<web::Data<LiveSettings>>,
+) -> impl Responder {
+    if let Err(response) = authorize_admin(&req, &current_settings(settings).admin) {
+        return response;
+    }
+    let request = body.into_inner();
+    let manager = key_manager.clone();
+    let generated = web::block(move || {
+        manager.generate(request.kid, request.key_type, request.purposes, request.activate)
+    })
+    .await;
+    let key = match generated {
+        Ok(Ok(key)) => key,
+        Ok(Err(e)) => return key_admin_error(e),
+        Err(e) => return key_admin_error(KeyAdminError::Storage(e.to_string())),
+    };
+    info!("Generated {} key {} ({:?})", key.key_type.as_str(), key.kid, key.state);
+    let keys = key_manager.current();
+    let created = keys.get(&key.kid).map(|key| key_info(&keys, key));
+    HttpResponse::Created().json(created)
+}
+
+/// Handles GET requests to `/admin/keys/{kid}/kcv`.
+///
+/// Returns the key check value of a key: 6 hex digits that are the same
+/// wherever the key is deployed, so operators can confirm that two
+/// deployments share a key without revealing it. See
+/// `crypto::key_check_value` and `crypto::aes_check_value`.
+///
+/// # Errors
+/// Returns a 401 Unauthorized or 403 Forbidden as described in
+/// `authorize_admin`, a 404 Not Found for an unknown key, and a 500
+/// Internal Server Error if the key provider holding the key fails.
+pub async fn key_check_value(
+    req: HttpRequest,
+    kid: web::Path<String>,
+    key_manager: web::Data<KeyManager>,
+    settings: Option<web::Data<LiveSettings>>,
+) -> impl Responder {
+    if let Err(response) = authorize_admin(&req, &current_settings(settings).admin) {
+        return response;
+    }
+    let keys = key_manager.current();
+    let Some(key) = keys.get(&kid) else {
+        return key_admin_error(KeyAdminError::UnknownKey(kid.into_inner()));
+    };
+    match keys.check_value(key) {
+        Ok(kcv) => HttpResponse::Ok().json(KeyCheckValue { kid: key.kid.clone(), key_type: key.key_type, kcv }),
+        Err(e) => {
+            error!("Failed to compute check value: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to compute the key check value",
+                "code": "key_check_failed"
+            }))
+        }
+    }
+}
+
+/// Handles POST requests to `/keys/generate`.
+///
+/// Generates a random key of the requested type and size (32 bytes, or a
+/// valid P-256 scalar) and returns it once, with its key check value; it
+/// is not stored anywhere. To add a key to the service's keyring instead,
+/// use `POST /admin/keys`. The response is marked `Cache-Control: no-store`.
+/// Like the `/admin` endpoints, it needs the admin token; each key handed
+/// out is logged with its check value.
+///
+/// # Errors
+/// Returns a 401 Unauthorized or 403 Forbidden as described in
+/// `authorize_admin`, and a 500 Internal Server Error if the key cannot be
+/// encoded.
+pub async fn generate_key_material(
+    req: HttpRequest,
+    body: web::Json<KeyGenerationRequest>,
+    settings: Option<web::Data<LiveSettings>>,
+) -> impl Responder {
+    if let Err(response) = authorize_admin(&req, &current_settings(settings).admin) {
+        return response;
+    }
+    match GeneratedKey::random(body.key_type) {
+        Ok(key) => {
+            info!("Generated a {} key for export, check value {}", key.key_type.as_str(), key.kcv);
+            HttpResponse::Ok().insert_header(("Cache-Control", "no-store")).json(key)
+        }
+        Err(e) => {
+            error!("Failed to generate a key: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to generate a key"
+            }))
+        }
+    }
+}
+
+/// Handles POST requests to `/admin/keys/{kid}/{action}`, where `action` is
+/// `activate`, `demote` or `retire`.
+///
+/// - `activate` makes the key active; the active key it replaces, of the
+///   same type for signing keys, becomes verify-only.
+/// - `demote` makes an active key verify-only. The active HMAC key can only
+///   be replaced by activating another one.
+/// - `retire` stops accepting a verify-only key altogether.
+///
+/// Returns the updated key list.
+///
+/// # Errors
+/// Returns a 401 Unauthorized or 403 Forbidden as described in
+/// `authorize_admin`, a 404 Not Found for an unknown key or action, a 409
+/// Conflict if the change is not allowed in the key's state or the keys
+/// were not loaded from a keyring file, and a 500 Internal Server Error if
+/// the file cannot be written.
+pub async fn change_key_state(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    key_manager: web::Data<KeyManager>,
+    settings: Option<web::Data<LiveSettings>>,
+) -> impl Responder {
+    if let Err(response) = authorize_admin(&req, &current_settings(settings).admin) {
+        return response;
+    }
+    let (kid, action) = path.into_inner();
+    let change: fn(&KeyManager, &str) -> Result<Arc<KeySet>, KeyAdminError> = match action.as_str() {
+        "activate" => KeyManager::activate,
+        "demote" => KeyManager::demote,
+        "retire" => KeyManager::retire,
+        _ => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": format!("Unknown key action: {}", action)
+            }));
+        }
+    };
+    // The keyring file is written synchronously, off the async workers
+    let manager = key_manager.clone();
+    let target = kid.clone();
+    let result = web::block(move || change(&manager, &target))
+        .await
+        .unwrap_or_else(|e| Err(KeyAdminError::Storage(e.to_string())));
+    match result {
+        Ok(keys) => {
+            info!("Key {}: {} applied", kid, action);
+            HttpResponse::Ok().json(key_list(&keys))
+        }
+        Err(e) => key_admin_error(e),
+    }
+}
@@ -0,0 +1,176 @@
+//! Secret sources.
+//! Environment variables leak through `/proc/<pid>/environ` and crash
+//! reporters, so every secret variable may also be read from a file:
+//! - `<NAME>_FILE`: path of a file holding the value, or `-` to read it
+//!   from standard input (for one variable at most, and only at startup;
+//!   reloads keep the value read then).
+//! - `SECRETS_DIR`: a directory, such as a mounted Kubernetes or Docker
+//!   secret, with one file per variable, named `HMAC_SECRET_KEY`,
+//!   `hmac_secret_key` or `hmac-secret-key`.
+//!
+//! A variable set directly wins over `SECRETS_DIR`; setting both `<NAME>`
+//! and `<NAME>_FILE` is an error. One trailing newline is removed from
+//! file contents.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+/// Variables holding secrets, besides `WEBHOOK_<NAME>_SECRET`.
+pub const SECRET_VARIABLES: &[&str] = &[
+    "HMAC_SECRET_KEY",
+    "HMAC_KEYS",
+    "JWS_KEYS",
+    "KEYRING_PASSPHRASE",
+    "TENANT_MASTER_KEY",
+    "ADMIN_TOKEN",
+];
+
+/// Secrets read from files, for the variables not set directly.
+#[derive(Default)]
+pub struct Secrets {
+    values: HashMap<String, Zeroizing<String>>,
+    files: Vec<PathBuf>,
+    /// The variable read from standard input, if any.
+    stdin: Option<String>,
+}
+
+impl Secrets {
+    /// Reads the secret variables that `var` does not set from their
+    /// `<NAME>_FILE` or `SECRETS_DIR` file, or from standard input.
+    pub fn read(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        Secrets::read_with_stdin(var, io::stdin())
+    }
+
+    /// Like `read`, with `stdin` standing for standard input.
+    pub fn read_with_stdin(var: impl Fn(&str) -> Option<String>, stdin: impl Read) -> Result<Self, String> {
+        Secrets::read_from(var, Stdin::Read(Box::new(stdin)))
+    }
+
+    /// Reads the secrets again, for a reload. Standard input was consumed at
+    /// startup, so a `<NAME>_FILE` of `-` takes the value kept from then in
+    /// `startup`, see `kept_for_reload`; another variable reading from
+    /// standard input is an error.
+    pub fn reread(var: impl Fn(&str) -> Option<String>, startup: &Secrets) -> Result<Self, String> {
+        Secrets::read_from(var, Stdin::Kept(startup))
+    }
+
+    /// The secrets that cannot be read again: the value read from standard
+    /// input, if any, for `reread`.
+    pub fn kept_for_reload(&self) -> Secrets {
+        let values = self
+            .stdin
+            .iter()
+            .filter_map(|name| self.values.get(name).map(|value| (name.clone(), value.clone())))
+            .collect();
+        Secrets { values, files: Vec::new(), stdin: self.stdin.clone() }
+    }
+
+    fn read_from(var: impl Fn(&str) -> Option<String>, mut stdin: Stdin) -> Result<Self, String> {
+        let mut names: Vec<String> = SECRET_VARIABLES.iter().map(|name| name.to_string()).collect();
+        if let Some(profiles) = var("WEBHOOK_PROFILES") {
+            names.extend(
+                profiles
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(|name| format!("WEBHOOK_{}_SECRET", name.to_ascii_uppercase().replace('-', "_"))),
+            );
+        }
+        let dir = var("SECRETS_DIR");
+        let mut from_stdin: Option<String> = None;
+        let mut values = HashMap::new();
+        let mut files = Vec::new();
+        for name in names {
+            let file = var(&format!("{}_FILE", name));
+            if var(&name).is_some() {
+                if file.is_some() {
+                    return Err(format!("{} and {}_FILE are both set", name, name));
+                }
+                continue;
+            }
+            let value = match file.as_deref() {
+                Some("-") => {
+                    if let Some(other) = from_stdin.replace(name.clone()) {
+                        return Err(format!("{}_FILE and {}_FILE both read from stdin", other, name));
+                    }
+                    match &mut stdin {
+                        Stdin::Read(reader) => read_stdin(reader)?,
+                        Stdin::Kept(startup) => match startup.values.get(&name).filter(|_| startup.stdin.as_ref() == Some(&name)) {
+                            Some(value) => value.clone(),
+                            None => {
+                                return Err(format!(
+                                    "{}_FILE reads from stdin, which is only read at startup; restart to change it",
+                                    name
+                                ));
+                            }
+                        },
+                    }
+                }
+                Some(path) => {
+                    files.push(PathBuf::from(path));
+                    read_secret_file(Path::new(path)).map_err(|e| format!("{}_FILE: {}", name, e))?
+                }
+                None => match dir.as_deref().and_then(|dir| find_in_dir(Path::new(dir), &name)) {
+                    Some(path) => {
+                        let value = read_secret_file(&path).map_err(|e| format!("{} in SECRETS_DIR: {}", name, e))?;
+                        files.push(path);
+                        value
+                    }
+                    None => continue,
+                },
+            };
+            values.insert(name, value);
+        }
+        Ok(Secrets { values, files, stdin: from_stdin })
+    }
+
+    /// The files the secrets were read from.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// The value read for `name`, if any.
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.values.get(name).map(|value| value.to_string())
+    }
+}
+
+/// Where a `<NAME>_FILE` of `-` takes its value from.
+enum Stdin<'a> {
+    /// Standard input, read at startup.
+    Read(Box<dyn Read + 'a>),
+    /// The value read at startup, for a reload.
+    Kept(&'a Secrets),
+}
+
+/// The file for `name` in `dir`, under any of its accepted spellings.
+fn find_in_dir(dir: &Path, name: &str) -> Option<PathBuf> {
+    let lower = name.to_ascii_lowercase();
+    [name.to_string(), lower.clone(), lower.replace('_', "-")]
+        .into_iter()
+        .map(|file| dir.join(file))
+        .find(|path| path.is_file())
+}
+
+fn read_secret_file(path: &Path) -> Result<Zeroizing<String>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    Ok(trimmed(contents))
+}
+
+fn read_stdin(stdin: &mut dyn Read) -> Result<Zeroizing<String>, String> {
+    let mut contents = Zeroizing::new(String::new());
+    stdin
+        .read_to_string(&mut contents)
+        .map_err(|e| format!("cannot read a secret from stdin: {}", e))?;
+    Ok(trimmed(std::mem::take(&mut *contents)))
+}
+
+/// Removes one trailing newline, wiping the original.
+fn trimmed(contents: String) -> Zeroizing<String> {
+    let contents = Zeroizing::new(contents);
+    let value = contents.strip_suffix('\n').unwrap_or(&contents);
+    Zeroizing::new(value.strip_suffix('\r').unwrap_or(value).to_string())
+}
@@ -0,0 +1,127 @@
+//! Tenant scoping.
+//! A request names its tenant in the `X-Tenant-Id` header or in the path,
+//! under `/tenants/{tenant}/`. Its signing and encryption keys are then
+//! derived from the active derivation key of the `KeyManager` with
+//! HKDF-SHA256, the tenant id as info, so one tenant's signatures and
+//! ciphertexts are worthless to another. Requests without a tenant use the
+//! service's own keys.
+
+use actix_web::{dev::{Payload, ServiceRequest}, error::InternalError, http::header::HeaderMap, FromRequest, HttpRequest, HttpResponse};
+use futures::future::{ready, Ready};
+use std::sync::Arc;
+use zeroize::Zeroizing;
+
+use crate::crypto::{validate_tenant_id, Keyring};
+use crate::key_manager::KeyManager;
+
+/// Header naming the tenant a request is scoped to.
+pub const TENANT_HEADER: &str = "X-Tenant-Id";
+
+/// The tenant of a request, if any, extracted from the `{tenant}` path
+/// segment or the `X-Tenant-Id` header.
+///
+/// Extraction fails with a 400 Bad Request when the id is invalid, or when
+/// the path and the header name different tenants.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tenant(Option<String>);
+
+impl Tenant {
+    /// Scopes a request to `tenant`.
+    pub fn new(tenant: impl Into<String>) -> Self {
+        Tenant(Some(tenant.into()))
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+
+    /// The signing keys of the request: the tenant's derived keys, or the
+    /// service keyring when the request has no tenant.
+    ///
+    /// Returns a ready-made 400 response when no derivation key is available.
+    pub fn keyring(&self, keys: &KeyManager) -> Result<Arc<Keyring>, HttpResponse> {
+        self.signing_keys(keys).map_err(unavailable)
+    }
+
+    /// Like `keyring`, with the reason as a plain message.
+    pub(crate) fn signing_keys(&self, keys: &KeyManager) -> Result<Arc<Keyring>, String> {
+        let Some(tenant) = self.id() else {
+            return Ok(keys.keyring());
+        };
+        keys.current().tenant_keyring(tenant).map(Arc::new)
+    }
+
+    /// The tenant of a request seen by a middleware, which runs before
+    /// routing: the `{tenant}` segment is read from the path itself.
+    pub(crate) fn of_service_request(req: &ServiceRequest) -> Result<Self, String> {
+        let from_path = req
+            .path()
+            .strip_prefix("/tenants/")
+            .and_then(|rest| rest.split_once('/'))
+            .map(|(tenant, _)| tenant.to_string());
+        resolve(from_path, req.headers()).map(Tenant)
+    }
+
+    /// The tenant's encryption keys, the one for new ciphertexts first, or
+    /// `None` when the request has no tenant.
+    ///
+    /// Returns a ready-made 400 response when no derivation key is available.
+    pub fn encryption_keys(&self, keys: Option<&KeyManager>) -> Result<Option<Zeroizing<Vec<[u8; 32]>>>, HttpResponse> {
+        let Some(tenant) = self.id() else {
+            return Ok(None);
+        };
+        let Some(keys) = keys else {
+            return Err(unavailable("No keys are configured".to_string()));
+        };
+        keys.current().tenant_encryption_keys(tenant).map(Some).map_err(unavailable)
+    }
+}
+
+fn unavailable(e: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": format!("Tenant scoping is not available: {}", e),
+        "code": "tenant_keys_unavailable"
+    }))
+}
+
+fn invalid(e: String) -> actix_web::Error {
+    let response = HttpResponse::BadRequest().json(serde_json::json!({
+        "error": e,
+        "code": "invalid_tenant"
+    }));
+    InternalError::from_response("invalid tenant", response).into()
+}
+
+impl FromRequest for Tenant {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let from_path = req.match_info().get("tenant").map(str::to_string);
+        ready(resolve(from_path, req.headers()).map(Tenant).map_err(invalid))
+    }
+}
+
+/// Reconciles the tenant named by the path with the one named by the header.
+fn resolve(from_path: Option<String>, headers: &HeaderMap) -> Result<Option<String>, String> {
+    let from_header = match headers.get(TENANT_HEADER) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .map_err(|_| format!("{} is not valid ASCII", TENANT_HEADER))?
+                .trim()
+                .to_string(),
+        ),
+        None => None,
+    };
+    let tenant = match (from_path, from_header) {
+        (Some(path), Some(header)) if path != header => {
+            return Err(format!("The path names tenant {} but {} names {}", path, TENANT_HEADER, header));
+        }
+        (path, header) => path.or(header),
+    };
+    if let Some(tenant) = &tenant {
+        validate_tenant_id(tenant)?;
+    }
+    Ok(tenant)
+}